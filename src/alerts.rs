@@ -0,0 +1,469 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use chrono::Utc;
+use serde::Serialize;
+
+use crate::api::OpenWeaterToTsv;
+
+// 閾値アラートのルール
+// ALERT_RULESに「;」区切りで記述する。書式は
+//   [名前:] <項目> <演算子> <値> [for <回数> cycles] [hysteresis <幅>] [cooldown <時間>]
+// 例: heat: temp > 35 hysteresis 1 cooldown 1h; wind.gust >= 20; weather_id in 200..299;
+//     humidity < 30 for 2 cycles
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Eq,
+    Ne,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Condition {
+    Compare(Op, f64),
+    // 範囲指定（両端を含む）
+    In(f64, f64),
+}
+
+#[derive(Clone, Debug)]
+pub struct Rule {
+    pub name: String,
+    field: String,
+    condition: Condition,
+    // 何回連続で条件を満たしたら発火するか
+    cycles: u32,
+    // 解除するために閾値からどれだけ戻る必要があるか
+    hysteresis: f64,
+    // 再発火までの最短間隔（秒）
+    cooldown: i64,
+}
+
+// ルールで使える項目名を取得結果の値に変換する
+fn field_value(record: &OpenWeaterToTsv, field: &str) -> Option<f64> {
+    let v = match field {
+        "temp" | "temperature" => record.temp,
+        "feels_like" => record.feels_like,
+        "temp_min" => record.temp_min,
+        "temp_max" => record.temp_max,
+        "pressure" => record.pressure as f64,
+        "sea_level" => record.sea_level as f64,
+        "grnd_level" => record.grnd_level as f64,
+        "humidity" => record.humidity as f64,
+        "visibility" => record.visibility as f64,
+        "wind.speed" | "speed" => record.speed,
+        "wind.deg" | "deg" => record.deg as f64,
+        "wind.gust" | "gust" => record.gust,
+        "clouds" | "clouds.all" | "all" => record.all as f64,
+        "rain.1h" | "rain_1h" => record.rain_1h,
+        "rain.3h" | "rain_3h" => record.rain_3h,
+        "snow.1h" | "snow_h1" => record.snow_h1,
+        "snow.3h" | "snow_h3" => record.snow_h3,
+        "weather_id" | "weather.id" => record.weather_to_id as f64,
+        _ => return None,
+    };
+    Some(v)
+}
+
+// 時間指定を秒に変換する（例: 90, 90s, 30m, 1h, 1d）
+pub fn parse_duration(value: &str) -> Result<i64, String> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => value.split_at(i),
+        None => (value, "s"),
+    };
+    let number: i64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration: {}", value))?;
+    let scale = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return Err(format!("invalid duration: {}", value)),
+    };
+    number
+        .checked_mul(scale)
+        .ok_or_else(|| format!("duration too large: {}", value))
+}
+
+fn parse_number(value: &str) -> Result<f64, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid number: {}", value))
+}
+
+impl Rule {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut tokens: Vec<&str> = text.split_whitespace().collect();
+        if tokens.is_empty() {
+            return Err(String::from("empty rule"));
+        }
+
+        // 先頭が「名前:」の場合はルール名として扱う
+        let mut name = None;
+        if let Some(v) = tokens[0].strip_suffix(':') {
+            name = Some(v.to_string());
+            tokens.remove(0);
+        }
+        if tokens.len() < 3 {
+            return Err(format!("incomplete rule: {}", text));
+        }
+
+        let field = tokens[0].to_string();
+        if field_value(&OpenWeaterToTsv::new(), &field).is_none() {
+            return Err(format!("unknown field: {}", field));
+        }
+
+        let condition = match tokens[1] {
+            "in" => {
+                let (from, to) = tokens[2]
+                    .split_once("..")
+                    .ok_or_else(|| format!("invalid range: {}", tokens[2]))?;
+                Condition::In(parse_number(from)?, parse_number(to)?)
+            }
+            op => {
+                let op = match op {
+                    ">" => Op::Gt,
+                    ">=" => Op::Ge,
+                    "<" => Op::Lt,
+                    "<=" => Op::Le,
+                    "==" | "=" => Op::Eq,
+                    "!=" => Op::Ne,
+                    _ => return Err(format!("unknown operator: {}", op)),
+                };
+                Condition::Compare(op, parse_number(tokens[2])?)
+            }
+        };
+
+        let mut rule = Rule {
+            name: name.unwrap_or_else(|| tokens.join(" ")),
+            field,
+            condition,
+            cycles: 1,
+            hysteresis: 0.0,
+            cooldown: 0,
+        };
+
+        // オプション
+        let mut rest = tokens[3..].iter();
+        while let Some(option) = rest.next() {
+            let value = rest
+                .next()
+                .ok_or_else(|| format!("missing value for {}", option))?;
+            match *option {
+                "for" => {
+                    rule.cycles = value
+                        .parse()
+                        .map_err(|_| format!("invalid cycles: {}", value))?;
+                    // 「for 2 cycles」の「cycles」は省略可
+                    if matches!(rest.clone().next(), Some(&"cycles") | Some(&"cycle")) {
+                        rest.next();
+                    }
+                }
+                "hysteresis" => rule.hysteresis = parse_number(value)?,
+                "cooldown" => rule.cooldown = parse_duration(value)?,
+                _ => return Err(format!("unknown option: {}", option)),
+            }
+        }
+
+        Ok(rule)
+    }
+
+    // 条件を満たしているか
+    fn matches(&self, v: f64) -> bool {
+        match self.condition {
+            Condition::Compare(Op::Gt, t) => v > t,
+            Condition::Compare(Op::Ge, t) => v >= t,
+            Condition::Compare(Op::Lt, t) => v < t,
+            Condition::Compare(Op::Le, t) => v <= t,
+            Condition::Compare(Op::Eq, t) => v == t,
+            Condition::Compare(Op::Ne, t) => v != t,
+            Condition::In(from, to) => from <= v && v <= to,
+        }
+    }
+
+    // 発火中の場合はヒステリシス分だけ広げた条件で判定する
+    fn still_matches(&self, v: f64) -> bool {
+        let h = self.hysteresis;
+        match self.condition {
+            Condition::Compare(Op::Gt, t) => v > t - h,
+            Condition::Compare(Op::Ge, t) => v >= t - h,
+            Condition::Compare(Op::Lt, t) => v < t + h,
+            Condition::Compare(Op::Le, t) => v <= t + h,
+            Condition::In(from, to) => from - h <= v && v <= to + h,
+            _ => self.matches(v),
+        }
+    }
+}
+
+pub fn parse_rules(text: &str) -> Result<Vec<Rule>, String> {
+    text.split(';')
+        .filter(|v| !v.trim().is_empty())
+        .map(Rule::parse)
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertKind {
+    Started,
+    Stopped,
+}
+
+// ルールの発火・解除イベント
+#[derive(Clone, Debug, Serialize)]
+pub struct AlertEvent {
+    pub rule: String,
+    pub kind: AlertKind,
+    pub location: String,
+    pub field: String,
+    pub value: f64,
+    // イベント発生時刻、UNIX、UTC
+    pub at: i64,
+    pub record: OpenWeaterToTsv,
+}
+
+impl fmt::Display for AlertEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            AlertKind::Started => "started",
+            AlertKind::Stopped => "stopped",
+        };
+        write!(
+            f,
+            "[{}] {} {}: {} = {}",
+            self.location, self.rule, kind, self.field, self.value
+        )
+    }
+}
+
+// ルールと地点ごとの状態
+#[derive(Default)]
+struct RuleState {
+    consecutive: u32,
+    active: bool,
+    // 発火イベントを通知したか（クールダウン中は通知しない）
+    notified: bool,
+    last_fired: Option<i64>,
+}
+
+pub struct AlertEngine {
+    rules: Vec<Rule>,
+    states: HashMap<(usize, String), RuleState>,
+}
+
+impl AlertEngine {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        AlertEngine {
+            rules,
+            states: HashMap::new(),
+        }
+    }
+
+    // 新しい取得結果を全ルールで評価し、発生したイベントを返す
+    pub fn evaluate(&mut self, record: &OpenWeaterToTsv) -> Vec<AlertEvent> {
+        self.evaluate_at(record, Utc::now().timestamp())
+    }
+
+    // 評価する時刻（UNIX、UTC）を指定して評価する
+    fn evaluate_at(&mut self, record: &OpenWeaterToTsv, now: i64) -> Vec<AlertEvent> {
+        let location = record.name.clone();
+        let mut events = Vec::new();
+
+        for (i, rule) in self.rules.iter().enumerate() {
+            let v = match field_value(record, &rule.field) {
+                Some(v) => v,
+                None => continue,
+            };
+            let state = self.states.entry((i, location.to_lowercase())).or_default();
+            let event = |kind| AlertEvent {
+                rule: rule.name.clone(),
+                kind,
+                location: location.clone(),
+                field: rule.field.clone(),
+                value: v,
+                at: now,
+                record: record.clone(),
+            };
+
+            if state.active {
+                if !rule.still_matches(v) {
+                    state.active = false;
+                    state.consecutive = 0;
+                    if state.notified {
+                        state.notified = false;
+                        events.push(event(AlertKind::Stopped));
+                    }
+                }
+            } else if rule.matches(v) {
+                state.consecutive += 1;
+                if state.consecutive >= rule.cycles {
+                    state.active = true;
+                    let cooled = state
+                        .last_fired
+                        .is_none_or(|last| now - last >= rule.cooldown);
+                    if cooled {
+                        state.notified = true;
+                        state.last_fired = Some(now);
+                        events.push(event(AlertKind::Started));
+                    }
+                }
+            } else {
+                state.consecutive = 0;
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(temp: f64) -> OpenWeaterToTsv {
+        let mut record = OpenWeaterToTsv::new();
+        record.name = String::from("Osaka");
+        record.temp = temp;
+        record
+    }
+
+    // 評価して発生したイベントの種類を返す
+    fn kinds(engine: &mut AlertEngine, temp: f64, now: i64) -> Vec<AlertKind> {
+        engine
+            .evaluate_at(&record(temp), now)
+            .iter()
+            .map(|v| v.kind)
+            .collect()
+    }
+
+    #[test]
+    fn parse_rule() {
+        let rule = Rule::parse("heat: temp > 35 for 2 cycles hysteresis 1 cooldown 1h").unwrap();
+        assert_eq!(rule.name, "heat");
+        assert_eq!(rule.field, "temp");
+        assert!(matches!(rule.condition, Condition::Compare(Op::Gt, t) if t == 35.0));
+        assert_eq!(rule.cycles, 2);
+        assert_eq!(rule.hysteresis, 1.0);
+        assert_eq!(rule.cooldown, 3600);
+
+        // 名前を省略した場合はルールの記述が名前になる
+        let rule = Rule::parse("weather_id in 200..299").unwrap();
+        assert_eq!(rule.name, "weather_id in 200..299");
+        assert!(matches!(rule.condition, Condition::In(from, to) if from == 200.0 && to == 299.0));
+        assert_eq!(rule.cycles, 1);
+
+        // 「cycles」は省略できる
+        assert_eq!(Rule::parse("humidity < 30 for 3").unwrap().cycles, 3);
+
+        let rules = parse_rules("temp > 35; ; wind.gust >= 20;").unwrap();
+        assert_eq!(rules.len(), 2);
+    }
+
+    #[test]
+    fn parse_rule_errors() {
+        let cases = [
+            ("", "empty rule"),
+            ("temp >", "incomplete rule: temp >"),
+            ("dew_point > 20", "unknown field: dew_point"),
+            ("temp >> 30", "unknown operator: >>"),
+            ("temp > hot", "invalid number: hot"),
+            ("temp in 10-20", "invalid range: 10-20"),
+            ("temp > 30 for", "missing value for for"),
+            ("temp > 30 for two", "invalid cycles: two"),
+            ("temp > 30 cooldown 1w", "invalid duration: 1w"),
+            ("temp > 30 repeat 2", "unknown option: repeat"),
+        ];
+        for (text, error) in cases {
+            assert_eq!(Rule::parse(text).err().unwrap(), error, "{}", text);
+        }
+    }
+
+    #[test]
+    fn parse_durations() {
+        assert_eq!(parse_duration("90"), Ok(90));
+        assert_eq!(parse_duration("90s"), Ok(90));
+        assert_eq!(parse_duration("30m"), Ok(1800));
+        assert_eq!(parse_duration("1h"), Ok(3600));
+        assert_eq!(parse_duration(" 2d "), Ok(172800));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("1.5h").is_err());
+        assert!(parse_duration("h").is_err());
+        // 桁あふれはエラーにする
+        assert_eq!(
+            parse_duration("999999999999999d"),
+            Err(String::from("duration too large: 999999999999999d"))
+        );
+        assert!(Rule::parse("temp > 30 cooldown 999999999999999d").is_err());
+    }
+
+    #[test]
+    fn fire_and_clear() {
+        let mut engine = AlertEngine::new(parse_rules("heat: temp > 35").unwrap());
+        assert_eq!(kinds(&mut engine, 30.0, 0), vec![]);
+        let events = engine.evaluate_at(&record(36.0), 60);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, AlertKind::Started);
+        assert_eq!(events[0].rule, "heat");
+        assert_eq!(events[0].location, "Osaka");
+        assert_eq!(events[0].value, 36.0);
+        assert_eq!(events[0].at, 60);
+        // 発火中は同じイベントを繰り返さない
+        assert_eq!(kinds(&mut engine, 37.0, 120), vec![]);
+        assert_eq!(kinds(&mut engine, 35.0, 180), vec![AlertKind::Stopped]);
+        assert_eq!(kinds(&mut engine, 34.0, 240), vec![]);
+    }
+
+    #[test]
+    fn fire_after_consecutive_cycles() {
+        let mut engine = AlertEngine::new(parse_rules("temp > 35 for 3 cycles").unwrap());
+        assert_eq!(kinds(&mut engine, 36.0, 0), vec![]);
+        assert_eq!(kinds(&mut engine, 36.0, 60), vec![]);
+        // 途中で条件を外れた場合は数え直す
+        assert_eq!(kinds(&mut engine, 30.0, 120), vec![]);
+        assert_eq!(kinds(&mut engine, 36.0, 180), vec![]);
+        assert_eq!(kinds(&mut engine, 36.0, 240), vec![]);
+        assert_eq!(kinds(&mut engine, 36.0, 300), vec![AlertKind::Started]);
+    }
+
+    #[test]
+    fn clear_with_hysteresis() {
+        let mut engine = AlertEngine::new(parse_rules("temp > 35 hysteresis 2").unwrap());
+        assert_eq!(kinds(&mut engine, 36.0, 0), vec![AlertKind::Started]);
+        // 閾値を下回ってもヒステリシスの幅の中では解除しない
+        assert_eq!(kinds(&mut engine, 34.0, 60), vec![]);
+        assert_eq!(kinds(&mut engine, 33.5, 120), vec![]);
+        assert_eq!(kinds(&mut engine, 33.0, 180), vec![AlertKind::Stopped]);
+        // 解除後は元の閾値で判定する
+        assert_eq!(kinds(&mut engine, 34.0, 240), vec![]);
+        assert_eq!(kinds(&mut engine, 35.5, 300), vec![AlertKind::Started]);
+    }
+
+    #[test]
+    fn suppress_during_cooldown() {
+        let mut engine = AlertEngine::new(parse_rules("temp > 35 cooldown 1h").unwrap());
+        assert_eq!(kinds(&mut engine, 36.0, 0), vec![AlertKind::Started]);
+        assert_eq!(kinds(&mut engine, 30.0, 600), vec![AlertKind::Stopped]);
+        // クールダウン中の発火は通知せず、その解除も通知しない
+        assert_eq!(kinds(&mut engine, 36.0, 1200), vec![]);
+        assert_eq!(kinds(&mut engine, 30.0, 1800), vec![]);
+        // クールダウンが過ぎたら再び通知する
+        assert_eq!(kinds(&mut engine, 36.0, 3600), vec![AlertKind::Started]);
+        assert_eq!(kinds(&mut engine, 30.0, 4200), vec![AlertKind::Stopped]);
+    }
+
+    #[test]
+    fn state_per_location() {
+        let mut engine = AlertEngine::new(parse_rules("temp > 35").unwrap());
+        assert_eq!(kinds(&mut engine, 36.0, 0), vec![AlertKind::Started]);
+        let mut tokyo = record(36.0);
+        tokyo.name = String::from("Tokyo");
+        let events = engine.evaluate_at(&tokyo, 0);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].location, "Tokyo");
+    }
+}
@@ -4,209 +4,15 @@ use serde::Serialize;
 // API定義
 // JSONを受け取ったあとに構造体にデシリアライズする為のもの
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Coord {
-    // 都市の地理的位置、経度
-    pub lon: f32,
-    // 都市の地理的位置、経度
-    pub lat: f32,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Weather {
-    // 気象条件ID
-    pub id: i32,
-    // 気象パラメータのグループ（雨、雪、極端など）
-    pub main: String,
-    // グループ内の気象条件。あなたの言語で出力を得ることができます。
-    pub description: String,
-    // 天気アイコンID
-    pub icon: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Main {
-    // 温度。単位のデフォルト：ケルビン、メートル法：摂氏、インペリアル：華氏。
-    pub temp: f32,
-    // 温度。この温度パラメータは、人間の天気の知覚を説明します。
-    // 単位のデフォルト：ケルビン、メートル法：摂氏、インペリアル：華氏。
-    pub feels_like: f32,
-    // 現時点での最低気温。これは、現在観測されている最低気温です（大規模なメガロポリスや都市部内）。
-    // 単位のデフォルト：ケルビン、メートル法：摂氏、インペリアル：華氏。
-    pub temp_min: f32,
-    // 現時点での最高気温。これは、現在観測されている最高気温です（大規模なメガロポリスと都市部内）。
-    // 単位のデフォルト：ケルビン、メートル法：摂氏、インペリアル：華氏。
-    pub temp_max: f32,
-    // 大気圧（sea_levelまたはgrnd_levelデータがない場合は、海面上）、hPa
-    pub pressure: i32,
-    // 湿度、％
-    pub humidity: i32,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Wind {
-    // 風速。
-    // 単位デフォルト：メートル/秒、メートル法：メートル/秒、インペリアル：マイル/時。
-    pub speed: f32,
-    // 風向、度（気象）
-    pub deg: i32,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Clouds {
-    // 曇り、％
-    pub all: i32,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Sys {
-    // 内部パラメータ
-    pub r#type: i32,
-    // 内部パラメータ
-    pub id: i32,
-    // 内部パラメータ
-    pub message: Option<f32>,
-    // 国コード（GB、JPなど）
-    pub country: String,
-    // 日の出時刻、UNIX、UTC
-    pub sunrise: i32,
-    // 日没時間、UNIX、UTC
-    pub sunset: i32,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct OpenWeatherResponse {
-    pub coord: Option<Coord>,
-    pub weather: Option<Vec<Weather>>,
-    // 内部パラメータ
-    pub base: Option<String>,
-    pub main: Option<Main>,
-    // 視程、メーター。視程の最大値は10kmです
-    pub visibility: Option<i32>,
-    pub wind: Option<Wind>,
-    pub clouds: Option<Clouds>,
-    // データ計算の時間、UNIX、UTC
-    pub dt: Option<i32>,
-    pub sys: Option<Sys>,
-    // UTCから秒単位でシフト
-    pub timezone: Option<i32>,
-    // City ID
-    pub id: Option<i32>,
-    // City name
-    pub name: Option<String>,
-    // 内部パラメータ
-    pub cod: Option<i32>,
-}
-
-// Coord構造体の初期化
-/* impl Coord {
-    pub fn new(lon: f32, lat: f32) -> Self {
-        Coord {
-            lon: lon,
-            lat: lat,
-        }
-    }
-}
-
-// Weather構造体の初期化
-impl Weather {
-    pub fn new(id: i32, main: String, description: String, icon: String) -> Self {
-        Weather {
-            id: id,
-            main: main,
-            description: description,
-            icon: icon,
-        }
-    }
-}
-
-// Main構造体の初期化
-impl Main {
-    pub fn new(temp: f32, feels_like: f32, temp_min: f32, temp_max: f32, pressure: i32, humidity: i32) -> Self {
-        Main {
-            temp: temp,
-            feels_like: feels_like,
-            temp_min: temp_min,
-            temp_max: temp_max,
-            pressure: pressure,
-            humidity: humidity,
-        }
-    }
-}
-
-// Wind構造体の初期化
-impl Wind {
-    pub fn new(speed: f32, deg: i32) -> Self {
-        Wind {
-            speed: speed,
-            deg: deg,
-        }
+// 単位の指定（WEATHER_UNITS）に対応する気温と風速の表示単位
+pub fn unit_labels(units: &str) -> (&'static str, &'static str) {
+    match units {
+        "imperial" => ("°F", "mph"),
+        "standard" => ("K", "m/s"),
+        _ => ("°C", "m/s"),
     }
 }
 
-// Clouds構造体の初期化
-impl Clouds {
-    pub fn new(all: i32) -> Self {
-        Clouds {
-            all: all,
-        }
-    }
-}
-
-// Sys構造体の初期化
-impl Sys {
-    pub fn new(
-        r#type: i32,
-        id: i32,
-        message: f32,
-        country: String,
-        sunrise: i32,
-        sunset: i32,
-    ) -> Self {
-        Sys {
-            r#type: r#type,
-            id: id,
-            message: Some(message),
-            country: country,
-            sunrise: sunrise,
-            sunset: sunset,
-        }
-    }
-} */
-
-// tsv 変換用の構造体
-/* #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
-pub struct OpenWeaterToTsv<'a> {
-    pub lon: f32,
-    pub lat: f32,
-    pub weather_to_id: i32,
-    pub weather_to_main: &'a str,
-    pub description: &'a str,
-    pub icon: &'a str,
-    pub base: &'a str,
-    pub temp: f32,
-    pub feels_like: f32,
-    pub temp_min: f32,
-    pub temp_max: f32,
-    pub pressure: i32,
-    pub humidity: i32,
-    pub visibility: i32,
-    pub speed: f32,
-    pub deg: i32,
-    pub all: i32,
-    pub dt: i32,
-    pub r#type: i32,
-    pub sys_to_id: i32,
-    pub message: f32,
-    pub country: &'a str,
-    pub sunrise: i32,
-    pub sunset: i32,
-    pub timezone: i32,
-    pub id: i32,
-    pub name: &'a str,
-    pub cod: i32,
-} */
-
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct OpenWeaterToTsv {
     pub lon: f64,
@@ -244,6 +50,14 @@ pub struct OpenWeaterToTsv {
     pub id: i64,
     pub name: String,
     pub cod: i64,
+    // 取得元（live: 現在の天気、backfill: 停止中の欠損を過去のデータで埋めたもの、import: History Bulkから取り込んだもの）
+    // 列がない古いファイルはliveとして読む
+    #[serde(default = "default_source")]
+    pub source: String,
+}
+
+fn default_source() -> String {
+    String::from("live")
 }
 
 /* impl<'a> OpenWeaterToTsv<'a> {
@@ -348,6 +162,7 @@ impl OpenWeaterToTsv {
             id: 0,
             name: String::from(""),
             cod: 0,
+            source: default_source(),
         }
     }
 }
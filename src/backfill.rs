@@ -0,0 +1,436 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use chrono::{DateTime, Local, TimeZone};
+use serde_json::{json, Value};
+
+use crate::alerts;
+use crate::api::OpenWeaterToTsv;
+use crate::config;
+use crate::env_or;
+use crate::jobs::{self, check_response};
+use crate::schedule::Schedule;
+use crate::secrets;
+use crate::state;
+use crate::store;
+use crate::ApiClient;
+
+// History APIで1回に取得できる期間（1週間）
+const HISTORY_MAX_RANGE: i64 = 7 * 86400;
+
+// スケジュールで取得する予定だった時刻（fromより後、now以前）
+// 起点（ALIGN=0の場合の開始時刻）は前回の取得の開始時刻
+fn expected_slots(schedule: &Schedule, anchor: DateTime<Local>, from: i64, now: i64) -> Vec<i64> {
+    let mut slots = Vec::new();
+    let mut t = Local.timestamp(from, 0);
+    while let Some(next) = schedule.next_after(anchor, t) {
+        if next.timestamp() > now {
+            break;
+        }
+        slots.push(next.timestamp());
+        t = next;
+    }
+    slots
+}
+
+// 欠損している枠を求める（expectedは予定の時刻の昇順）
+// 枠の前後の枠との間隔の半分以内に取得結果がなければ欠損とする
+// 次の取得で埋まる直近の枠（最後の枠）は対象外
+fn missing_slots(dts: &[i64], expected: &[i64]) -> Vec<i64> {
+    let mut slots = Vec::new();
+    for (i, slot) in expected
+        .iter()
+        .enumerate()
+        .take(expected.len().saturating_sub(1))
+    {
+        let after = expected[i + 1] - slot;
+        let before = if i > 0 { slot - expected[i - 1] } else { after };
+        let j = dts.partition_point(|v| *v < slot - before / 2);
+        if dts.get(j).is_none_or(|v| *v >= slot + after / 2) {
+            slots.push(*slot);
+        }
+    }
+    slots
+}
+
+// 天気（weather配列の先頭）を設定する
+fn set_weather(record: &mut OpenWeaterToTsv, value: &Value) {
+    let weather = value.get("weather").and_then(|v| v.get(0));
+    let weather = weather.unwrap_or(&Value::Null);
+    record.weather_to_id = weather.get("id").and_then(|v| v.as_i64()).unwrap_or(0);
+    let text = |key: &str| {
+        weather
+            .get(key)
+            .and_then(|v| v.as_str())
+            .unwrap_or("")
+            .to_string()
+    };
+    record.weather_to_main = text("main");
+    record.description = text("description");
+    record.icon = text("icon");
+}
+
+// 雨量・積雪量（「1h」「3h」）を取得する
+fn amount(value: &Value, kind: &str, hours: &str) -> f64 {
+    value
+        .get(kind)
+        .and_then(|v| v.get(hours))
+        .and_then(|v| v.as_f64())
+        .unwrap_or(0.0)
+}
+
+// 日の出・日没の時刻（ローカル時間）
+fn time_of_day(value: Option<&Value>) -> Option<String> {
+    let t: DateTime<Local> = Local.timestamp(value?.as_i64()?, 0);
+    Some(t.format("%H:%M:%S").to_string())
+}
+
+// 地点の情報（地点名、国、緯度経度など）は保存済みの取得結果から引き継ぐ
+fn base_record(base: &OpenWeaterToTsv, dt: i64) -> OpenWeaterToTsv {
+    let mut record = OpenWeaterToTsv::new();
+    record.lat = base.lat;
+    record.lon = base.lon;
+    record.country = base.country.clone();
+    record.sunrise = base.sunrise.clone();
+    record.sunset = base.sunset.clone();
+    record.timezone = base.timezone;
+    record.id = base.id;
+    record.name = base.name.clone();
+    record.cod = 200;
+    record.dt = dt;
+    record.source = String::from("backfill");
+    record
+}
+
+// One Call API（timemachine）のdataの1件を変換する
+fn from_onecall(base: &OpenWeaterToTsv, value: &Value) -> Option<OpenWeaterToTsv> {
+    let f = |key: &str| value.get(key).and_then(|v| v.as_f64()).unwrap_or(0.0);
+    let i = |key: &str| value.get(key).and_then(|v| v.as_i64()).unwrap_or(0);
+    let mut record = base_record(base, value.get("dt")?.as_i64()?);
+    set_weather(&mut record, value);
+    record.temp = f("temp");
+    record.feels_like = f("feels_like");
+    record.temp_min = record.temp;
+    record.temp_max = record.temp;
+    record.pressure = i("pressure");
+    record.humidity = i("humidity");
+    record.visibility = i("visibility");
+    record.speed = f("wind_speed");
+    record.deg = i("wind_deg");
+    record.gust = f("wind_gust");
+    record.all = i("clouds");
+    record.rain_1h = amount(value, "rain", "1h");
+    record.snow_h1 = amount(value, "snow", "1h");
+    if let Some(v) = time_of_day(value.get("sunrise")) {
+        record.sunrise = v;
+    }
+    if let Some(v) = time_of_day(value.get("sunset")) {
+        record.sunset = v;
+    }
+    Some(record)
+}
+
+// 現在の天気と同じ形式（main、wind、clouds、weather、rain、snow）の値を設定する
+// History APIやHistory Bulkの1件もこの形式
+pub fn set_observation(record: &mut OpenWeaterToTsv, value: &Value) {
+    let main = value.get("main").unwrap_or(&Value::Null);
+    let wind = value.get("wind").unwrap_or(&Value::Null);
+    let f = |v: &Value, key: &str| v.get(key).and_then(|v| v.as_f64()).unwrap_or(0.0);
+    let i =
+        |v: &Value, key: &str| v.get(key).and_then(|v| v.as_f64()).unwrap_or(0.0).round() as i64;
+    set_weather(record, value);
+    record.temp = f(main, "temp");
+    record.feels_like = f(main, "feels_like");
+    record.temp_min = f(main, "temp_min");
+    record.temp_max = f(main, "temp_max");
+    record.pressure = i(main, "pressure");
+    record.sea_level = i(main, "sea_level");
+    record.grnd_level = i(main, "grnd_level");
+    record.humidity = i(main, "humidity");
+    record.visibility = i(value, "visibility");
+    record.speed = f(wind, "speed");
+    record.deg = i(wind, "deg");
+    record.gust = f(wind, "gust");
+    record.all = i(value.get("clouds").unwrap_or(&Value::Null), "all");
+    record.rain_1h = amount(value, "rain", "1h");
+    record.rain_3h = amount(value, "rain", "3h");
+    record.snow_h1 = amount(value, "snow", "1h");
+    record.snow_h3 = amount(value, "snow", "3h");
+}
+
+// History APIのlistの1件を変換する
+fn from_history(base: &OpenWeaterToTsv, value: &Value) -> Option<OpenWeaterToTsv> {
+    let mut record = base_record(base, value.get("dt")?.as_i64()?);
+    set_observation(&mut record, value);
+    Some(record)
+}
+
+// 欠損している枠の天気を取得する
+// onecall: 枠ごとにtimemachineを呼び出す
+// history: 欠損している期間の1時間ごとの天気をまとめて取得し、枠に最も近いものを使う
+// 途中で失敗した場合も、それまでに取得できた分とエラーを返す
+async fn fetch(
+    api_client: &ApiClient,
+    base: &OpenWeaterToTsv,
+    slots: &[i64],
+    spacing: i64,
+) -> (Vec<OpenWeaterToTsv>, Option<Box<dyn std::error::Error>>) {
+    let mut records = Vec::new();
+    let mut error = None;
+    match env_or("BACKFILL_API", "onecall").as_str() {
+        "onecall" => {
+            for slot in slots {
+                let value = match api_client.get_timemachine(base.lat, base.lon, *slot).await {
+                    Ok(body) => check_response(&body),
+                    Err(e) => Err(e),
+                };
+                let value = match value {
+                    Ok(v) => v,
+                    Err(e) => {
+                        error = Some(e);
+                        break;
+                    }
+                };
+                let data = value.get("data").and_then(|v| v.get(0));
+                if let Some(record) = data.and_then(|v| from_onecall(base, v)) {
+                    records.push(record);
+                }
+            }
+        }
+        "history" => {
+            let mut candidates = Vec::new();
+            let mut start = slots[0] - spacing / 2;
+            let end = slots[slots.len() - 1] + spacing / 2;
+            while start < end {
+                let chunk_end = end.min(start + HISTORY_MAX_RANGE);
+                let value = match api_client
+                    .get_history(base.lat, base.lon, start, chunk_end)
+                    .await
+                {
+                    Ok(body) => check_response(&body),
+                    Err(e) => Err(e),
+                };
+                let value = match value {
+                    Ok(v) => v,
+                    Err(e) => {
+                        error = Some(e);
+                        break;
+                    }
+                };
+                let list = value.get("list").cloned().unwrap_or(json!([]));
+                for item in list.as_array().into_iter().flatten() {
+                    candidates.extend(from_history(base, item));
+                }
+                start = chunk_end;
+            }
+            for slot in slots {
+                let nearest = candidates
+                    .iter()
+                    .filter(|v| (v.dt - slot).abs() <= spacing / 2)
+                    .min_by_key(|v| (v.dt - slot).abs());
+                if let Some(record) = nearest {
+                    records.push(record.clone());
+                }
+            }
+        }
+        v => error = Some(format!("BACKFILL_API: invalid value {:?} (onecall, history)", v).into()),
+    }
+    (records, error)
+}
+
+// 地点の取得スケジュール
+struct Plan {
+    location: String,
+    schedule: Schedule,
+    // 前回の取得の開始時刻（ALIGN=0の場合の起点）
+    started: Option<i64>,
+}
+
+// 地点ごとの取得スケジュール
+// JOBSを使う場合は保存するweatherのジョブ、それ以外はSCHEDULE、INTERVAL、ALIGNに従う
+fn plans(locations: &[String]) -> Result<Vec<Plan>, Box<dyn std::error::Error>> {
+    let state = state::load(&state::path())
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    let mut plans = Vec::new();
+    let job_specs = env_or("JOBS", "");
+    if job_specs.is_empty() {
+        let started = state.schedule.map(|v| v.started);
+        let schedule = Schedule::from_values(&config::current())?;
+        for location_name in locations {
+            plans.push(Plan {
+                location: location_name.clone(),
+                schedule: schedule.clone(),
+                started,
+            });
+        }
+        return Ok(plans);
+    }
+    for job in jobs::parse_jobs(&job_specs)? {
+        // 1回だけのジョブには決まった枠がない
+        if job.endpoint != jobs::Endpoint::Weather
+            || !job.sinks.contains(&jobs::Sink::Collector)
+            || job.runs == Some(1)
+        {
+            continue;
+        }
+        let started = state.jobs.get(&job.name).map(|v| v.started);
+        let job_locations = if job.locations.is_empty() {
+            locations
+        } else {
+            &job.locations
+        };
+        for location_name in job_locations {
+            plans.push(Plan {
+                location: location_name.clone(),
+                schedule: job.schedule.clone(),
+                started,
+            });
+        }
+    }
+    Ok(plans)
+}
+
+// 起動時に保存済みの取得結果の欠損を探し、過去のデータで埋める（BACKFILL=1かつTSV_OUT=1の場合）
+// 対象はBACKFILL_WINDOW（デフォルト2日）以内で、取得のスケジュールの枠に取得結果がない時間帯
+// 埋めた取得結果はsourceをbackfillにして、地点ごとに1つのtsvファイルに保存する
+pub async fn run(
+    api_client: &ApiClient,
+    locations: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    if env_or("BACKFILL", "1") != "1" || env_or("TSV_OUT", "0") != "1" {
+        return Ok(());
+    }
+    let window = alerts::parse_duration(&env_or("BACKFILL_WINDOW", "2d"))
+        .map_err(|e| format!("BACKFILL_WINDOW: {}", e))?;
+    if window <= 0 {
+        return Ok(());
+    }
+
+    let now = Local::now();
+    let records = store::load_records(Path::new(store::LOG_DIR))?;
+    let plans = plans(locations)?;
+    let mut names: Vec<&String> = Vec::new();
+    for plan in &plans {
+        if !names.contains(&&plan.location) {
+            names.push(&plan.location);
+        }
+    }
+    for location_name in names {
+        let stored: Vec<&OpenWeaterToTsv> = records
+            .iter()
+            .filter(|v| store::is_location(v, location_name))
+            .collect();
+        let base = match stored.last() {
+            Some(v) => *v,
+            None => continue,
+        };
+        let dts: Vec<i64> = stored.iter().map(|v| v.dt).collect();
+
+        // 最初の保存済みの取得結果より前は対象外
+        let from = dts[0].max(now.timestamp() - window);
+        let mut expected = Vec::new();
+        for plan in plans.iter().filter(|v| &v.location == location_name) {
+            let anchor = Local.timestamp(plan.started.unwrap_or(dts[0]), 0);
+            expected.extend(expected_slots(
+                &plan.schedule,
+                anchor,
+                from,
+                now.timestamp(),
+            ));
+        }
+        expected.sort_unstable();
+        expected.dedup();
+        let slots = missing_slots(&dts, &expected);
+        if slots.is_empty() {
+            continue;
+        }
+        let spacing = expected
+            .windows(2)
+            .map(|v| v[1] - v[0])
+            .min()
+            .unwrap_or(3600);
+
+        // 取得に失敗した場合は、それまでに取得できた分だけ保存して次の地点へ進む
+        let (fetched, error) = fetch(api_client, base, &slots, spacing).await;
+        if let Some(e) = error {
+            tracing::warn!(
+                location = %location_name,
+                slots = slots.len(),
+                fetched = fetched.len(),
+                error = %secrets::redact(&e.to_string()),
+                "backfill failed"
+            );
+        }
+        // 保存済みの観測時刻と重複するものは除く
+        let mut seen: HashSet<i64> = dts.into_iter().collect();
+        let rows: Vec<OpenWeaterToTsv> =
+            fetched.into_iter().filter(|v| seen.insert(v.dt)).collect();
+        if !rows.is_empty() {
+            let name = format!(
+                "backfill-{}-{}",
+                location_name.to_lowercase(),
+                now.format("%Y-%m-%d%H:%M:%S")
+            );
+            store::write_records(&name, &rows)?;
+        }
+        tracing::info!(
+            location = %location_name,
+            slots = slots.len(),
+            rows = rows.len(),
+            "backfilled"
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::Cron;
+
+    fn at(h: u32, mi: u32) -> i64 {
+        Local.ymd(2024, 1, 1).and_hms(h, mi, 0).timestamp()
+    }
+
+    #[test]
+    fn slots_follow_cron_schedule() {
+        let schedule = Schedule::Cron(Cron::parse("0 * * * *").unwrap());
+        let anchor = Local.timestamp(at(0, 0), 0);
+        let expected = expected_slots(&schedule, anchor, at(0, 10), at(4, 20));
+        assert_eq!(expected, vec![at(1, 0), at(2, 0), at(3, 0), at(4, 0)]);
+
+        // 毎時0分に取得していれば、30分の枠を欠損とはしない
+        let dts = vec![at(0, 0), at(1, 0), at(2, 0), at(3, 0)];
+        assert!(missing_slots(&dts, &expected).is_empty());
+        // 直近の枠は次の取得で埋まる
+        let dts = vec![at(0, 0), at(1, 0), at(3, 0)];
+        assert_eq!(missing_slots(&dts, &expected), vec![at(2, 0)]);
+    }
+
+    #[test]
+    fn slots_follow_unaligned_interval() {
+        let schedule = Schedule::interval("30m", false).unwrap();
+        let anchor = Local.timestamp(at(0, 7), 0);
+        let expected = expected_slots(&schedule, anchor, at(0, 7), at(2, 0));
+        assert_eq!(expected, vec![at(0, 37), at(1, 7), at(1, 37)]);
+
+        // 観測時刻（dt）は取得した時刻より少し前になる
+        let dts = vec![at(0, 5), at(0, 35)];
+        assert_eq!(missing_slots(&dts, &expected), vec![at(1, 7)]);
+    }
+
+    #[test]
+    fn slots_follow_aligned_interval() {
+        let schedule = Schedule::interval("30m", true).unwrap();
+        let anchor = Local.timestamp(at(0, 7), 0);
+        let expected = expected_slots(&schedule, anchor, at(0, 7), at(2, 0));
+        assert_eq!(expected, vec![at(0, 30), at(1, 0), at(1, 30), at(2, 0)]);
+
+        let dts = vec![at(0, 7), at(0, 44), at(1, 29)];
+        assert_eq!(missing_slots(&dts, &expected), vec![at(1, 0)]);
+        assert!(missing_slots(&dts, &[]).is_empty());
+    }
+}
@@ -0,0 +1,164 @@
+use serde_json::{json, Value};
+
+use crate::api::{self, OpenWeaterToTsv};
+use crate::template;
+
+// ステータスバー向けの出力形式
+#[derive(Clone, Copy, PartialEq)]
+pub enum BarFormat {
+    // Waybarのカスタムモジュール（1行に1つのJSON）
+    Waybar,
+    // i3bar / swaybarのプロトコル（ブロックの配列を無限に続ける）
+    I3bar,
+    // Polybarのスクリプトモジュール（1行のテキスト）
+    Polybar,
+}
+
+impl BarFormat {
+    // DISPLAY_MODEから出力形式を決める。ステータスバー向けでない場合はNone
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "waybar" => Some(BarFormat::Waybar),
+            "i3bar" => Some(BarFormat::I3bar),
+            "polybar" => Some(BarFormat::Polybar),
+            _ => None,
+        }
+    }
+}
+
+// 天気のグループ（Clear, Clouds, Rain...）をCSSのクラス名にする
+pub fn class(record: &OpenWeaterToTsv) -> String {
+    record.weather_to_main.to_lowercase()
+}
+
+// i3barのブロックの文字色（天気のグループごと）
+fn color(record: &OpenWeaterToTsv) -> &'static str {
+    match record.weather_to_main.as_str() {
+        "Clear" => "#f9e2af",
+        "Clouds" => "#bac2de",
+        "Rain" | "Drizzle" => "#89b4fa",
+        "Thunderstorm" => "#cba6f7",
+        "Snow" => "#ffffff",
+        _ => "#a6adc8",
+    }
+}
+
+// ツールチップに表示する詳細（単位は取得時の指定に従う）
+pub fn tooltip(record: &OpenWeaterToTsv, units: &str) -> String {
+    let v = record;
+    let (t, s) = api::unit_labels(units);
+    [
+        format!("{} ({})", v.name, v.country),
+        format!("{} {}", v.weather_to_main, v.description),
+        format!(
+            "temp: {:.1}{t} (feels like {:.1}{t}, {:.1}〜{:.1}{t})",
+            v.temp, v.feels_like, v.temp_min, v.temp_max
+        ),
+        format!("humidity: {}%", v.humidity),
+        format!("pressure: {}hPa", v.pressure),
+        format!("wind: {}{s} {}° (gust {}{s})", v.speed, v.deg, v.gust),
+        format!("clouds: {}%", v.all),
+        format!("visibility: {}m", v.visibility),
+        format!("rain: {}mm/1h", v.rain_1h),
+        format!("snow: {}mm/1h", v.snow_h1),
+        format!("sunrise: {}", v.sunrise),
+        format!("sunset: {}", v.sunset),
+    ]
+    .join("\n")
+}
+
+// Waybarのカスタムモジュール（return-type: json）の出力
+// 複数地点の場合はテキストを「 | 」で、ツールチップを空行でつなぐ
+// percentageには湿度を使う（format-iconsの切り替え用）
+pub fn waybar(records: &[OpenWeaterToTsv], text_template: &str, units: &str) -> Value {
+    let text: Vec<String> = records
+        .iter()
+        .map(|v| template::render(text_template, &template::record_value(v)))
+        .collect();
+    let tooltip: Vec<String> = records.iter().map(|v| tooltip(v, units)).collect();
+    let class: Vec<String> = records.iter().map(class).collect();
+    let percentage = records.first().map(|v| v.humidity).unwrap_or(0);
+    json!({
+        "text": text.join(" | "),
+        "tooltip": tooltip.join("\n\n"),
+        "class": class,
+        "percentage": percentage,
+    })
+}
+
+// i3barのブロック（地点ごとに1つ）
+pub fn i3bar(records: &[OpenWeaterToTsv], text_template: &str, units: &str) -> Value {
+    let (temp_unit, _) = api::unit_labels(units);
+    let blocks: Vec<Value> = records
+        .iter()
+        .map(|v| {
+            json!({
+                "name": "weather",
+                "instance": v.name,
+                "full_text": template::render(text_template, &template::record_value(v)),
+                "short_text": format!("{:.0}{}", v.temp, temp_unit),
+                "color": color(v),
+            })
+        })
+        .collect();
+    json!(blocks)
+}
+
+// Polybarの出力（複数地点は「 | 」でつなぐ）
+pub fn polybar(records: &[OpenWeaterToTsv], text_template: &str) -> String {
+    let text: Vec<String> = records
+        .iter()
+        .map(|v| template::render(text_template, &template::record_value(v)))
+        .collect();
+    text.join(" | ")
+}
+
+// i3barプロトコルのヘッダー。以降は「[ブロック],」を1行ずつ出力する
+pub fn i3bar_header() -> String {
+    format!("{}\n[\n[],", json!({ "version": 1 }))
+}
+
+// 1回分の取得結果を出力形式に合わせた1行にする
+pub fn line(
+    format: BarFormat,
+    records: &[OpenWeaterToTsv],
+    text_template: &str,
+    units: &str,
+) -> String {
+    match format {
+        BarFormat::Waybar => waybar(records, text_template, units).to_string(),
+        BarFormat::I3bar => format!("{},", i3bar(records, text_template, units)),
+        BarFormat::Polybar => polybar(records, text_template),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> OpenWeaterToTsv {
+        let mut record = OpenWeaterToTsv::new();
+        record.name = String::from("Osaka");
+        record.weather_to_main = String::from("Clouds");
+        record.temp = 70.3;
+        record.speed = 8.1;
+        record
+    }
+
+    #[test]
+    fn unit_labels_follow_units() {
+        let tooltip = tooltip(&record(), "imperial");
+        assert!(tooltip.contains("temp: 70.3°F"), "{}", tooltip);
+        assert!(tooltip.contains("wind: 8.1mph"), "{}", tooltip);
+        assert!(!tooltip.contains("°C"), "{}", tooltip);
+
+        let blocks = i3bar(&[record()], "{name}", "imperial");
+        assert_eq!(blocks[0]["short_text"], "70°F");
+        let blocks = i3bar(&[record()], "{name}", "metric");
+        assert_eq!(blocks[0]["short_text"], "70°C");
+
+        let value = waybar(&[record()], "{name}", "standard");
+        assert!(value["tooltip"].as_str().unwrap().contains("70.3K"));
+        assert_eq!(value["class"], json!(["clouds"]));
+    }
+}
@@ -0,0 +1,395 @@
+use chrono::{DateTime, Local, TimeZone};
+
+use crate::api::OpenWeaterToTsv;
+
+// 折れ線グラフの描画方法
+#[derive(Clone, Copy, PartialEq)]
+pub enum ChartStyle {
+    // 点字で1文字に2x4ドット描画する
+    Braille,
+    // 「*」で1文字に1点描画する（点字が表示できない端末向け）
+    Ascii,
+}
+
+impl ChartStyle {
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "ascii" => ChartStyle::Ascii,
+            _ => ChartStyle::Braille,
+        }
+    }
+}
+
+// グラフにする項目（表示名、単位、値の取り出し方）
+pub struct Series {
+    pub name: &'static str,
+    pub unit: &'static str,
+    pub value: fn(&OpenWeaterToTsv) -> f64,
+}
+
+pub const SERIES: [Series; 3] = [
+    Series {
+        name: "Temperature",
+        unit: "°C",
+        value: |v| v.temp,
+    },
+    Series {
+        name: "Pressure",
+        unit: "hPa",
+        value: |v| v.pressure as f64,
+    },
+    Series {
+        name: "Humidity",
+        unit: "%",
+        value: |v| v.humidity as f64,
+    },
+];
+
+// 欠測とみなさずに線でつなぐ最大の間隔（秒）。取得間隔は30分
+const MAX_GAP_SECS: i64 = 7200;
+
+// 期間[from, to]をn個に区切り、区間ごとの平均値を返す
+// データがない区間は前後の値で補間し、MAX_GAP_SECSを超える欠測はNoneのままにする
+pub fn bucket(
+    records: &[OpenWeaterToTsv],
+    value: fn(&OpenWeaterToTsv) -> f64,
+    from: i64,
+    to: i64,
+    n: usize,
+) -> Vec<Option<f64>> {
+    if n == 0 {
+        return Vec::new();
+    }
+    let mut sums = vec![(0.0, 0); n];
+    let span = (to - from).max(1) as f64;
+    for record in records {
+        if record.dt < from || record.dt > to {
+            continue;
+        }
+        let i = (((record.dt - from) as f64 / span) * n as f64) as usize;
+        let entry = &mut sums[i.min(n - 1)];
+        entry.0 += value(record);
+        entry.1 += 1;
+    }
+    let mut values: Vec<Option<f64>> = sums
+        .into_iter()
+        .map(|(sum, count)| {
+            if count == 0 {
+                None
+            } else {
+                Some(sum / count as f64)
+            }
+        })
+        .collect();
+
+    // 短い欠測を線形補間する
+    let max_gap = (MAX_GAP_SECS as f64 / (span / n as f64)).ceil() as usize;
+    let mut previous: Option<usize> = None;
+    for i in 0..n {
+        let v = match values[i] {
+            Some(v) => v,
+            None => continue,
+        };
+        if let Some(p) = previous {
+            let gap = i - p - 1;
+            if gap > 0 && gap <= max_gap {
+                let pv = values[p].unwrap_or(v);
+                for (k, slot) in values.iter_mut().enumerate().take(i).skip(p + 1) {
+                    let t = (k - p) as f64 / (i - p) as f64;
+                    *slot = Some(pv + (v - pv) * t);
+                }
+            }
+        }
+        previous = Some(i);
+    }
+    values
+}
+
+fn range(values: &[Option<f64>]) -> Option<(f64, f64)> {
+    let mut iter = values.iter().flatten();
+    let first = *iter.next()?;
+    Some(iter.fold((first, first), |(min, max), &v| (min.min(v), max.max(v))))
+}
+
+// スパークライン（▁〜█）を作成する。データがない区間は空白にする
+pub fn sparkline(values: &[Option<f64>]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let (min, max) = match range(values) {
+        Some(v) => v,
+        None => return " ".repeat(values.len()),
+    };
+    values
+        .iter()
+        .map(|v| match v {
+            None => ' ',
+            Some(_) if max == min => BARS[3],
+            Some(v) => BARS[(((v - min) / (max - min)) * 7.0).round() as usize],
+        })
+        .collect()
+}
+
+// 値を0..levelsの段階に変換する（上が大きい値）
+fn level(v: f64, min: f64, max: f64, levels: usize) -> usize {
+    if max == min {
+        return levels / 2;
+    }
+    (((v - min) / (max - min)) * (levels - 1) as f64).round() as usize
+}
+
+// 折れ線グラフを作成する
+// 点字の場合はvaluesの2件で1文字、ASCIIの場合は1件で1文字になる
+pub fn line_chart(values: &[Option<f64>], height: usize, style: ChartStyle) -> Vec<String> {
+    if height == 0 {
+        return Vec::new();
+    }
+    let (min, max) = match range(values) {
+        Some(v) => v,
+        None => return vec![String::from("(no data)")],
+    };
+
+    let (dots_x, dots_y) = match style {
+        ChartStyle::Braille => (2, 4),
+        ChartStyle::Ascii => (1, 1),
+    };
+    let columns = values.len().div_ceil(dots_x);
+    let levels = height * dots_y;
+    // 描画するドット（上から数えた行、列）
+    let mut canvas = vec![vec![false; values.len()]; levels];
+    let mut previous: Option<usize> = None;
+    for (x, v) in values.iter().enumerate() {
+        let y = match v {
+            Some(v) => levels - 1 - level(*v, min, max, levels),
+            None => {
+                previous = None;
+                continue;
+            }
+        };
+        // 前の点と縦につなぐ
+        let (from, to) = match previous {
+            Some(p) => (p.min(y), p.max(y)),
+            None => (y, y),
+        };
+        for row in canvas.iter_mut().take(to + 1).skip(from) {
+            row[x] = true;
+        }
+        previous = Some(y);
+    }
+
+    let mut lines = Vec::new();
+    for row in 0..height {
+        let mut line = String::new();
+        for column in 0..columns {
+            match style {
+                ChartStyle::Braille => {
+                    // 点字のドット配置（左列: 1,2,3,7 右列: 4,5,6,8）
+                    const BITS: [[u32; 4]; 2] =
+                        [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+                    let mut bits = 0;
+                    for (dx, column_bits) in BITS.iter().enumerate() {
+                        let x = column * 2 + dx;
+                        if x >= values.len() {
+                            continue;
+                        }
+                        for (dy, bit) in column_bits.iter().enumerate() {
+                            if canvas[row * 4 + dy][x] {
+                                bits |= bit;
+                            }
+                        }
+                    }
+                    line.push(char::from_u32(0x2800 + bits).unwrap_or(' '));
+                }
+                ChartStyle::Ascii => {
+                    line.push(if canvas[row][column] { '*' } else { ' ' });
+                }
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+// 地点の履歴をグラフで表示するための文字列を作成する
+pub fn render_history(
+    records: &[OpenWeaterToTsv],
+    window: i64,
+    width: usize,
+    height: usize,
+    style: ChartStyle,
+) -> Vec<String> {
+    let to = Local::now().timestamp();
+    let from = to - window;
+    // 軸ラベルの幅
+    let label = 10;
+    let plot = width.saturating_sub(label + 1).max(8);
+    let dots_x = match style {
+        ChartStyle::Braille => 2,
+        ChartStyle::Ascii => 1,
+    };
+
+    let mut lines = Vec::new();
+    for series in SERIES.iter() {
+        let values = bucket(records, series.value, from, to, plot * dots_x);
+        let spark = bucket(records, series.value, from, to, plot);
+        let latest = records
+            .iter()
+            .rev()
+            .find(|v| v.dt >= from)
+            .map(|v| format!("{:.1}{}", (series.value)(v), series.unit))
+            .unwrap_or_else(|| String::from("-"));
+        let (min, max) = range(&values).unwrap_or((0.0, 0.0));
+
+        lines.push(format!(
+            "{}  now {}  min {:.1}  max {:.1}",
+            series.name, latest, min, max
+        ));
+        lines.push(format!("{:>1$} {2}", "", label, sparkline(&spark)));
+        for (i, row) in line_chart(&values, height, style).into_iter().enumerate() {
+            let axis = if i == 0 {
+                format!("{:.1}", max)
+            } else if i == height - 1 {
+                format!("{:.1}", min)
+            } else {
+                String::new()
+            };
+            lines.push(format!("{:>1$} {2}", axis, label, row));
+        }
+
+        let from_label: DateTime<Local> = Local.timestamp(from, 0);
+        let to_label: DateTime<Local> = Local.timestamp(to, 0);
+        let from_label = from_label.format("%m/%d %H:%M").to_string();
+        let to_label = to_label.format("%m/%d %H:%M").to_string();
+        lines.push(format!(
+            "{:>1$} {2}{3:>4$}",
+            "",
+            label,
+            from_label,
+            to_label,
+            plot.saturating_sub(from_label.len())
+        ));
+        lines.push(String::new());
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: i64 = 3600;
+
+    fn record(dt: i64, temp: f64) -> OpenWeaterToTsv {
+        let mut record = OpenWeaterToTsv::new();
+        record.dt = dt;
+        record.temp = temp;
+        record
+    }
+
+    fn temp(v: &OpenWeaterToTsv) -> f64 {
+        v.temp
+    }
+
+    #[test]
+    fn bucket_empty() {
+        assert_eq!(bucket(&[], temp, 0, 10 * HOUR, 5), vec![None; 5]);
+        assert!(bucket(&[record(HOUR, 1.0)], temp, 0, 10 * HOUR, 0).is_empty());
+    }
+
+    #[test]
+    fn bucket_single_point() {
+        let values = bucket(&[record(3 * HOUR, 12.5)], temp, 0, 10 * HOUR, 10);
+        let mut expected = vec![None; 10];
+        expected[3] = Some(12.5);
+        assert_eq!(values, expected);
+
+        // 期間の終わりちょうどの値は最後の区間に入る
+        let values = bucket(&[record(10 * HOUR, 1.0)], temp, 0, 10 * HOUR, 10);
+        assert_eq!(values[9], Some(1.0));
+    }
+
+    #[test]
+    fn bucket_average_and_gaps() {
+        let records = [
+            record(-HOUR, 100.0),
+            record(0, 10.0),
+            record(HOUR / 2, 20.0),
+            record(3 * HOUR, 45.0),
+            record(9 * HOUR, 0.0),
+            record(11 * HOUR, 100.0),
+        ];
+        let values = bucket(&records, temp, 0, 10 * HOUR, 10);
+        assert_eq!(
+            values,
+            vec![
+                Some(15.0),
+                Some(25.0),
+                Some(35.0),
+                Some(45.0),
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(0.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn sparkline_scaling() {
+        assert_eq!(sparkline(&[]), "");
+        assert_eq!(sparkline(&[None, None]), "  ");
+        assert_eq!(sparkline(&[None, Some(5.0), None]), " ▄ ");
+        assert_eq!(
+            sparkline(&[Some(0.0), Some(7.0), None, Some(3.0), Some(14.0)]),
+            "▁▅ ▃█"
+        );
+    }
+
+    #[test]
+    fn line_chart_empty() {
+        assert_eq!(line_chart(&[], 3, ChartStyle::Ascii), vec!["(no data)"]);
+        assert_eq!(
+            line_chart(&[None, None], 3, ChartStyle::Braille),
+            vec!["(no data)"]
+        );
+        assert!(line_chart(&[Some(1.0)], 0, ChartStyle::Braille).is_empty());
+    }
+
+    #[test]
+    fn line_chart_single_point() {
+        assert_eq!(
+            line_chart(&[Some(1.0)], 3, ChartStyle::Ascii),
+            vec![" ", "*", " "]
+        );
+        // 点字は1件でも1文字になり、中央の段に描画する
+        assert_eq!(
+            line_chart(&[Some(1.0)], 1, ChartStyle::Braille),
+            vec!["\u{2802}"]
+        );
+    }
+
+    #[test]
+    fn line_chart_ascii() {
+        let values = [Some(0.0), Some(1.0), Some(2.0)];
+        assert_eq!(
+            line_chart(&values, 3, ChartStyle::Ascii),
+            vec!["  *", " **", "** "]
+        );
+        // 欠測をまたいで線をつながない
+        let values = [Some(0.0), None, Some(2.0)];
+        assert_eq!(
+            line_chart(&values, 3, ChartStyle::Ascii),
+            vec!["  *", "   ", "*  "]
+        );
+    }
+
+    #[test]
+    fn line_chart_braille_odd_length() {
+        // 3件は2文字になり、最後の文字は左列だけを使う
+        let values = [Some(0.0), Some(1.0), Some(2.0)];
+        assert_eq!(
+            line_chart(&values, 1, ChartStyle::Braille),
+            vec!["\u{28f0}\u{2803}"]
+        );
+    }
+}
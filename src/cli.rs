@@ -0,0 +1,171 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+use crate::config::Values;
+use crate::env_or;
+
+// コマンドライン引数
+// 指定したオプションは.envの値より優先する
+#[derive(Parser)]
+#[command(
+    name = "openweather-client",
+    version,
+    about = "OpenWeather collector and viewer"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Config file (default: $XDG_CONFIG_HOME/openweather-client/config.toml)
+    #[arg(short, long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Profile in the config file [env: PROFILE]
+    #[arg(short, long, global = true)]
+    pub profile: Option<String>,
+
+    /// Location name(s), comma separated; use name@lat:lon to pin a location to coordinates [env: LOCATION_NAME]
+    #[arg(short, long, global = true)]
+    pub location: Option<String>,
+
+    /// Units for one-shot output and the initial dashboard units: metric, imperial or standard [env: WEATHER_UNITS]
+    #[arg(short, long, global = true)]
+    pub units: Option<String>,
+
+    /// Language of the weather description, e.g. ja, en [env: WEATHER_LANG]
+    #[arg(long, global = true)]
+    pub lang: Option<String>,
+
+    /// Output format: plain, template, json, dashboard, waybar, i3bar, polybar (export: tsv, csv, json; report: table, tsv, markdown) [env: DISPLAY_MODE]
+    #[arg(short, long, global = true)]
+    pub format: Option<String>,
+
+    /// Collection interval, e.g. 30m; aligned to the clock unless ALIGN=0 [env: INTERVAL]
+    #[arg(short, long, global = true)]
+    pub interval: Option<String>,
+
+    /// Stop collecting after this duration, e.g. 7d; 0 for no limit [env: DURATION]
+    #[arg(short, long, global = true)]
+    pub duration: Option<String>,
+
+    /// Cron expression (minute hour day month weekday) used instead of --interval, e.g. "*/10 * * * *" [env: SCHEDULE]
+    #[arg(long, global = true)]
+    pub cron: Option<String>,
+
+    /// Stop collecting at this time (unix seconds, RFC3339 or YYYY-MM-DD) [env: UNTIL]
+    #[arg(long, global = true)]
+    pub until: Option<String>,
+
+    /// Stop collecting after this many runs [env: RUNS]
+    #[arg(long, global = true)]
+    pub runs: Option<u64>,
+
+    /// Never prompt; fail if the configuration is incomplete (automatic when stdin is not a terminal) [env: NON_INTERACTIVE=1]
+    #[arg(long, global = true)]
+    pub non_interactive: bool,
+
+    /// Run unattended: write a pidfile and log to a rotating file instead of stdout (watch only; does not fork) [env: DAEMON=1]
+    #[arg(long, global = true)]
+    pub daemon: bool,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum Command {
+    /// Fetch the current weather once and print it
+    Now,
+    /// Collect periodically (default)
+    Watch,
+    /// Print the 5 day / 3 hour forecast
+    Forecast,
+    /// Draw charts from stored records
+    History {
+        /// Time window to draw, e.g. 24h, 7d [env: HISTORY_WINDOW]
+        #[arg(short, long)]
+        window: Option<String>,
+    },
+    /// Write stored records to stdout or a file
+    Export {
+        /// Start time (unix seconds, RFC3339 or YYYY-MM-DD)
+        #[arg(long)]
+        from: Option<String>,
+        /// End time (unix seconds, RFC3339 or YYYY-MM-DD)
+        #[arg(long)]
+        to: Option<String>,
+        /// Output file (default: stdout)
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Summarize stored records per day or week (-f table, tsv or markdown)
+    Report {
+        /// daily or weekly [env: REPORT_PERIOD]
+        #[arg(long)]
+        period: Option<String>,
+        /// Start time (unix seconds, RFC3339 or YYYY-MM-DD)
+        #[arg(long)]
+        from: Option<String>,
+        /// End time (unix seconds, RFC3339 or YYYY-MM-DD)
+        #[arg(long)]
+        to: Option<String>,
+    },
+    /// Import OpenWeather History Bulk files (JSON or CSV) into the stored records
+    Import {
+        /// History Bulk files (.json or .csv)
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Location name for the imported records (default: city_name in the file)
+        #[arg(long)]
+        name: Option<String>,
+        /// Units of the values in the files: metric, imperial or standard
+        #[arg(long, default_value = "metric")]
+        data_units: String,
+    },
+    /// Show the collector state: schedule, last fetch and failures per location [env: STATE_FILE]
+    Status,
+    /// Show the effective configuration
+    Config {
+        /// Re-enter the API key, URL and location
+        #[arg(long)]
+        setup: bool,
+    },
+}
+
+impl Cli {
+    // 入力を求めてよいか（cron/systemd/コンテナなど標準入力が端末でない場合や、デーモンの場合は求めない）
+    pub fn is_interactive(&self) -> bool {
+        !self.non_interactive
+            && env_or("NON_INTERACTIVE", "0") != "1"
+            && env_or("DAEMON", "0") != "1"
+            && termion::is_tty(&std::io::stdin())
+    }
+
+    // 指定されたオプションで設定値を上書きする（.envを読み込んだ後に呼ぶ）
+    pub fn apply(&self, values: &mut Values) {
+        let runs = self.runs.map(|v| v.to_string());
+        let overrides = [
+            ("LOCATION_NAME", &self.location),
+            ("WEATHER_UNITS", &self.units),
+            ("WEATHER_LANG", &self.lang),
+            ("DISPLAY_MODE", &self.format),
+            ("INTERVAL", &self.interval),
+            ("DURATION", &self.duration),
+            ("SCHEDULE", &self.cron),
+            ("UNTIL", &self.until),
+            ("RUNS", &runs),
+        ];
+        for (key, value) in overrides {
+            if let Some(value) = value {
+                values.set(key, value);
+            }
+        }
+        if let Some(Command::History {
+            window: Some(window),
+        }) = &self.command
+        {
+            values.set("HISTORY_WINDOW", window);
+        }
+        if self.daemon {
+            values.set("DAEMON", "1");
+        }
+    }
+}
@@ -0,0 +1,1038 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use serde::Deserialize;
+
+use crate::alerts;
+use crate::daemon;
+use crate::jobs;
+use crate::report;
+use crate::schedule;
+use crate::store;
+use crate::template;
+
+// 設定ファイル（TOML）
+// 各項目は対応する環境変数に読み替える。優先順位は
+// コマンドライン > 環境変数 > 設定ファイル > .env > デフォルト値
+//
+// 例:
+//   default_profile = "home"
+//   [api]
+//   units = "metric"
+//   [locations]
+//   names = ["osaka", "kyoto"]
+//   [schedule]
+//   interval = "30m"
+//   [[jobs]]
+//   endpoint = "air_quality"
+//   every = "1h"
+//   [profiles.travel.locations]
+//   names = ["sapporo"]
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    // --profileを指定しなかった場合に使うプロファイル
+    default_profile: Option<String>,
+    #[serde(default)]
+    api: ApiSection,
+    #[serde(default)]
+    locations: LocationsSection,
+    #[serde(default)]
+    schedule: ScheduleSection,
+    #[serde(default)]
+    outputs: OutputsSection,
+    #[serde(default)]
+    alerts: AlertsSection,
+    #[serde(default)]
+    daemon: DaemonSection,
+    #[serde(default)]
+    logging: LoggingSection,
+    #[serde(default)]
+    backfill: BackfillSection,
+    #[serde(default)]
+    report: ReportSection,
+    // エンドポイントごとの取得ジョブ
+    jobs: Option<Vec<JobSection>>,
+    // 名前付きのプロファイル。指定した項目だけ上書きする
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(default)]
+    api: ApiSection,
+    #[serde(default)]
+    locations: LocationsSection,
+    #[serde(default)]
+    schedule: ScheduleSection,
+    #[serde(default)]
+    outputs: OutputsSection,
+    #[serde(default)]
+    alerts: AlertsSection,
+    #[serde(default)]
+    daemon: DaemonSection,
+    #[serde(default)]
+    logging: LoggingSection,
+    #[serde(default)]
+    backfill: BackfillSection,
+    #[serde(default)]
+    report: ReportSection,
+    jobs: Option<Vec<JobSection>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ApiSection {
+    url: Option<String>,
+    forecast_url: Option<String>,
+    geocoding_url: Option<String>,
+    onecall_url: Option<String>,
+    history_url: Option<String>,
+    // APIキーは設定ファイルに直接書かず、キーファイルまたはコマンドで指定する
+    key_file: Option<String>,
+    key_command: Option<String>,
+    units: Option<String>,
+    lang: Option<String>,
+    // 1分あたりの呼び出し回数の上限（0の場合は制限しない）
+    rate_limit: Option<u32>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct LocationsSection {
+    names: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ScheduleSection {
+    interval: Option<String>,
+    // 一定間隔の場合に時計の区切り（毎時0分など）に揃えるか
+    align: Option<bool>,
+    // cron式（指定した場合はintervalより優先する）
+    cron: Option<String>,
+    duration: Option<String>,
+    until: Option<String>,
+    runs: Option<u64>,
+    // 終了のシグナルを受けてから取得や通知の完了を待つ時間
+    shutdown_timeout: Option<String>,
+    // 再起動した場合に前回の開始時刻と実行回数を引き継ぐか
+    resume: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct OutputsSection {
+    tsv: Option<bool>,
+    display: Option<String>,
+    template: Option<String>,
+    history_window: Option<String>,
+    chart_style: Option<String>,
+    icon_mode: Option<String>,
+    icon_theme_dir: Option<String>,
+    server_addr: Option<String>,
+    #[serde(default)]
+    mqtt: MqttSection,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct MqttSection {
+    host: Option<String>,
+    port: Option<u16>,
+    tls: Option<bool>,
+    ca_file: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    client_id: Option<String>,
+    topic: Option<String>,
+    discovery_prefix: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct AlertsSection {
+    rules: Option<Vec<String>>,
+    #[serde(default)]
+    notify: NotifySection,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct NotifySection {
+    webhook_url: Option<String>,
+    slack_url: Option<String>,
+    exec: Option<String>,
+    retry: Option<u32>,
+    subject: Option<String>,
+    template: Option<String>,
+    #[serde(default)]
+    smtp: SmtpSection,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct SmtpSection {
+    host: Option<String>,
+    port: Option<u16>,
+    tls: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    from: Option<String>,
+    to: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct DaemonSection {
+    enabled: Option<bool>,
+    pid_file: Option<String>,
+    log_file: Option<String>,
+    // 「10M」などのサイズ
+    log_max_size: Option<String>,
+    log_keep: Option<u32>,
+    // 取得の状態を保存するファイル
+    state_file: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct LoggingSection {
+    // error / warn / info / debug / trace
+    level: Option<String>,
+    // text / json
+    format: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct BackfillSection {
+    enabled: Option<bool>,
+    // onecall / history
+    api: Option<String>,
+    // 欠損を探す期間
+    window: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ReportSection {
+    // 集計を通知する時刻（「07:00」など）
+    time: Option<String>,
+    // daily / weekly
+    period: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct JobSection {
+    name: Option<String>,
+    endpoint: String,
+    locations: Option<Vec<String>>,
+    every: Option<String>,
+    cron: Option<String>,
+    once: Option<bool>,
+    runs: Option<u64>,
+    sinks: Option<Vec<String>>,
+}
+
+impl JobSection {
+    // JOBSの書式に変換する
+    fn spec(&self) -> String {
+        let mut spec = String::new();
+        if let Some(name) = &self.name {
+            spec.push_str(&format!("{}: ", name));
+        }
+        spec.push_str(&self.endpoint);
+        if let Some(locations) = &self.locations {
+            spec.push_str(&format!(" for {}", locations.join(",")));
+        }
+        if let Some(every) = &self.every {
+            spec.push_str(&format!(" every {}", every));
+        }
+        if let Some(cron) = &self.cron {
+            spec.push_str(&format!(" cron({})", cron));
+        }
+        if self.once == Some(true) {
+            spec.push_str(" once");
+        }
+        if let Some(runs) = self.runs {
+            spec.push_str(&format!(" runs {}", runs));
+        }
+        if let Some(sinks) = &self.sinks {
+            spec.push_str(&format!(" to {}", sinks.join(",")));
+        }
+        spec
+    }
+}
+
+// 設定値（環境変数名と値）
+// 環境変数・設定ファイル・.env・コマンドラインをまとめたもので、プロセスの環境変数は書き換えない
+#[derive(Clone, Default)]
+pub struct Values(HashMap<String, String>);
+
+impl Values {
+    // 起動時に設定されていた環境変数から作成する
+    pub fn from_process() -> Self {
+        Values(
+            env::vars_os()
+                .filter_map(|(key, value)| {
+                    Some((key.into_string().ok()?, value.into_string().ok()?))
+                })
+                .collect(),
+        )
+    }
+
+    // 値を取得する。未設定または空の場合はNone
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .get(key)
+            .map(|v| v.as_str())
+            .filter(|v| !v.is_empty())
+    }
+
+    // 値を取得する。未設定または空の場合はデフォルト値を返す
+    pub fn or(&self, key: &str, default: &str) -> String {
+        String::from(self.get(key).unwrap_or(default))
+    }
+
+    // 空でない値の一覧
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .filter(|(_, value)| !value.is_empty())
+    }
+
+    // 設定されているか（空の値も含む）
+    pub fn contains(&self, key: &str) -> bool {
+        self.0.contains_key(key)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.0.insert(key.to_string(), value.to_string());
+    }
+
+    // 設定されていない項目のみ設定する
+    pub fn set_default(&mut self, key: &str, value: &str) {
+        if !self.contains(key) {
+            self.set(key, value);
+        }
+    }
+}
+
+// 現在の設定値（起動時と読み込み直しが成功した時に差し替える）
+static CURRENT: RwLock<Option<Arc<Values>>> = RwLock::new(None);
+
+// 現在の設定値。読み込む前（テストなど）は空の設定を返す
+pub fn current() -> Arc<Values> {
+    CURRENT
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .unwrap_or_default()
+}
+
+// 設定値を差し替える
+pub fn replace(values: Values) {
+    *CURRENT.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(values));
+}
+
+// 読み込んだ設定ファイル
+pub struct LoadedConfig {
+    pub path: PathBuf,
+    pub profile: Option<String>,
+    // 環境変数名と値
+    pub values: Vec<(&'static str, String)>,
+}
+
+impl LoadedConfig {
+    // 環境変数が設定されていない項目のみ設定する（.envより先に呼ぶ）
+    pub fn apply(&self, values: &mut Values) {
+        for (key, value) in &self.values {
+            values.set_default(key, value);
+        }
+    }
+}
+
+// 設定ファイルの場所（$XDG_CONFIG_HOME/openweather-client/config.toml）
+pub fn default_path() -> Option<PathBuf> {
+    let base = match env::var("XDG_CONFIG_HOME") {
+        Ok(v) if !v.is_empty() => PathBuf::from(v),
+        _ => PathBuf::from(env::var("HOME").ok()?).join(".config"),
+    };
+    Some(base.join("openweather-client").join("config.toml"))
+}
+
+// 設定ファイルを読み込む
+// --configで指定したファイルが存在しない場合はエラー、既定の場所にない場合はNoneを返す
+pub fn load(path: Option<&Path>, profile: Option<&str>) -> Result<Option<LoadedConfig>, String> {
+    let path = match path {
+        Some(path) => {
+            if !path.is_file() {
+                return Err(format!("config file not found: {}", path.display()));
+            }
+            path.to_path_buf()
+        }
+        None => match default_path() {
+            Some(path) if path.is_file() => path,
+            _ => return Ok(None),
+        },
+    };
+
+    let text = std::fs::read_to_string(&path)
+        .map_err(|e| format!("{}: cannot read: {}", path.display(), e))?;
+    let file: ConfigFile =
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let base = Profile {
+        api: file.api,
+        locations: file.locations,
+        schedule: file.schedule,
+        outputs: file.outputs,
+        alerts: file.alerts,
+        daemon: file.daemon,
+        logging: file.logging,
+        backfill: file.backfill,
+        report: file.report,
+        jobs: file.jobs,
+    };
+    base.validate("")
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    // プロファイルの項目で上書きする
+    let profile = profile.map(String::from).or(file.default_profile);
+    let mut values = base.values();
+    if let Some(name) = &profile {
+        let selected = match file.profiles.get(name) {
+            Some(v) => v,
+            None => {
+                let names: Vec<&str> = file.profiles.keys().map(|v| v.as_str()).collect();
+                return Err(format!(
+                    "{}: profiles.{} not found (available: {})",
+                    path.display(),
+                    name,
+                    names.join(", ")
+                ));
+            }
+        };
+        selected
+            .validate(&format!("profiles.{}.", name))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        for (key, value) in selected.values() {
+            values.retain(|(k, _)| *k != key);
+            values.push((key, value));
+        }
+    }
+
+    Ok(Some(LoadedConfig {
+        path,
+        profile,
+        values,
+    }))
+}
+
+// 値が候補のいずれかか確認する
+fn check_one_of(value: &str, allowed: &[&str]) -> Result<(), String> {
+    if allowed.contains(&value) {
+        return Ok(());
+    }
+    Err(format!(
+        "invalid value {:?} (expected one of: {})",
+        value,
+        allowed.join(", ")
+    ))
+}
+
+// 0より長い時間か確認する
+fn check_duration(value: &str) -> Result<(), String> {
+    match alerts::parse_duration(value)? {
+        secs if secs > 0 => Ok(()),
+        _ => Err(String::from("must be greater than 0")),
+    }
+}
+
+fn check_number<T: std::str::FromStr>(value: &str) -> Result<(), String> {
+    value
+        .parse::<T>()
+        .map(|_| ())
+        .map_err(|_| format!("invalid number {:?}", value))
+}
+
+// 表示形式（watch、export、reportで使うもの全て）
+const DISPLAY_MODES: [&str; 12] = [
+    "plain",
+    "template",
+    "json",
+    "history",
+    "dashboard",
+    "waybar",
+    "i3bar",
+    "polybar",
+    "tsv",
+    "csv",
+    "table",
+    "markdown",
+];
+
+// 設定項目（環境変数名）ごとに値の妥当性を確認する。確認しない項目はOkを返す
+// 設定ファイル・環境変数・.env・コマンドラインのどこで指定した値にも同じ確認を行う
+fn check_value(key: &str, value: &str) -> Result<(), String> {
+    match key {
+        "WEATHER_UNITS" => check_one_of(value, &["metric", "imperial", "standard"]),
+        "RATE_LIMIT" | "NOTIFY_RETRY" | "LOG_KEEP" => check_number::<u32>(value),
+        "LOCATION_NAME" => match value.split(',').all(|v| v.trim().is_empty()) {
+            true => Err(String::from("no location")),
+            false => Ok(()),
+        },
+        "INTERVAL" | "SHUTDOWN_TIMEOUT" | "HISTORY_WINDOW" | "BACKFILL_WINDOW" => {
+            check_duration(value)
+        }
+        // 0は無期限
+        "DURATION" if value == "0" => Ok(()),
+        "DURATION" => check_duration(value),
+        "SCHEDULE" => schedule::Cron::parse(value).map(|_| ()),
+        "UNTIL" => match store::parse_time(value) {
+            Some(_) => Ok(()),
+            None => Err(format!("invalid time {:?}", value)),
+        },
+        "RUNS" => check_number::<u64>(value),
+        "DISPLAY_MODE" => check_one_of(value, &DISPLAY_MODES),
+        "CHART_STYLE" => check_one_of(value, &["braille", "ascii"]),
+        "ICON_MODE" => check_one_of(value, &["auto", "image", "ascii", "emoji"]),
+        "SERVER_ADDR" => match value.parse::<SocketAddr>() {
+            Ok(_) => Ok(()),
+            Err(_) => Err(format!("invalid address {:?}", value)),
+        },
+        "MQTT_PORT" | "NOTIFY_SMTP_PORT" => check_number::<u16>(value),
+        "ALERT_RULES" => alerts::parse_rules(value).map(|_| ()),
+        "NOTIFY_SMTP_TLS" => check_one_of(value, &["none", "starttls", "tls"]),
+        "NOTIFY_SMTP_TO" => match value.split(',').all(|v| v.trim().is_empty()) {
+            true => Err(String::from("no recipient")),
+            false => Ok(()),
+        },
+        "LOG_MAX_SIZE" => daemon::parse_size(value).map(|_| ()),
+        "LOG_LEVEL" => tracing_subscriber::EnvFilter::try_new(value)
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+        "LOG_FORMAT" => check_one_of(value, &["text", "json"]),
+        "BACKFILL_API" => check_one_of(value, &["onecall", "history"]),
+        "REPORT_PERIOD" => check_one_of(value, &["daily", "weekly"]),
+        "REPORT_TIME" => report::parse_time(value, report::Period::Daily).map(|_| ()),
+        "JOBS" => jobs::parse_jobs(value).map(|_| ()),
+        "OUTPUT_TEMPLATE" | "NOTIFY_SUBJECT" | "NOTIFY_TEMPLATE" => template::check(value),
+        _ => Ok(()),
+    }
+}
+
+// 環境変数名に対応する設定ファイルの項目名
+fn file_key(key: &str) -> &str {
+    match key {
+        "WEATHER_UNITS" => "api.units",
+        "RATE_LIMIT" => "api.rate_limit",
+        "LOCATION_NAME" => "locations.names",
+        "INTERVAL" => "schedule.interval",
+        "SCHEDULE" => "schedule.cron",
+        "DURATION" => "schedule.duration",
+        "UNTIL" => "schedule.until",
+        "RUNS" => "schedule.runs",
+        "SHUTDOWN_TIMEOUT" => "schedule.shutdown_timeout",
+        "DISPLAY_MODE" => "outputs.display",
+        "HISTORY_WINDOW" => "outputs.history_window",
+        "CHART_STYLE" => "outputs.chart_style",
+        "ICON_MODE" => "outputs.icon_mode",
+        "OUTPUT_TEMPLATE" => "outputs.template",
+        "SERVER_ADDR" => "outputs.server_addr",
+        "MQTT_PORT" => "outputs.mqtt.port",
+        "ALERT_RULES" => "alerts.rules",
+        "NOTIFY_RETRY" => "alerts.notify.retry",
+        "NOTIFY_SUBJECT" => "alerts.notify.subject",
+        "NOTIFY_TEMPLATE" => "alerts.notify.template",
+        "NOTIFY_SMTP_PORT" => "alerts.notify.smtp.port",
+        "NOTIFY_SMTP_TLS" => "alerts.notify.smtp.tls",
+        "NOTIFY_SMTP_TO" => "alerts.notify.smtp.to",
+        "LOG_MAX_SIZE" => "daemon.log_max_size",
+        "LOG_KEEP" => "daemon.log_keep",
+        "LOG_LEVEL" => "logging.level",
+        "LOG_FORMAT" => "logging.format",
+        "BACKFILL_API" => "backfill.api",
+        "BACKFILL_WINDOW" => "backfill.window",
+        "REPORT_PERIOD" => "report.period",
+        "REPORT_TIME" => "report.time",
+        "JOBS" => "jobs",
+        _ => key,
+    }
+}
+
+// まとめた設定値を確認する。不正な値を全て環境変数名付きで返す
+pub fn validate(values: &Values) -> Result<(), String> {
+    let mut errors: Vec<String> = values
+        .iter()
+        .filter_map(|(key, value)| {
+            check_value(key, value)
+                .err()
+                .map(|e| format!("{}: {}", key, e))
+        })
+        .collect();
+    if errors.is_empty() {
+        return Ok(());
+    }
+    errors.sort();
+    Err(errors.join("\n  "))
+}
+
+impl Profile {
+    // 値の妥当性を確認する。エラーには問題のある項目名を含める
+    // 一覧で指定する項目は何番目かを含め、それ以外は環境変数と同じ確認を行う
+    fn validate(&self, prefix: &str) -> Result<(), String> {
+        // 設定ファイルでは常に取得を表示する形式のみ指定できる
+        if let Some(display) = &self.outputs.display {
+            check_one_of(display, &DISPLAY_MODES[..8])
+                .map_err(|e| format!("{}outputs.display: {}", prefix, e))?;
+        }
+        if let Some(rules) = &self.alerts.rules {
+            for (i, rule) in rules.iter().enumerate() {
+                alerts::parse_rules(rule)
+                    .map_err(|e| format!("{}alerts.rules[{}]: {}", prefix, i, e))?;
+            }
+        }
+        if let Some(to) = &self.alerts.notify.smtp.to {
+            if to.iter().all(|v| v.trim().is_empty()) {
+                return Err(format!("{}alerts.notify.smtp.to: no recipient", prefix));
+            }
+        }
+        if let Some(list) = &self.jobs {
+            for (i, job) in list.iter().enumerate() {
+                if job
+                    .locations
+                    .iter()
+                    .flatten()
+                    .any(|v| v.contains(',') || v.contains(char::is_whitespace))
+                {
+                    return Err(format!(
+                        "{}jobs[{}].locations: names cannot contain \",\" or spaces",
+                        prefix, i
+                    ));
+                }
+                jobs::parse_jobs(&job.spec())
+                    .map_err(|e| format!("{}jobs[{}]: {}", prefix, i, e))?;
+            }
+        }
+        for (key, value) in self.values().into_iter().filter(|(_, v)| !v.is_empty()) {
+            check_value(key, &value).map_err(|e| format!("{}{}: {}", prefix, file_key(key), e))?;
+        }
+        Ok(())
+    }
+
+    // 設定されている項目を環境変数名と値の組にする
+    fn values(&self) -> Vec<(&'static str, String)> {
+        let mut values = Vec::new();
+        let mut push = |key: &'static str, value: Option<String>| {
+            if let Some(value) = value {
+                values.push((key, value));
+            }
+        };
+        let flag = |v: Option<bool>| v.map(|v| String::from(if v { "1" } else { "0" }));
+
+        let api = &self.api;
+        push("OPEN_WEATHER_URL", api.url.clone());
+        push("FORECAST_URL", api.forecast_url.clone());
+        push("GEOCODING_URL", api.geocoding_url.clone());
+        push("ONECALL_URL", api.onecall_url.clone());
+        push("HISTORY_URL", api.history_url.clone());
+        push("API_KEY_FILE", api.key_file.clone());
+        push("API_KEY_COMMAND", api.key_command.clone());
+        push("WEATHER_UNITS", api.units.clone());
+        push("WEATHER_LANG", api.lang.clone());
+        push("RATE_LIMIT", api.rate_limit.map(|v| v.to_string()));
+
+        push(
+            "LOCATION_NAME",
+            self.locations.names.as_ref().map(|v| v.join(",")),
+        );
+
+        push("INTERVAL", self.schedule.interval.clone());
+        push("ALIGN", flag(self.schedule.align));
+        push("SCHEDULE", self.schedule.cron.clone());
+        push("DURATION", self.schedule.duration.clone());
+        push("UNTIL", self.schedule.until.clone());
+        push("RUNS", self.schedule.runs.map(|v| v.to_string()));
+        push("SHUTDOWN_TIMEOUT", self.schedule.shutdown_timeout.clone());
+        push("RESUME", flag(self.schedule.resume));
+
+        let outputs = &self.outputs;
+        push("TSV_OUT", flag(outputs.tsv));
+        push("DISPLAY_MODE", outputs.display.clone());
+        push("OUTPUT_TEMPLATE", outputs.template.clone());
+        push("HISTORY_WINDOW", outputs.history_window.clone());
+        push("CHART_STYLE", outputs.chart_style.clone());
+        push("ICON_MODE", outputs.icon_mode.clone());
+        push("ICON_THEME_DIR", outputs.icon_theme_dir.clone());
+        push("SERVER_ADDR", outputs.server_addr.clone());
+
+        let mqtt = &outputs.mqtt;
+        push("MQTT_HOST", mqtt.host.clone());
+        push("MQTT_PORT", mqtt.port.map(|v| v.to_string()));
+        push("MQTT_TLS", flag(mqtt.tls));
+        push("MQTT_CA_FILE", mqtt.ca_file.clone());
+        push("MQTT_USERNAME", mqtt.username.clone());
+        push("MQTT_PASSWORD", mqtt.password.clone());
+        push("MQTT_CLIENT_ID", mqtt.client_id.clone());
+        push("MQTT_TOPIC", mqtt.topic.clone());
+        push("MQTT_DISCOVERY_PREFIX", mqtt.discovery_prefix.clone());
+
+        push(
+            "ALERT_RULES",
+            self.alerts.rules.as_ref().map(|v| v.join("; ")),
+        );
+        let notify = &self.alerts.notify;
+        push("NOTIFY_WEBHOOK_URL", notify.webhook_url.clone());
+        push("NOTIFY_SLACK_URL", notify.slack_url.clone());
+        push("NOTIFY_EXEC", notify.exec.clone());
+        push("NOTIFY_RETRY", notify.retry.map(|v| v.to_string()));
+        push("NOTIFY_SUBJECT", notify.subject.clone());
+        push("NOTIFY_TEMPLATE", notify.template.clone());
+
+        let smtp = &notify.smtp;
+        push("NOTIFY_SMTP_HOST", smtp.host.clone());
+        push("NOTIFY_SMTP_PORT", smtp.port.map(|v| v.to_string()));
+        push("NOTIFY_SMTP_TLS", smtp.tls.clone());
+        push("NOTIFY_SMTP_USERNAME", smtp.username.clone());
+        push("NOTIFY_SMTP_PASSWORD", smtp.password.clone());
+        push("NOTIFY_SMTP_FROM", smtp.from.clone());
+        push("NOTIFY_SMTP_TO", smtp.to.as_ref().map(|v| v.join(",")));
+
+        let daemon = &self.daemon;
+        push("DAEMON", flag(daemon.enabled));
+        push("PID_FILE", daemon.pid_file.clone());
+        push("LOG_FILE", daemon.log_file.clone());
+        push("LOG_MAX_SIZE", daemon.log_max_size.clone());
+        push("LOG_KEEP", daemon.log_keep.map(|v| v.to_string()));
+        push("STATE_FILE", daemon.state_file.clone());
+        push("LOG_LEVEL", self.logging.level.clone());
+        push("LOG_FORMAT", self.logging.format.clone());
+
+        let backfill = &self.backfill;
+        push("BACKFILL", flag(backfill.enabled));
+        push("BACKFILL_API", backfill.api.clone());
+        push("BACKFILL_WINDOW", backfill.window.clone());
+        push("REPORT_TIME", self.report.time.clone());
+        push("REPORT_PERIOD", self.report.period.clone());
+
+        push(
+            "JOBS",
+            self.jobs.as_ref().map(|v| {
+                v.iter()
+                    .map(|job| job.spec())
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            }),
+        );
+
+        values
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 設定ファイルを一時ディレクトリに書いて読み込む
+    fn load_str(text: &str, profile: Option<&str>) -> Result<LoadedConfig, String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, text).unwrap();
+        load(Some(&path), profile)
+            .map(|v| v.unwrap())
+            // エラーの先頭のファイル名を取り除く
+            .map_err(|e| e.replacen(&format!("{}: ", path.display()), "", 1))
+    }
+
+    fn value<'a>(config: &'a LoadedConfig, key: &str) -> Option<&'a str> {
+        config
+            .values
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn error(text: &str) -> String {
+        match load_str(text, None) {
+            Ok(_) => panic!("expected error: {}", text),
+            Err(e) => e,
+        }
+    }
+
+    const CONFIG: &str = r#"
+default_profile = "home"
+[api]
+units = "metric"
+[locations]
+names = ["osaka", "kyoto"]
+[schedule]
+interval = "30m"
+align = true
+[outputs]
+tsv = true
+[[jobs]]
+endpoint = "air_quality"
+every = "1h"
+[profiles.home.schedule]
+interval = "10m"
+[profiles.travel.api]
+units = "imperial"
+[profiles.travel.locations]
+names = ["sapporo"]
+"#;
+
+    #[test]
+    fn values_keep_earlier_sources() {
+        let mut values = Values::default();
+        values.set("INTERVAL", "10m");
+        values.set("UNTIL", "");
+        let config = load_str("[schedule]\ninterval = \"30m\"\nruns = 3\n", None).unwrap();
+        config.apply(&mut values);
+        // 環境変数で設定済みの項目（空の値も含む）は上書きしない
+        assert_eq!(values.get("INTERVAL"), Some("10m"));
+        assert_eq!(values.get("RUNS"), Some("3"));
+        values.set_default("UNTIL", "2030-01-01");
+        assert_eq!(values.get("UNTIL"), None);
+        assert_eq!(values.or("UNTIL", "-"), "-");
+    }
+
+    #[test]
+    fn maps_sections_to_env_names() {
+        let config = load_str("[api]\nunits = \"metric\"\n[outputs]\ntsv = false\n", None).unwrap();
+        assert_eq!(config.profile, None);
+        assert_eq!(value(&config, "WEATHER_UNITS"), Some("metric"));
+        assert_eq!(value(&config, "TSV_OUT"), Some("0"));
+        assert_eq!(value(&config, "LOCATION_NAME"), None);
+    }
+
+    #[test]
+    fn default_profile_overrides_base() {
+        let config = load_str(CONFIG, None).unwrap();
+        assert_eq!(config.profile.as_deref(), Some("home"));
+        assert_eq!(value(&config, "INTERVAL"), Some("10m"));
+        assert_eq!(value(&config, "ALIGN"), Some("1"));
+        assert_eq!(value(&config, "LOCATION_NAME"), Some("osaka,kyoto"));
+        assert_eq!(value(&config, "JOBS"), Some("air_quality every 1h"));
+    }
+
+    #[test]
+    fn selected_profile_overrides_default_profile() {
+        let config = load_str(CONFIG, Some("travel")).unwrap();
+        assert_eq!(config.profile.as_deref(), Some("travel"));
+        assert_eq!(value(&config, "WEATHER_UNITS"), Some("imperial"));
+        assert_eq!(value(&config, "LOCATION_NAME"), Some("sapporo"));
+        // プロファイルにない項目は共通の設定を使う
+        assert_eq!(value(&config, "INTERVAL"), Some("30m"));
+        assert_eq!(value(&config, "TSV_OUT"), Some("1"));
+        // 上書きした項目は1つだけになる
+        assert_eq!(
+            config
+                .values
+                .iter()
+                .filter(|(k, _)| *k == "WEATHER_UNITS")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn unknown_profile() {
+        assert_eq!(
+            load_str(CONFIG, Some("work")).err().unwrap(),
+            "profiles.work not found (available: home, travel)"
+        );
+    }
+
+    #[test]
+    fn missing_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing.toml");
+        assert_eq!(
+            load(Some(&path), None).err().unwrap(),
+            format!("config file not found: {}", path.display())
+        );
+    }
+
+    #[test]
+    fn unknown_key() {
+        assert!(error("[api]\nunit = \"metric\"\n").contains("unknown field `unit`"));
+    }
+
+    #[test]
+    fn profile_error_has_profile_prefix() {
+        let text = "[profiles.travel.schedule]\ninterval = \"soon\"\n";
+        // 選択していないプロファイルは確認しない
+        assert!(load_str(text, None).is_ok());
+        assert_eq!(
+            load_str(text, Some("travel"))
+                .err()
+                .unwrap()
+                .split(':')
+                .next()
+                .unwrap(),
+            "profiles.travel.schedule.interval"
+        );
+    }
+
+    #[test]
+    fn invalid_values() {
+        // 設定ファイルの内容と、エラーの先頭
+        let cases = [
+            (
+                "[api]\nunits = \"kelvin\"\n",
+                "api.units: invalid value \"kelvin\" (expected one of: metric, imperial, standard)",
+            ),
+            (
+                "[locations]\nnames = [\"\", \" \"]\n",
+                "locations.names: no location",
+            ),
+            ("[schedule]\ninterval = \"soon\"\n", "schedule.interval: "),
+            (
+                "[schedule]\ninterval = \"0s\"\n",
+                "schedule.interval: must be greater than 0",
+            ),
+            ("[schedule]\nduration = \"1x\"\n", "schedule.duration: "),
+            (
+                "[schedule]\nshutdown_timeout = \"x\"\n",
+                "schedule.shutdown_timeout: ",
+            ),
+            ("[schedule]\ncron = \"61 * * * *\"\n", "schedule.cron: "),
+            (
+                "[schedule]\nuntil = \"tomorrow\"\n",
+                "schedule.until: invalid time \"tomorrow\"",
+            ),
+            (
+                "[outputs]\ndisplay = \"html\"\n",
+                "outputs.display: invalid value \"html\"",
+            ),
+            // エクスポート用の形式は常に取得する設定には使えない
+            (
+                "[outputs]\ndisplay = \"csv\"\n",
+                "outputs.display: invalid value \"csv\"",
+            ),
+            (
+                "[outputs]\nhistory_window = \"long\"\n",
+                "outputs.history_window: ",
+            ),
+            (
+                "[outputs]\nchart_style = \"bars\"\n",
+                "outputs.chart_style: invalid value \"bars\"",
+            ),
+            (
+                "[outputs]\nicon_mode = \"svg\"\n",
+                "outputs.icon_mode: invalid value \"svg\"",
+            ),
+            (
+                "[outputs]\nserver_addr = \"localhost\"\n",
+                "outputs.server_addr: invalid address \"localhost\"",
+            ),
+            (
+                "[alerts]\nrules = [\"temp > 30\", \"nonsense\"]\n",
+                "alerts.rules[1]: ",
+            ),
+            (
+                "[outputs]\ntemplate = \"{name} {temp:.1°C\"\n",
+                "outputs.template: unclosed \"{\" at 7",
+            ),
+            (
+                "[alerts.notify]\ntemplate = \"{location}: {rule\"\n",
+                "alerts.notify.template: ",
+            ),
+            (
+                "[alerts.notify.smtp]\ntls = \"ssl\"\n",
+                "alerts.notify.smtp.tls: invalid value \"ssl\"",
+            ),
+            (
+                "[alerts.notify.smtp]\nhost = \"localhost\"\nto = [\"\"]\n",
+                "alerts.notify.smtp.to: no recipient",
+            ),
+            ("[daemon]\nlog_max_size = \"big\"\n", "daemon.log_max_size: "),
+            ("[logging]\nlevel = \"info,=\"\n", "logging.level: "),
+            (
+                "[logging]\nformat = \"xml\"\n",
+                "logging.format: invalid value \"xml\"",
+            ),
+            (
+                "[backfill]\napi = \"archive\"\n",
+                "backfill.api: invalid value \"archive\"",
+            ),
+            ("[backfill]\nwindow = \"a week\"\n", "backfill.window: "),
+            (
+                "[report]\nperiod = \"monthly\"\n",
+                "report.period: invalid value \"monthly\"",
+            ),
+            ("[report]\ntime = \"25:00\"\n", "report.time: "),
+            (
+                "[[jobs]]\nendpoint = \"weather\"\nlocations = [\"new york\"]\n",
+                "jobs[0].locations: names cannot contain \",\" or spaces",
+            ),
+            (
+                "[[jobs]]\nendpoint = \"weather\"\nonce = true\n[[jobs]]\nendpoint = \"weather\"\n",
+                "jobs[1]: job \"weather\": missing schedule (every <interval>, cron(...) or once)",
+            ),
+            // 個別には正しくても、まとめると名前が重複する
+            (
+                "[[jobs]]\nendpoint = \"weather\"\nonce = true\n[[jobs]]\nendpoint = \"weather\"\nevery = \"1h\"\n",
+                "jobs: job \"weather every 1h\": duplicate name \"weather\"",
+            ),
+        ];
+        for (text, expected) in cases {
+            let error = error(text);
+            assert!(
+                error.starts_with(expected),
+                "{:?}: expected {:?}, got {:?}",
+                text,
+                expected,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn accepted_values() {
+        let cases = [
+            // 0は無期限
+            "[schedule]\nduration = \"0\"\n",
+            // 空の場合はサーバーを起動しない
+            "[outputs]\nserver_addr = \"\"\n",
+            "[outputs]\nserver_addr = \"127.0.0.1:8080\"\n",
+            "[report]\ntime = \"07:00\"\nperiod = \"weekly\"\n",
+        ];
+        for text in cases {
+            assert!(load_str(text, None).is_ok(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn validate_merged_values() {
+        let mut values = Values::default();
+        values.set("INTERVAL", "30m");
+        values.set("DISPLAY_MODE", "csv");
+        values.set("SERVER_ADDR", "");
+        values.set("PATH", "/usr/bin");
+        assert!(validate(&values).is_ok());
+
+        // 環境変数や.envの値も設定ファイルと同じ確認を行い、全ての誤りを返す
+        values.set("ALERT_RULES", "temp >> 30");
+        values.set("MQTT_PORT", "99999");
+        values.set("ICON_MODE", "svg");
+        assert_eq!(
+            validate(&values).err().unwrap(),
+            [
+                "ALERT_RULES: unknown operator: >>",
+                "ICON_MODE: invalid value \"svg\" (expected one of: auto, image, ascii, emoji)",
+                "MQTT_PORT: invalid number \"99999\"",
+            ]
+            .join("\n  ")
+        );
+    }
+}
@@ -0,0 +1,331 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use crate::env_or;
+use crate::store;
+
+// 出力先のログファイル（デーモンの場合のみ）
+static LOG_FILE: OnceLock<Mutex<LogFile>> = OnceLock::new();
+
+// デーモンとして動作するか（--daemonまたはDAEMON=1）
+pub fn is_enabled() -> bool {
+    env_or("DAEMON", "0") == "1"
+}
+
+// 「10M」「512K」などのサイズをバイト数にする
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => value.split_at(i),
+        None => (value, ""),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid size: {:?}", value))?;
+    let unit = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        _ => return Err(format!("invalid size unit: {:?}", value)),
+    };
+    number
+        .checked_mul(unit)
+        .ok_or_else(|| format!("size too large: {:?}", value))
+}
+
+// サイズが上限を超えたら「.1」「.2」…に移して新しく作るログファイル
+struct LogFile {
+    path: PathBuf,
+    // 0の場合は切り替えない
+    max_size: u64,
+    // 残す古いファイルの数
+    keep: u32,
+}
+
+impl LogFile {
+    // ファイルを開き、標準出力と標準エラー出力をつなぎ替える
+    fn open(&self) -> io::Result<()> {
+        io::stdout().flush()?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        for target in [libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+            if unsafe { libc::dup2(file.as_raw_fd(), target) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    fn rotated(&self, index: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        PathBuf::from(path)
+    }
+
+    // 上限を超えていればファイルを移す。移した場合はtrue（開き直すのは呼び出し側）
+    fn rotate_if_needed(&self) -> io::Result<bool> {
+        let size = fs::metadata(&self.path).map(|v| v.len()).unwrap_or(0);
+        if self.max_size == 0 || size < self.max_size {
+            return Ok(false);
+        }
+        io::stdout().flush()?;
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for i in (1..self.keep).rev() {
+                let _ = fs::rename(self.rotated(i), self.rotated(i + 1));
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        Ok(true)
+    }
+}
+
+// 取得ごとに呼び出し、ログファイルが上限を超えていれば切り替える
+pub fn rotate_log() {
+    if let Some(log_file) = LOG_FILE.get() {
+        let log_file = log_file.lock().unwrap();
+        let rotated = log_file.rotate_if_needed().and_then(|rotated| {
+            if rotated {
+                log_file.open()?;
+            }
+            Ok(())
+        });
+        if let Err(e) = rotated {
+            tracing::error!(error = %e, "log rotate failed");
+        }
+    }
+}
+
+// 動作中のデーモン。終了時（drop）にpidファイルを削除する
+// pidファイルは開いたままロックしておき、同時に起動したプロセスを開始させない
+pub struct Daemon {
+    pid_file: PathBuf,
+    _lock: fs::File,
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.pid_file);
+    }
+}
+
+// プロセスが動作しているか
+// 権限がなくシグナルを送れない場合（EPERM）も、プロセスは存在するので動作中とする
+pub fn is_running(pid: i32) -> bool {
+    if pid <= 0 {
+        return false;
+    }
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return true;
+    }
+    io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+// ログファイルの場所（LOG_FILE）
+pub fn log_path() -> PathBuf {
+    PathBuf::from(env_or(
+        "LOG_FILE",
+        &format!("{}/openweather-client.log", store::LOG_DIR),
+    ))
+}
+
+// デーモンとして開始する。forkはしないので、systemdやnohupなどから起動する
+// PID_FILEにプロセスIDを書き込み、以降の出力はLOG_FILEに書く
+// （LOG_MAX_SIZEを超えたら切り替え、古いファイルをLOG_KEEP個まで残す）
+pub fn start() -> Result<Daemon, String> {
+    let pid_file = PathBuf::from(env_or(
+        "PID_FILE",
+        &format!("{}/openweather-client.pid", store::LOG_DIR),
+    ));
+    let log_path = log_path();
+    let max_size =
+        parse_size(&env_or("LOG_MAX_SIZE", "10M")).map_err(|e| format!("LOG_MAX_SIZE: {}", e))?;
+    let keep = env_or("LOG_KEEP", "5")
+        .parse::<u32>()
+        .map_err(|_| String::from("LOG_KEEP: invalid number"))?;
+
+    // 既に動作中の場合は開始しない（残っているpidファイルは上書きする）
+    let lock = lock_pid_file(&pid_file)?;
+    let daemon = Daemon {
+        pid_file,
+        _lock: lock,
+    };
+
+    let log_file = LogFile {
+        path: log_path,
+        max_size,
+        keep,
+    };
+    log_file
+        .open()
+        .map_err(|e| format!("LOG_FILE {}: {}", log_file.path.display(), e))?;
+    let _ = LOG_FILE.set(Mutex::new(log_file));
+
+    Ok(daemon)
+}
+
+// pidファイルをロックしてプロセスIDを書き込む
+// ロックの確認と取得を1回で行うため、同時に起動しても片方だけが開始する
+fn lock_pid_file(path: &Path) -> Result<fs::File, String> {
+    let error = |e: io::Error| format!("PID_FILE {}: {}", path.display(), e);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(error)?;
+    }
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .map_err(error)?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } < 0 {
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::WouldBlock {
+            return Err(error(e));
+        }
+        let pid = fs::read_to_string(path).unwrap_or_default();
+        return Err(format!(
+            "already running (pid {}, {})",
+            pid.trim(),
+            path.display()
+        ));
+    }
+    file.set_len(0).map_err(error)?;
+    writeln!(file, "{}", std::process::id()).map_err(error)?;
+    file.flush().map_err(error)?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_sizes() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("10B"), Ok(10));
+        assert_eq!(parse_size("512K"), Ok(512 << 10));
+        assert_eq!(parse_size(" 10m "), Ok(10 << 20));
+        assert_eq!(parse_size("2GB"), Ok(2 << 30));
+        assert_eq!(parse_size("0"), Ok(0));
+    }
+
+    #[test]
+    fn parse_size_errors() {
+        assert_eq!(parse_size(""), Err(String::from("invalid size: \"\"")));
+        assert_eq!(parse_size("M"), Err(String::from("invalid size: \"M\"")));
+        assert_eq!(
+            parse_size("10T"),
+            Err(String::from("invalid size unit: \"10T\""))
+        );
+        assert_eq!(
+            parse_size("-1K"),
+            Err(String::from("invalid size: \"-1K\""))
+        );
+        // 桁あふれ
+        assert_eq!(
+            parse_size("99999999999999999999"),
+            Err(String::from("invalid size: \"99999999999999999999\""))
+        );
+        assert_eq!(
+            parse_size("17179869184G"),
+            Err(String::from("size too large: \"17179869184G\""))
+        );
+    }
+
+    fn log_file(dir: &Path, max_size: u64, keep: u32) -> LogFile {
+        LogFile {
+            path: dir.join("app.log"),
+            max_size,
+            keep,
+        }
+    }
+
+    #[test]
+    fn rotate_keeps_old_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = log_file(dir.path(), 10, 2);
+
+        // ファイルがない、または上限未満の場合は何もしない
+        assert!(!log.rotate_if_needed().unwrap());
+        fs::write(&log.path, "123456789").unwrap();
+        assert!(!log.rotate_if_needed().unwrap());
+
+        fs::write(&log.path, "first log!").unwrap();
+        assert!(log.rotate_if_needed().unwrap());
+        assert!(!log.path.exists());
+        assert_eq!(fs::read_to_string(log.rotated(1)).unwrap(), "first log!");
+
+        fs::write(&log.path, "second log").unwrap();
+        assert!(log.rotate_if_needed().unwrap());
+        assert_eq!(fs::read_to_string(log.rotated(1)).unwrap(), "second log");
+        assert_eq!(fs::read_to_string(log.rotated(2)).unwrap(), "first log!");
+
+        // LOG_KEEPを超えた古いファイルは消える
+        fs::write(&log.path, "third log!").unwrap();
+        assert!(log.rotate_if_needed().unwrap());
+        assert_eq!(fs::read_to_string(log.rotated(1)).unwrap(), "third log!");
+        assert_eq!(fs::read_to_string(log.rotated(2)).unwrap(), "second log");
+        assert!(!log.rotated(3).exists());
+    }
+
+    #[test]
+    fn rotate_without_keep_or_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = log_file(dir.path(), 10, 0);
+        fs::write(&log.path, "0123456789").unwrap();
+        assert!(log.rotate_if_needed().unwrap());
+        assert!(!log.path.exists());
+        assert!(!log.rotated(1).exists());
+
+        // 0の場合は切り替えない
+        let log = log_file(dir.path(), 0, 5);
+        fs::write(&log.path, "0123456789").unwrap();
+        assert!(!log.rotate_if_needed().unwrap());
+        assert!(log.path.exists());
+    }
+
+    #[test]
+    fn running_process() {
+        assert!(is_running(std::process::id() as i32));
+        assert!(!is_running(0));
+        assert!(!is_running(-1));
+        assert!(!is_running(i32::MAX));
+        // initは常に存在する（root以外で実行した場合はEPERMになる）
+        assert!(is_running(1));
+    }
+
+    #[test]
+    fn pid_file_is_locked_while_running() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run").join("app.pid");
+        // 前回のプロセスが残したpidファイルは上書きする
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "999999999\n").unwrap();
+
+        let lock = lock_pid_file(&path).unwrap();
+        let pid = format!("{}\n", std::process::id());
+        assert_eq!(fs::read_to_string(&path).unwrap(), pid);
+
+        // ロックしている間は開始できない
+        assert_eq!(
+            lock_pid_file(&path).err().unwrap(),
+            format!(
+                "already running (pid {}, {})",
+                std::process::id(),
+                path.display()
+            )
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), pid);
+
+        drop(lock);
+        assert!(lock_pid_file(&path).is_ok());
+    }
+}
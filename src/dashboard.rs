@@ -0,0 +1,526 @@
+use std::collections::HashMap;
+use std::io::{stdin, stdout, Write};
+use std::path::Path;
+use std::sync::OnceLock;
+
+use chrono::{DateTime, Local, TimeZone};
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::screen::AlternateScreen;
+use termion::{clear, cursor, style};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+use tracing::Instrument;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::api::OpenWeaterToTsv;
+use crate::chart;
+use crate::env_or;
+use crate::icon::IconSet;
+use crate::schedule::{self, Scheduler};
+use crate::secrets;
+use crate::signals::Signals;
+use crate::store;
+use crate::Collector;
+
+// 履歴パネルの最小の高さ
+const HISTORY_MIN_HEIGHT: u16 = 5;
+
+// wキーで切り替える履歴の期間（秒）
+const WINDOWS: [i64; 2] = [86400, 604800];
+
+// 表示単位（取得は常にメートル法で行い、表示時に変換する）
+#[derive(Clone, Copy, PartialEq)]
+enum Units {
+    Metric,
+    Imperial,
+}
+
+impl Units {
+    fn toggle(self) -> Self {
+        match self {
+            Units::Metric => Units::Imperial,
+            Units::Imperial => Units::Metric,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Units::Metric => "metric",
+            Units::Imperial => "imperial",
+        }
+    }
+
+    fn temp(self, celsius: f64) -> String {
+        match self {
+            Units::Metric => format!("{:.1}°C", celsius),
+            Units::Imperial => format!("{:.1}°F", celsius * 9.0 / 5.0 + 32.0),
+        }
+    }
+
+    fn speed(self, meter_per_sec: f64) -> String {
+        match self {
+            Units::Metric => format!("{:.1} m/s", meter_per_sec),
+            Units::Imperial => format!("{:.1} mph", meter_per_sec * 2.236_936),
+        }
+    }
+}
+
+// キー入力の受信側
+// 読み取りのスレッドは最初の表示時に1回だけ開始し、SIGHUPで表示し直す場合も同じものを使う
+static KEYS: OnceLock<Mutex<UnboundedReceiver<Key>>> = OnceLock::new();
+
+// キー入力はブロッキングなので別スレッドで読み取る
+fn keys() -> &'static Mutex<UnboundedReceiver<Key>> {
+    KEYS.get_or_init(|| {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        std::thread::spawn(move || {
+            for key in stdin().keys().flatten() {
+                if tx.send(key).is_err() {
+                    break;
+                }
+            }
+        });
+        Mutex::new(rx)
+    })
+}
+
+// 風向（度）を16方位に変換する
+fn compass(deg: i64) -> &'static str {
+    const POINTS: [&str; 16] = [
+        "N", "NNE", "NE", "ENE", "E", "ESE", "SE", "SSE", "S", "SSW", "SW", "WSW", "W", "WNW",
+        "NW", "NNW",
+    ];
+    POINTS[(((deg as f64 + 11.25) / 22.5) as usize) % 16]
+}
+
+// 表示幅に収まるように切り詰める（全角文字は幅2として数える）
+fn fit(text: &str, width: usize) -> String {
+    let mut out = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        let w = c.width().unwrap_or(0);
+        if used + w > width {
+            break;
+        }
+        used += w;
+        out.push(c);
+    }
+    out
+}
+
+// 期間の表示名
+fn window_name(window: i64) -> String {
+    if window % 86400 == 0 && window >= 86400 * 2 {
+        format!("{}d", window / 86400)
+    } else {
+        format!("{}h", window / 3600)
+    }
+}
+
+// 枠付きのパネルを描画する
+fn panel(
+    out: &mut impl Write,
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+    title: &str,
+    lines: &[String],
+) -> std::io::Result<()> {
+    let inner = width.saturating_sub(2) as usize;
+    let title = fit(&format!(" {} ", title), inner);
+    let top = format!(
+        "┌{}{}┐",
+        title,
+        "─".repeat(inner.saturating_sub(title.width()))
+    );
+    write!(out, "{}{}", cursor::Goto(x, y), top)?;
+    for row in 1..height.saturating_sub(1) {
+        let line = lines
+            .get(row as usize - 1)
+            .map(|v| fit(&format!(" {}", v), inner))
+            .unwrap_or_default();
+        let padding = " ".repeat(inner.saturating_sub(line.width()));
+        write!(out, "{}│{}{}│", cursor::Goto(x, y + row), line, padding)?;
+    }
+    write!(
+        out,
+        "{}└{}┘",
+        cursor::Goto(x, y + height.saturating_sub(1)),
+        "─".repeat(inner)
+    )?;
+    Ok(())
+}
+
+struct Dashboard {
+    locations: Vec<String>,
+    // 表示中の地点
+    index: usize,
+    units: Units,
+    icons: IconSet,
+    // 履歴パネルのスパークラインの期間（秒）
+    window: i64,
+    // 設定した地点名ごとの最新の取得結果
+    latest: HashMap<String, OpenWeaterToTsv>,
+    // 取得結果の地点名（小文字）ごとの履歴
+    history: HashMap<String, Vec<OpenWeaterToTsv>>,
+    last_update: Option<DateTime<Local>>,
+    // フッターに表示するメッセージ（エラーやアラート）
+    status: String,
+}
+
+impl Dashboard {
+    fn new(locations: Vec<String>, icons: IconSet, window: i64) -> Self {
+        Dashboard {
+            locations,
+            index: 0,
+            units: match env_or("WEATHER_UNITS", "metric").as_str() {
+                "imperial" => Units::Imperial,
+                _ => Units::Metric,
+            },
+            icons,
+            window,
+            latest: HashMap::new(),
+            history: HashMap::new(),
+            last_update: None,
+            status: String::new(),
+        }
+    }
+
+    // 保存済みの取得結果を履歴として読み込む
+    fn load_history(&mut self) {
+        let records = match store::load_records(Path::new(store::LOG_DIR)) {
+            Ok(v) => v,
+            Err(e) => {
+                self.status = format!("history load error: {}", e);
+                return;
+            }
+        };
+        for record in records {
+            self.push_history(record);
+        }
+    }
+
+    fn push_history(&mut self, record: OpenWeaterToTsv) {
+        let history = self.history.entry(record.name.to_lowercase()).or_default();
+        // 同じ観測時刻の取得結果は重複させない
+        if history.last().map(|v| v.dt) != Some(record.dt) {
+            history.push(record);
+        }
+    }
+
+    fn location(&self) -> &str {
+        &self.locations[self.index]
+    }
+
+    // 全地点の天気を取得する
+    async fn refresh(&mut self, collector: &mut Collector) {
+        let mut messages = Vec::new();
+        for location in self.locations.clone() {
+            match collector.collect(&location).await {
+                Ok((record, events)) => {
+                    for event in events {
+                        messages.push(format!("alert {}", event));
+                    }
+                    self.latest.insert(location, record.clone());
+                    self.push_history(record);
+                }
+                Err(e) => {
+                    messages.push(format!("{}: {}", location, secrets::redact(&e.to_string())))
+                }
+            }
+        }
+        self.last_update = Some(Local::now());
+        self.status = messages.join(" / ");
+    }
+
+    fn draw(&self, out: &mut impl Write) -> std::io::Result<()> {
+        let (width, height) = termion::terminal_size().unwrap_or((80, 24));
+        let half = width / 2;
+        write!(out, "{}", clear::All)?;
+
+        // タイトル
+        let record = self.latest.get(self.location());
+        let place = match record {
+            Some(v) => format!("{}, {}", v.name, v.country),
+            None => self.location().to_string(),
+        };
+        let updated = match self.last_update {
+            Some(v) => v.format("%H:%M:%S").to_string(),
+            None => String::from("--:--:--"),
+        };
+        let title = format!(
+            " OpenWeather  {}  ({}/{})  {}  updated {}",
+            place,
+            self.index + 1,
+            self.locations.len(),
+            self.units.name(),
+            updated
+        );
+        write!(
+            out,
+            "{}{}{}{}",
+            cursor::Goto(1, 1),
+            style::Invert,
+            fit(&format!("{:<1$}", title, width as usize), width as usize),
+            style::Reset
+        )?;
+
+        let record = match record {
+            Some(v) => v,
+            None => {
+                write!(out, "{}Loading...", cursor::Goto(2, 3))?;
+                return self.draw_footer(out, width, height);
+            }
+        };
+        let units = self.units;
+
+        // 天気概況（右側にアイコンを表示する）
+        panel(
+            out,
+            1,
+            2,
+            half,
+            12,
+            "Conditions",
+            &[
+                record.description.clone(),
+                format!("{} ({})", record.weather_to_main, record.weather_to_id),
+                format!("Clouds     {}%", record.all),
+                format!("Visibility {} m", record.visibility),
+                format!("Rain 1h    {} mm", record.rain_1h),
+                format!("Snow 1h    {} mm", record.snow_h1),
+            ],
+        )?;
+
+        // 気温
+        panel(
+            out,
+            half + 1,
+            2,
+            width - half,
+            12,
+            "Temperature",
+            &[
+                format!("Now        {}", units.temp(record.temp)),
+                format!("Feels like {}", units.temp(record.feels_like)),
+                format!("Min        {}", units.temp(record.temp_min)),
+                format!("Max        {}", units.temp(record.temp_max)),
+                format!("Humidity   {}%", record.humidity),
+                format!("Pressure   {} hPa", record.pressure),
+            ],
+        )?;
+
+        // 風
+        panel(
+            out,
+            1,
+            14,
+            half,
+            6,
+            "Wind",
+            &[
+                format!("Speed      {}", units.speed(record.speed)),
+                format!("Gust       {}", units.speed(record.gust)),
+                format!("Direction  {}° {}", record.deg, compass(record.deg)),
+            ],
+        )?;
+
+        // 日の出・日の入り
+        let observed: DateTime<Local> = Local.timestamp(record.dt, 0);
+        panel(
+            out,
+            half + 1,
+            14,
+            width - half,
+            6,
+            "Sun",
+            &[
+                format!("Sunrise    {}", record.sunrise),
+                format!("Sunset     {}", record.sunset),
+                format!("Observed   {}", observed.format("%m/%d %H:%M")),
+            ],
+        )?;
+
+        // 履歴
+        let history = self
+            .history
+            .get(&record.name.to_lowercase())
+            .map(|v| v.as_slice())
+            .unwrap_or(&[]);
+        let history_height = height.saturating_sub(20).max(HISTORY_MIN_HEIGHT);
+        let to = Local::now().timestamp();
+        let spark_width = (width as usize).saturating_sub(40).max(8);
+        let mut lines: Vec<String> = chart::SERIES
+            .iter()
+            .map(|series| {
+                let values =
+                    chart::bucket(history, series.value, to - self.window, to, spark_width);
+                format!("{:<12}{}", series.name, chart::sparkline(&values))
+            })
+            .collect();
+        let rows = (history_height as usize).saturating_sub(2 + lines.len());
+        lines.extend(history.iter().rev().take(rows).map(|v| {
+            let dt: DateTime<Local> = Local.timestamp(v.dt, 0);
+            format!(
+                "{}  {:>9}  {:>3}%  {:>4} hPa  {:>10}  {}",
+                dt.format("%m/%d %H:%M"),
+                units.temp(v.temp),
+                v.humidity,
+                v.pressure,
+                units.speed(v.speed),
+                v.description
+            )
+        }));
+        let title = format!("History (last {})", window_name(self.window));
+        panel(out, 1, 20, width, history_height, &title, &lines)?;
+
+        self.draw_footer(out, width, height)?;
+        out.flush()?;
+
+        // アイコン
+        self.icons
+            .draw(out, &record.icon, half.saturating_sub(21), 4)?;
+        out.flush()?;
+        Ok(())
+    }
+
+    fn draw_footer(&self, out: &mut impl Write, width: u16, height: u16) -> std::io::Result<()> {
+        let keys = " r: refresh  l: location  u: units  w: window  q: quit ";
+        write!(
+            out,
+            "{}{}{}{}",
+            cursor::Goto(1, height),
+            style::Invert,
+            keys,
+            style::Reset
+        )?;
+        let rest = (width as usize).saturating_sub(keys.len() + 1);
+        write!(out, " {}", fit(&self.status, rest))?;
+        out.flush()
+    }
+}
+
+// 全画面のダッシュボードを表示する。qで終了
+pub async fn run(
+    collector: &mut Collector,
+    locations: Vec<String>,
+    icons: IconSet,
+    window: i64,
+    mut scheduler: Scheduler,
+    signals: &mut Signals,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if locations.is_empty() {
+        return Err("LOCATION_NAME is empty".into());
+    }
+    let mut screen = AlternateScreen::from(stdout().into_raw_mode()?);
+    write!(screen, "{}", cursor::Hide)?;
+
+    let mut rx = keys().lock().await;
+
+    let mut dashboard = Dashboard::new(locations, icons, window);
+    dashboard.load_history();
+
+    // 次の定期取得の時刻（手動の更新はスケジュールに影響しない）
+    let mut next_fetch = Some(Instant::now());
+    let mut refresh_now = false;
+    let mut run = 0;
+    loop {
+        let due = next_fetch.is_some_and(|v| Instant::now() >= v);
+        if due || refresh_now {
+            dashboard.draw(&mut screen)?;
+            // 取得中に終了のシグナルを受けた場合は取り消して終了する
+            run += 1;
+            let refresh = dashboard
+                .refresh(collector)
+                .instrument(tracing::info_span!("cycle", run));
+            if signals.guard(refresh).await.is_none() {
+                break;
+            }
+            if due {
+                scheduler.mark_run();
+                let next = scheduler.next(Local::now());
+                collector.state.scheduled(None, &scheduler, next);
+                next_fetch = next.map(|v| Instant::now() + schedule::wait_time(v));
+            }
+            refresh_now = false;
+        }
+        dashboard.draw(&mut screen)?;
+
+        // 終了条件に達した場合やシグナルを受けた場合終了（SIGHUPの場合は呼び出し元で読み込み直す）
+        if signals.interrupted() {
+            break;
+        }
+        let deadline = match next_fetch {
+            Some(v) => v,
+            None => break,
+        };
+
+        tokio::select! {
+            key = rx.recv() => match key {
+                // 利用者の操作による終了として記録する（入力が閉じられた場合も同じ）
+                Some(Key::Char('q')) | Some(Key::Ctrl('c')) | Some(Key::Esc) | None => {
+                    signals.quit();
+                    break;
+                }
+                Some(Key::Char('r')) => refresh_now = true,
+                Some(Key::Char('l')) => {
+                    dashboard.index = (dashboard.index + 1) % dashboard.locations.len();
+                }
+                Some(Key::Char('u')) => dashboard.units = dashboard.units.toggle(),
+                Some(Key::Char('w')) => {
+                    dashboard.window = match WINDOWS.iter().position(|v| *v == dashboard.window) {
+                        Some(i) => WINDOWS[(i + 1) % WINDOWS.len()],
+                        None => WINDOWS[0],
+                    };
+                }
+                _ => {}
+            },
+            _ = tokio::time::sleep_until(deadline) => {}
+            _ = signals.recv() => {}
+        }
+    }
+
+    write!(screen, "{}", cursor::Show)?;
+    screen.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compass_points() {
+        assert_eq!(compass(0), "N");
+        assert_eq!(compass(11), "N");
+        assert_eq!(compass(12), "NNE");
+        assert_eq!(compass(90), "E");
+        assert_eq!(compass(200), "SSW");
+        assert_eq!(compass(348), "NNW");
+        assert_eq!(compass(349), "N");
+        assert_eq!(compass(360), "N");
+    }
+
+    #[test]
+    fn fit_to_width() {
+        assert_eq!(fit("Osaka", 10), "Osaka");
+        assert_eq!(fit("Osaka", 3), "Osa");
+        assert_eq!(fit("Osaka", 0), "");
+        // 全角文字は幅2として数え、途中で切れる文字は含めない
+        assert_eq!(fit("薄い雲", 4), "薄い");
+        assert_eq!(fit("薄い雲", 5), "薄い");
+        assert_eq!(fit("a薄い", 2), "a");
+    }
+
+    #[test]
+    fn window_names() {
+        assert_eq!(window_name(86400), "24h");
+        assert_eq!(window_name(604800), "7d");
+        assert_eq!(window_name(172800), "2d");
+        assert_eq!(window_name(3600 * 36), "36h");
+    }
+}
@@ -0,0 +1,104 @@
+use chrono::{DateTime, Local, TimeZone};
+use serde_json::Value;
+
+use crate::api;
+
+// 5日間/3時間ごとの予報の1件
+pub struct ForecastEntry {
+    pub dt: i64,
+    pub temp: f64,
+    pub feels_like: f64,
+    pub humidity: i64,
+    pub description: String,
+    pub speed: f64,
+    // 降水確率（0〜1）
+    pub pop: f64,
+    // 3時間の降水量
+    pub rain_3h: f64,
+}
+
+// 予報APIのレスポンスを読み込む
+pub fn parse(body: &str) -> Result<Vec<ForecastEntry>, Box<dyn std::error::Error>> {
+    let deserialize: Value = serde_json::from_str(body)?;
+
+    // 予報APIのcodは文字列で返ってくる
+    let cod = match deserialize.get("cod") {
+        Some(Value::String(s)) => s.parse().unwrap_or(0),
+        Some(v) => v.as_i64().unwrap_or(0),
+        None => 0,
+    };
+    if cod != 200 {
+        let message = deserialize
+            .get("message")
+            .and_then(|v| v.as_str())
+            .unwrap_or("");
+        return Err(format!("not 200 status ({}: {})\ncheck config", cod, message).into());
+    }
+
+    let list = match deserialize.get("list").and_then(|v| v.as_array()) {
+        Some(v) => v,
+        None => return Err("forecast list not found".into()),
+    };
+    let entries = list
+        .iter()
+        .map(|v| ForecastEntry {
+            dt: v.get("dt").and_then(|v| v.as_i64()).unwrap_or(0),
+            temp: v
+                .pointer("/main/temp")
+                .and_then(|v| v.as_f64())
+                .unwrap_or(0.0),
+            feels_like: v
+                .pointer("/main/feels_like")
+                .and_then(|v| v.as_f64())
+                .unwrap_or(0.0),
+            humidity: v
+                .pointer("/main/humidity")
+                .and_then(|v| v.as_i64())
+                .unwrap_or(0),
+            description: v
+                .pointer("/weather/0/description")
+                .and_then(|v| v.as_str())
+                .unwrap_or("")
+                .to_string(),
+            speed: v
+                .pointer("/wind/speed")
+                .and_then(|v| v.as_f64())
+                .unwrap_or(0.0),
+            pop: v.get("pop").and_then(|v| v.as_f64()).unwrap_or(0.0),
+            rain_3h: v
+                .pointer("/rain/3h")
+                .and_then(|v| v.as_f64())
+                .unwrap_or(0.0),
+        })
+        .collect();
+
+    Ok(entries)
+}
+
+// 予報を表形式で表示する（単位は取得時の指定に従う）
+pub fn print(location_name: &str, entries: &[ForecastEntry], units: &str) {
+    let (temp_unit, speed_unit) = api::unit_labels(units);
+    println!("{}", location_name);
+    println!(
+        "{:<12} {:>8} {:>8} {:>4} {:>5} {:>7} {:>9}  description",
+        "time", "temp", "feels", "hum", "pop", "rain", "wind"
+    );
+    for v in entries {
+        let time: DateTime<Local> = Local.timestamp(v.dt, 0);
+        println!(
+            "{:<12} {:>6.1}{} {:>6.1}{} {:>3}% {:>4.0}% {:>5.1}mm {:>5.1}{}  {}",
+            time.format("%m/%d %H:%M"),
+            v.temp,
+            temp_unit,
+            v.feels_like,
+            temp_unit,
+            v.humidity,
+            v.pop * 100.0,
+            v.rain_3h,
+            v.speed,
+            speed_unit,
+            v.description
+        );
+    }
+    println!();
+}
@@ -0,0 +1,422 @@
+use std::env;
+use std::io::Write;
+use std::path::PathBuf;
+
+use image::DynamicImage;
+use termion::cursor;
+
+use crate::config::Values;
+
+// OpenWeatherのアイコンコードと、バイナリに埋め込んだアイコン画像（作業ディレクトリに依存しないようにする）
+const EMBEDDED: [(&str, &[u8]); 18] = [
+    ("01d", include_bytes!("../assets/01d.png")),
+    ("01n", include_bytes!("../assets/01n.png")),
+    ("02d", include_bytes!("../assets/02d.png")),
+    ("02n", include_bytes!("../assets/02n.png")),
+    ("03d", include_bytes!("../assets/03d.png")),
+    ("03n", include_bytes!("../assets/03n.png")),
+    ("04d", include_bytes!("../assets/04d.png")),
+    ("04n", include_bytes!("../assets/04n.png")),
+    ("09d", include_bytes!("../assets/09d.png")),
+    ("09n", include_bytes!("../assets/09n.png")),
+    ("10d", include_bytes!("../assets/10d.png")),
+    ("10n", include_bytes!("../assets/10n.png")),
+    ("11d", include_bytes!("../assets/11d.png")),
+    ("11n", include_bytes!("../assets/11n.png")),
+    ("13d", include_bytes!("../assets/13d.png")),
+    ("13n", include_bytes!("../assets/13n.png")),
+    ("50d", include_bytes!("../assets/50d.png")),
+    ("50n", include_bytes!("../assets/50n.png")),
+];
+
+// 天気アイコンの表示方法
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IconMode {
+    // 画像（kitty/iTerm/sixel、または色付きのブロック文字）
+    Image,
+    // 複数行のアスキーアート
+    Ascii,
+    // 絵文字1文字
+    Emoji,
+}
+
+impl IconMode {
+    // ICON_MODE（auto / image / ascii / emoji）から表示方法を決める
+    pub fn from_values(values: &Values) -> Self {
+        match values.or("ICON_MODE", "auto").as_str() {
+            "image" => IconMode::Image,
+            "ascii" => IconMode::Ascii,
+            "emoji" => IconMode::Emoji,
+            _ => IconMode::detect(),
+        }
+    }
+
+    // 端末が画像を表示できるか判定する
+    fn detect() -> Self {
+        if !termion::is_tty(&std::io::stdout()) {
+            return IconMode::Ascii;
+        }
+        let term = env::var("TERM").unwrap_or_default();
+        if term.is_empty() || term == "dumb" || term == "linux" {
+            return IconMode::Ascii;
+        }
+        if viuer::is_iterm_supported() || viuer::get_kitty_support() != viuer::KittySupport::None {
+            return IconMode::Image;
+        }
+        // ブロック文字での描画には256色以上が必要
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" || term.contains("256color") {
+            return IconMode::Image;
+        }
+        IconMode::Ascii
+    }
+}
+
+// アイコンの表示方法と画像の読み込み元
+#[derive(Clone, Debug)]
+pub struct IconSet {
+    pub mode: IconMode,
+    // アイコンテーマのディレクトリ。未指定の場合は埋め込み画像を使う
+    theme_dir: Option<PathBuf>,
+}
+
+impl IconSet {
+    // ICON_MODEとICON_THEME_DIRから作成する
+    // テーマのディレクトリには全てのアイコンコードの画像（{コード}.png）が必要
+    pub fn from_values(values: &Values) -> Result<Self, String> {
+        let theme_dir = values.get("ICON_THEME_DIR").map(PathBuf::from);
+        if let Some(dir) = &theme_dir {
+            if !dir.is_dir() {
+                return Err(format!("icon theme not found: {}", dir.display()));
+            }
+            let mut missing = Vec::new();
+            let mut broken = Vec::new();
+            for (code, _) in EMBEDDED {
+                let path = dir.join(format!("{}.png", code));
+                if !path.is_file() {
+                    missing.push(code);
+                } else if image::open(&path).is_err() {
+                    // 表示時に読み込めず毎回アスキーアートになるので、起動時に確認する
+                    broken.push(code);
+                }
+            }
+            if !missing.is_empty() {
+                return Err(format!(
+                    "icon theme {} is missing: {}",
+                    dir.display(),
+                    missing.join(", ")
+                ));
+            }
+            if !broken.is_empty() {
+                return Err(format!(
+                    "icon theme {} has invalid images: {}",
+                    dir.display(),
+                    broken.join(", ")
+                ));
+            }
+        }
+
+        Ok(IconSet {
+            mode: IconMode::from_values(values),
+            theme_dir,
+        })
+    }
+
+    // アイコン画像を読み込む
+    fn image(&self, icon: &str) -> Option<DynamicImage> {
+        match &self.theme_dir {
+            Some(dir) => image::open(dir.join(format!("{}.png", icon))).ok(),
+            None => {
+                let (_, bytes) = EMBEDDED.iter().find(|(code, _)| *code == icon)?;
+                image::load_from_memory(bytes).ok()
+            }
+        }
+    }
+
+    // アイコンを端末の(x, y)（1始まり）に描画する
+    // 画像が読み込めない、または表示に失敗した場合はアスキーアートで表示する
+    pub fn draw(&self, out: &mut impl Write, icon: &str, x: u16, y: u16) -> std::io::Result<()> {
+        if icon.is_empty() {
+            return write!(out, "{}No Icon", cursor::Goto(x, y));
+        }
+        match self.mode {
+            IconMode::Image => {
+                if let Some(img) = self.image(icon) {
+                    out.flush()?;
+                    let conf = viuer::Config {
+                        width: Some(20),
+                        height: Some(10),
+                        x: x.saturating_sub(1),
+                        y: y as i16 - 1,
+                        ..Default::default()
+                    };
+                    if viuer::print(&img, &conf).is_ok() {
+                        return Ok(());
+                    }
+                }
+                draw_text(out, icon, IconMode::Ascii, x, y)
+            }
+            mode => draw_text(out, icon, mode, x, y),
+        }
+    }
+
+    // 位置指定をせずに標準出力に表示する（パイプやリダイレクト先向け）
+    pub fn print_plain(&self, icon: &str) {
+        if icon.is_empty() {
+            println!("No Icon");
+            return;
+        }
+        match self.mode {
+            IconMode::Emoji => println!("{}", emoji(icon)),
+            _ => {
+                for line in art(icon) {
+                    println!("{}", line);
+                }
+            }
+        }
+    }
+}
+
+// アイコンコード（01d〜50n）に対応するアスキーアート
+pub fn art(icon: &str) -> [&'static str; 5] {
+    let night = icon.ends_with('n');
+    match icon.get(..2).unwrap_or("") {
+        "01" if night => [
+            r"     _.._    ",
+            r"   .' .-'`   ",
+            r"  /  /       ",
+            r"  \  '.___.  ",
+            r"   '._  _.'  ",
+        ],
+        "01" => [
+            r"    \   /    ",
+            r"     .-.     ",
+            r"  - (   ) -  ",
+            r"     `-'     ",
+            r"    /   \    ",
+        ],
+        "02" if night => [
+            r"    _.._     ",
+            r"  .' .-.     ",
+            r"  | (   ).   ",
+            r"  '(___(__)  ",
+            r"             ",
+        ],
+        "02" => [
+            r"   \  /      ",
+            r" _ /''.-.    ",
+            r"   \_(   ).  ",
+            r"   /(___(__) ",
+            r"             ",
+        ],
+        "03" => [
+            r"             ",
+            r"     .--.    ",
+            r"  .-(    ).  ",
+            r" (___.__)__) ",
+            r"             ",
+        ],
+        "04" => [
+            r"     .--.    ",
+            r"  .-(    ).  ",
+            r" (___.__)__) ",
+            r"   .-(  ).   ",
+            r"  (___(__)   ",
+        ],
+        "10" if !night => [
+            r" _`/''.-.    ",
+            r"  ,\_(   ).  ",
+            r"   /(___(__) ",
+            r"     ' ' ' ' ",
+            r"    ' ' ' '  ",
+        ],
+        "09" | "10" => [
+            r"     .-.     ",
+            r"    (   ).   ",
+            r"   (___(__)  ",
+            r"    ' ' ' '  ",
+            r"   ' ' ' '   ",
+        ],
+        "11" => [
+            r"     .-.     ",
+            r"    (   ).   ",
+            r"   (___(__)  ",
+            r"    /_  /_   ",
+            r"     /   /   ",
+        ],
+        "13" => [
+            r"     .-.     ",
+            r"    (   ).   ",
+            r"   (___(__)  ",
+            r"    *  *  *  ",
+            r"   *  *  *   ",
+        ],
+        "50" => [
+            r"             ",
+            r" _ - _ - _ - ",
+            r"  _ - _ - _  ",
+            r" _ - _ - _ - ",
+            r"             ",
+        ],
+        _ => [
+            r"             ",
+            r"    .-.      ",
+            r"     __)     ",
+            r"    (        ",
+            r"     *       ",
+        ],
+    }
+}
+
+// アイコンコードに対応する絵文字
+pub fn emoji(icon: &str) -> &'static str {
+    let night = icon.ends_with('n');
+    match icon.get(..2).unwrap_or("") {
+        "01" if night => "🌙",
+        "01" => "☀️",
+        "02" if night => "☁️",
+        "02" => "⛅",
+        "03" | "04" => "☁️",
+        "09" => "🌧️",
+        "10" if night => "🌧️",
+        "10" => "🌦️",
+        "11" => "⛈️",
+        "13" => "❄️",
+        "50" => "🌫️",
+        _ => "❔",
+    }
+}
+
+// アスキーアートまたは絵文字でアイコンを描画する
+fn draw_text(
+    out: &mut impl Write,
+    icon: &str,
+    mode: IconMode,
+    x: u16,
+    y: u16,
+) -> std::io::Result<()> {
+    match mode {
+        IconMode::Emoji => write!(out, "{}{}", cursor::Goto(x, y), emoji(icon)),
+        _ => {
+            for (i, line) in art(icon).iter().enumerate() {
+                write!(out, "{}{}", cursor::Goto(x, y + i as u16), line)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn emoji_for_codes() {
+        assert_eq!(emoji("01d"), "☀️");
+        assert_eq!(emoji("01n"), "🌙");
+        assert_eq!(emoji("02d"), "⛅");
+        assert_eq!(emoji("02n"), "☁️");
+        assert_eq!(emoji("04d"), "☁️");
+        assert_eq!(emoji("09n"), "🌧️");
+        assert_eq!(emoji("10d"), "🌦️");
+        assert_eq!(emoji("10n"), "🌧️");
+        assert_eq!(emoji("11d"), "⛈️");
+        assert_eq!(emoji("13n"), "❄️");
+        assert_eq!(emoji("50d"), "🌫️");
+        assert_eq!(emoji("99d"), "❔");
+        assert_eq!(emoji(""), "❔");
+    }
+
+    #[test]
+    fn art_for_codes() {
+        // 全てのアイコンコードに専用のアスキーアートがある
+        let unknown = art("99d");
+        for (code, _) in EMBEDDED {
+            let lines = art(code);
+            assert_ne!(lines, unknown, "{}", code);
+            assert!(lines.iter().all(|line| line.len() == 13), "{}", code);
+        }
+        assert_ne!(art("01d"), art("01n"));
+        assert_ne!(art("10d"), art("10n"));
+        assert_eq!(art("09d"), art("10n"));
+        assert_eq!(art("03d"), art("03n"));
+        assert_eq!(art(""), unknown);
+    }
+
+    #[test]
+    fn draw_ascii_and_emoji() {
+        let mut icons = IconSet {
+            mode: IconMode::Emoji,
+            theme_dir: None,
+        };
+        let mut out = Vec::new();
+        icons.draw(&mut out, "13d", 3, 2).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("{}❄️", cursor::Goto(3, 2))
+        );
+
+        icons.mode = IconMode::Ascii;
+        let mut out = Vec::new();
+        icons.draw(&mut out, "50n", 1, 1).unwrap();
+        let expected: String = art("50n")
+            .iter()
+            .enumerate()
+            .map(|(i, line)| format!("{}{}", cursor::Goto(1, 1 + i as u16), line))
+            .collect();
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+
+        let mut out = Vec::new();
+        icons.draw(&mut out, "", 1, 1).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("{}No Icon", cursor::Goto(1, 1))
+        );
+    }
+
+    fn theme_values(dir: &std::path::Path) -> Values {
+        let mut values = Values::default();
+        values.set("ICON_MODE", "ascii");
+        values.set("ICON_THEME_DIR", &dir.display().to_string());
+        values
+    }
+
+    #[test]
+    fn theme_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        for (code, bytes) in EMBEDDED {
+            fs::write(dir.path().join(format!("{}.png", code)), bytes).unwrap();
+        }
+        let icons = IconSet::from_values(&theme_values(dir.path())).unwrap();
+        assert_eq!(icons.mode, IconMode::Ascii);
+        assert!(icons.image("01d").is_some());
+        assert!(icons.image("99d").is_none());
+
+        // 読み込めない画像
+        fs::write(dir.path().join("10n.png"), "not a png").unwrap();
+        let error = IconSet::from_values(&theme_values(dir.path())).unwrap_err();
+        assert!(error.ends_with("has invalid images: 10n"), "{}", error);
+
+        // 足りない画像
+        fs::remove_file(dir.path().join("01d.png")).unwrap();
+        fs::remove_file(dir.path().join("50n.png")).unwrap();
+        let error = IconSet::from_values(&theme_values(dir.path())).unwrap_err();
+        assert!(error.ends_with("is missing: 01d, 50n"), "{}", error);
+
+        let missing = dir.path().join("none");
+        let error = IconSet::from_values(&theme_values(&missing)).unwrap_err();
+        assert_eq!(
+            error,
+            format!("icon theme not found: {}", missing.display())
+        );
+    }
+
+    #[test]
+    fn embedded_images() {
+        let icons = IconSet::from_values(&Values::default()).unwrap();
+        for (code, _) in EMBEDDED {
+            assert!(icons.image(code).is_some(), "{}", code);
+        }
+    }
+}
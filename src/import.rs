@@ -0,0 +1,365 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use chrono::{Local, TimeZone};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::api::OpenWeaterToTsv;
+use crate::backfill;
+use crate::store;
+
+// History BulkのCSVの1行
+// 空欄の項目はNoneになる
+#[derive(Deserialize)]
+struct BulkRow {
+    dt: i64,
+    timezone: Option<i64>,
+    city_name: Option<String>,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    temp: Option<f64>,
+    visibility: Option<f64>,
+    feels_like: Option<f64>,
+    temp_min: Option<f64>,
+    temp_max: Option<f64>,
+    pressure: Option<f64>,
+    sea_level: Option<f64>,
+    grnd_level: Option<f64>,
+    humidity: Option<f64>,
+    wind_speed: Option<f64>,
+    wind_deg: Option<f64>,
+    wind_gust: Option<f64>,
+    rain_1h: Option<f64>,
+    rain_3h: Option<f64>,
+    snow_1h: Option<f64>,
+    snow_3h: Option<f64>,
+    clouds_all: Option<f64>,
+    weather_id: Option<i64>,
+    weather_main: Option<String>,
+    weather_description: Option<String>,
+    weather_icon: Option<String>,
+}
+
+impl BulkRow {
+    // JSONの形式（現在の天気と同じ形）に揃える
+    fn to_value(&self) -> Value {
+        json!({
+            "dt": self.dt,
+            "timezone": self.timezone,
+            "city_name": self.city_name,
+            "lat": self.lat,
+            "lon": self.lon,
+            "visibility": self.visibility,
+            "main": {
+                "temp": self.temp,
+                "feels_like": self.feels_like,
+                "temp_min": self.temp_min,
+                "temp_max": self.temp_max,
+                "pressure": self.pressure,
+                "sea_level": self.sea_level,
+                "grnd_level": self.grnd_level,
+                "humidity": self.humidity,
+            },
+            "wind": {
+                "speed": self.wind_speed,
+                "deg": self.wind_deg,
+                "gust": self.wind_gust,
+            },
+            "clouds": { "all": self.clouds_all },
+            "rain": { "1h": self.rain_1h, "3h": self.rain_3h },
+            "snow": { "1h": self.snow_1h, "3h": self.snow_3h },
+            "weather": [{
+                "id": self.weather_id,
+                "main": self.weather_main,
+                "description": self.weather_description,
+                "icon": self.weather_icon,
+            }],
+        })
+    }
+}
+
+// ファイルを読み込み、1件ずつJSONの値にする
+// 拡張子が.csvの場合はCSV、.jsonの場合はJSON、それ以外は先頭の文字で判定する
+fn read_file(path: &Path) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(path)?;
+    let csv = match path.extension().and_then(|v| v.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("csv") => true,
+        Some(ext) if ext.eq_ignore_ascii_case("json") => false,
+        _ => !text.trim_start().starts_with('['),
+    };
+    if !csv {
+        let value: Value = serde_json::from_str(&text)?;
+        return match value {
+            Value::Array(v) => Ok(v),
+            _ => Err("expected a JSON array".into()),
+        };
+    }
+
+    let mut values = Vec::new();
+    let mut rdr = csv::Reader::from_reader(text.as_bytes());
+    for (i, row) in rdr.deserialize::<BulkRow>().enumerate() {
+        // 壊れた行は読み飛ばす（1行目は見出し）
+        match row {
+            Ok(v) => values.push(v.to_value()),
+            Err(e) => tracing::warn!(path = %path.display(), line = i + 2, error = %e, "skip row"),
+        }
+    }
+    Ok(values)
+}
+
+// 保存する単位（メートル法）に変換する
+fn to_metric(record: &mut OpenWeaterToTsv, units: &str) {
+    let temp: fn(f64) -> f64 = match units {
+        "standard" => |v| v - 273.15,
+        "imperial" => |v| (v - 32.0) * 5.0 / 9.0,
+        _ => return,
+    };
+    record.temp = temp(record.temp);
+    record.feels_like = temp(record.feels_like);
+    record.temp_min = temp(record.temp_min);
+    record.temp_max = temp(record.temp_max);
+    if units == "imperial" {
+        // マイル/時をメートル/秒にする
+        record.speed /= 2.236_936;
+        record.gust /= 2.236_936;
+    }
+}
+
+// 1件をOpenWeaterToTsvに変換する（地点名はnameの指定、なければcity_name）
+fn convert(value: &Value, name: Option<&str>, units: &str) -> Option<OpenWeaterToTsv> {
+    let mut record = OpenWeaterToTsv::new();
+    record.dt = value.get("dt")?.as_i64()?;
+    // 日時として扱えない値は取り込まない（集計や表示で使えないため）
+    Local.timestamp_opt(record.dt, 0).single()?;
+    record.name = match name {
+        Some(v) => v.to_string(),
+        None => value.get("city_name")?.as_str()?.to_string(),
+    };
+    record.lat = value.get("lat").and_then(|v| v.as_f64()).unwrap_or(0.0);
+    record.lon = value.get("lon").and_then(|v| v.as_f64()).unwrap_or(0.0);
+    record.timezone = value.get("timezone").and_then(|v| v.as_i64()).unwrap_or(0);
+    record.cod = 200;
+    record.source = String::from("import");
+    backfill::set_observation(&mut record, value);
+    to_metric(&mut record, units);
+    Some(record)
+}
+
+// 全ての行を変換し、地点名とdtがseenと重複する行を除く
+// 変換した取得結果（dt順）と、重複した行数、変換できなかった行数を返す
+fn convert_all(
+    values: &[Value],
+    name: Option<&str>,
+    units: &str,
+    seen: &mut HashSet<(String, i64)>,
+) -> (Vec<OpenWeaterToTsv>, usize, usize) {
+    let mut records = Vec::new();
+    let mut invalid = 0;
+    let mut duplicates = 0;
+    for value in values {
+        let record = match convert(value, name, units) {
+            Some(v) => v,
+            None => {
+                invalid += 1;
+                continue;
+            }
+        };
+        if seen.insert((record.name.to_lowercase(), record.dt)) {
+            records.push(record);
+        } else {
+            duplicates += 1;
+        }
+    }
+    records.sort_by_key(|v| v.dt);
+    (records, duplicates, invalid)
+}
+
+// History Bulkのファイル（JSON/CSV）を取り込み、保存済みの取得結果と同じ形式で保存する
+// 地点名とdtが保存済みの取得結果（または先に取り込んだ行）と重複する行は取り込まない
+// unitsはファイルの単位（metric / imperial / standard）
+pub fn run(
+    files: &[PathBuf],
+    name: Option<&str>,
+    units: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !["metric", "imperial", "standard"].contains(&units) {
+        return Err(format!("invalid units {:?} (metric, imperial, standard)", units).into());
+    }
+    let mut seen: HashSet<(String, i64)> = store::load_records(Path::new(store::LOG_DIR))?
+        .into_iter()
+        .map(|v| (v.name.to_lowercase(), v.dt))
+        .collect();
+
+    let now = Local::now().format("%Y-%m-%d%H:%M:%S");
+    for path in files {
+        let values = read_file(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let (records, duplicates, invalid) = convert_all(&values, name, units, &mut seen);
+
+        if !records.is_empty() {
+            // 拡張子も含めて、同じ名前のCSVとJSONを取り込んでも上書きしないようにする
+            let file_name = path
+                .file_name()
+                .and_then(|v| v.to_str())
+                .unwrap_or("bulk")
+                .replace(char::is_whitespace, "_");
+            store::write_records(&format!("import-{}-{}", file_name, now), &records)?;
+        }
+        println!(
+            "{}: {} imported, {} duplicates, {} invalid",
+            path.display(),
+            records.len(),
+            duplicates,
+            invalid
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV: &str = "\
+dt,dt_iso,timezone,city_name,lat,lon,temp,visibility,dew_point,feels_like,temp_min,temp_max,pressure,sea_level,grnd_level,humidity,wind_speed,wind_deg,wind_gust,rain_1h,rain_3h,snow_1h,snow_3h,clouds_all,weather_id,weather_main,weather_description,weather_icon
+1700000000,2023-11-14 22:13:20 +0000 UTC,32400,Kyoto,35.01,135.76,12.5,10000,5.1,11.8,11.0,13.2,1018,,,71,2.5,320,,0.3,,,,75,500,Rain,light rain,10n
+not a number,,,Kyoto,,,,,,,,,,,,,,,,,,,,,,,,
+1700003600,2023-11-14 23:13:20 +0000 UTC,32400,Kyoto,35.01,135.76,12.1,,,,,,1019,,,73,1.9,300,,,,,,40,802,Clouds,scattered clouds,03n
+";
+
+    const JSON: &str = r#"[
+  {
+    "city_name": "Nara",
+    "lat": 34.68,
+    "lon": 135.8,
+    "main": {"temp": 290.15, "feels_like": 289.15, "temp_min": 288.15, "temp_max": 291.15, "pressure": 1012, "humidity": 80},
+    "wind": {"speed": 4.5, "deg": 180},
+    "clouds": {"all": 90},
+    "weather": [{"id": 501, "main": "Rain", "description": "moderate rain", "icon": "10d"}],
+    "rain": {"1h": 1.2},
+    "dt": 1700000000,
+    "dt_iso": "2023-11-14 22:13:20 +0000 UTC",
+    "timezone": 32400
+  },
+  {"city_name": "Nara", "dt": 99999999999999, "main": {"temp": 290.0}},
+  {"city_name": "Nara", "main": {"temp": 290.0}},
+  {"dt": 1700003600, "main": {"temp": 290.0}}
+]"#;
+
+    fn read(text: &str, file_name: &str) -> Vec<Value> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(file_name);
+        std::fs::write(&path, text).unwrap();
+        read_file(&path).unwrap()
+    }
+
+    #[test]
+    fn csv_rows() {
+        let values = read(CSV, "kyoto.csv");
+        // 壊れた行は読み飛ばす
+        assert_eq!(values.len(), 2);
+
+        let record = convert(&values[0], None, "metric").unwrap();
+        assert_eq!(record.dt, 1700000000);
+        assert_eq!(record.name, "Kyoto");
+        assert_eq!(record.lat, 35.01);
+        assert_eq!(record.timezone, 32400);
+        assert_eq!(record.temp, 12.5);
+        assert_eq!(record.feels_like, 11.8);
+        assert_eq!(record.temp_max, 13.2);
+        assert_eq!(record.pressure, 1018);
+        // 空欄は0
+        assert_eq!(record.sea_level, 0);
+        assert_eq!(record.gust, 0.0);
+        assert_eq!(record.humidity, 71);
+        assert_eq!(record.speed, 2.5);
+        assert_eq!(record.deg, 320);
+        assert_eq!(record.rain_1h, 0.3);
+        assert_eq!(record.all, 75);
+        assert_eq!(record.weather_to_id, 500);
+        assert_eq!(record.description, "light rain");
+        assert_eq!(record.icon, "10n");
+        assert_eq!(record.source, "import");
+
+        let record = convert(&values[1], Some("kyoto-station"), "metric").unwrap();
+        assert_eq!(record.name, "kyoto-station");
+        assert_eq!(record.visibility, 0);
+        assert_eq!(record.weather_to_main, "Clouds");
+    }
+
+    #[test]
+    fn json_rows_in_standard_units() {
+        // 拡張子がない場合は先頭の文字で判定する
+        let values = read(JSON, "nara");
+        assert_eq!(values.len(), 4);
+
+        let record = convert(&values[0], None, "standard").unwrap();
+        assert_eq!(record.name, "Nara");
+        assert!((record.temp - 17.0).abs() < 1e-9);
+        assert!((record.temp_min - 15.0).abs() < 1e-9);
+        assert_eq!(record.pressure, 1012);
+        assert_eq!(record.speed, 4.5);
+        assert_eq!(record.all, 90);
+        assert_eq!(record.rain_1h, 1.2);
+        assert_eq!(record.weather_to_id, 501);
+
+        // 範囲外のdt、dtなし、地点名なし
+        assert!(convert(&values[1], None, "standard").is_none());
+        assert!(convert(&values[2], None, "standard").is_none());
+        assert!(convert(&values[3], None, "standard").is_none());
+        assert!(convert(&values[3], Some("Nara"), "standard").is_some());
+    }
+
+    #[test]
+    fn imperial_units() {
+        let value = json!({
+            "dt": 1700000000,
+            "city_name": "Boston",
+            "main": {"temp": 50.0},
+            "wind": {"speed": 2.236936, "gust": 4.473872},
+        });
+        let record = convert(&value, None, "imperial").unwrap();
+        assert!((record.temp - 10.0).abs() < 1e-9);
+        assert!((record.speed - 1.0).abs() < 1e-6);
+        assert!((record.gust - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn duplicates_by_location_and_dt() {
+        let values = vec![
+            json!({"dt": 1700003600, "city_name": "Kyoto", "main": {"temp": 1.0}}),
+            json!({"dt": 1700000000, "city_name": "Kyoto", "main": {"temp": 2.0}}),
+            // 同じファイル内の重複（地点名の大文字小文字は区別しない）
+            json!({"dt": 1700000000, "city_name": "KYOTO", "main": {"temp": 3.0}}),
+            // 保存済みの取得結果と重複
+            json!({"dt": 1700007200, "city_name": "Kyoto", "main": {"temp": 4.0}}),
+            // 同じdtでも地点が異なれば取り込む
+            json!({"dt": 1700000000, "city_name": "Nara", "main": {"temp": 5.0}}),
+            json!({"city_name": "Kyoto"}),
+        ];
+        let mut seen = HashSet::from([(String::from("kyoto"), 1700007200)]);
+        let (records, duplicates, invalid) = convert_all(&values, None, "metric", &mut seen);
+
+        assert_eq!(duplicates, 2);
+        assert_eq!(invalid, 1);
+        let rows: Vec<(&str, i64, f64)> = records
+            .iter()
+            .map(|v| (v.name.as_str(), v.dt, v.temp))
+            .collect();
+        // dt順に並べる
+        assert_eq!(
+            rows,
+            vec![
+                ("Kyoto", 1700000000, 2.0),
+                ("Nara", 1700000000, 5.0),
+                ("Kyoto", 1700003600, 1.0),
+            ]
+        );
+
+        // 2回目の取り込みでは全て重複になる
+        let (records, duplicates, _) = convert_all(&values, None, "metric", &mut seen);
+        assert!(records.is_empty());
+        assert_eq!(duplicates, 5);
+    }
+}
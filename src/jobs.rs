@@ -0,0 +1,475 @@
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::Local;
+use serde_json::{json, Value};
+use tokio::sync::{watch, Mutex};
+use tokio::task::JoinSet;
+use tracing::Instrument;
+
+use crate::schedule::{self, Cron, Schedule, Scheduler};
+use crate::signals::Signals;
+use crate::state::StateFile;
+use crate::{do_get_weather, secrets, store, ApiClient, Collector};
+
+// 取得するAPI
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Endpoint {
+    // 現在の天気
+    Weather,
+    // 5日間/3時間ごとの予報
+    Forecast,
+    // 大気汚染
+    AirQuality,
+    // 地名検索
+    Geocoding,
+}
+
+impl Endpoint {
+    fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "weather" => Ok(Endpoint::Weather),
+            "forecast" => Ok(Endpoint::Forecast),
+            "air_quality" => Ok(Endpoint::AirQuality),
+            "geocoding" => Ok(Endpoint::Geocoding),
+            _ => Err(format!(
+                "unknown endpoint {:?} (weather, forecast, air_quality, geocoding)",
+                value
+            )),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Endpoint::Weather => "weather",
+            Endpoint::Forecast => "forecast",
+            Endpoint::AirQuality => "air_quality",
+            Endpoint::Geocoding => "geocoding",
+        }
+    }
+}
+
+// 取得結果の出力先
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sink {
+    // 現在の天気の保存・配信・アラート評価（weatherのみ）
+    Collector,
+    // 標準出力に1行ずつJSONで出力する
+    Stdout,
+    // 「weatherlog/{ジョブ名}.jsonl」に追記する
+    Jsonl,
+}
+
+impl Sink {
+    fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "collector" => Ok(Sink::Collector),
+            "stdout" => Ok(Sink::Stdout),
+            "jsonl" => Ok(Sink::Jsonl),
+            _ => Err(format!(
+                "unknown sink {:?} (collector, stdout, jsonl)",
+                value
+            )),
+        }
+    }
+}
+
+// 取得ジョブ
+pub struct Job {
+    pub name: String,
+    pub endpoint: Endpoint,
+    // 空の場合はLOCATION_NAMEの地点
+    pub locations: Vec<String>,
+    pub schedule: Schedule,
+    // 実行回数の上限（onceの場合は1）
+    pub runs: Option<u64>,
+    pub sinks: Vec<Sink>,
+}
+
+// ジョブの定義を読み込む。「;」区切りで複数指定できる
+// 書式: [名前:] エンドポイント [for 地点,地点] (every 間隔 | cron(cron式) | once) [runs 回数] [to 出力先,出力先]
+// 例: current: weather every 10m; forecast every 1h to jsonl; air_quality cron(*/30 * * * *); geocoding once
+pub fn parse_jobs(value: &str) -> Result<Vec<Job>, String> {
+    let mut jobs = Vec::new();
+    for spec in value.split(';').map(|v| v.trim()).filter(|v| !v.is_empty()) {
+        let job = parse_job(spec).map_err(|e| format!("job {:?}: {}", spec, e))?;
+        if jobs.iter().any(|v: &Job| v.name == job.name) {
+            return Err(format!("job {:?}: duplicate name {:?}", spec, job.name));
+        }
+        jobs.push(job);
+    }
+    Ok(jobs)
+}
+
+fn parse_job(spec: &str) -> Result<Job, String> {
+    // cron式は空白を含むので先に取り出す
+    let (spec, cron) = match spec.find("cron(") {
+        Some(start) => {
+            let end = spec[start..].find(')').ok_or("missing \")\" after cron(")?;
+            let cron = Cron::parse(&spec[start + 5..start + end])?;
+            let rest = format!("{} {}", &spec[..start], &spec[start + end + 1..]);
+            (rest, Some(cron))
+        }
+        None => (spec.to_string(), None),
+    };
+
+    let mut tokens: Vec<&str> = spec.split_whitespace().collect();
+    // 先頭の「名前:」
+    let mut name = None;
+    if let Some(first) = tokens.first() {
+        if let Some(v) = first.strip_suffix(':') {
+            name = Some(v.to_string());
+            tokens.remove(0);
+        } else if let Some((v, rest)) = first.split_once(':') {
+            name = Some(v.to_string());
+            tokens[0] = rest;
+        }
+    }
+
+    let mut tokens = tokens.into_iter();
+    let endpoint = Endpoint::parse(tokens.next().ok_or("missing endpoint")?)?;
+    let mut locations = Vec::new();
+    let mut schedule = cron.map(Schedule::Cron);
+    let mut runs = None;
+    let mut sinks = Vec::new();
+    while let Some(token) = tokens.next() {
+        match token {
+            "for" => {
+                let value = tokens.next().ok_or("missing locations after \"for\"")?;
+                locations = value
+                    .split(',')
+                    .map(|v| v.trim().to_string())
+                    .filter(|v| !v.is_empty())
+                    .collect();
+            }
+            "every" => {
+                let value = tokens.next().ok_or("missing interval after \"every\"")?;
+                schedule = Some(Schedule::interval(value, true)?);
+            }
+            "once" => {
+                // 起動時に1回だけ実行する
+                schedule = Some(Schedule::interval("1d", false)?);
+                runs = Some(1);
+            }
+            "runs" => {
+                let value = tokens.next().ok_or("missing number after \"runs\"")?;
+                runs = Some(
+                    value
+                        .parse::<u64>()
+                        .map_err(|_| format!("invalid number {:?}", value))?,
+                );
+            }
+            "to" => {
+                let value = tokens.next().ok_or("missing sinks after \"to\"")?;
+                for sink in value.split(',').filter(|v| !v.is_empty()) {
+                    sinks.push(Sink::parse(sink.trim())?);
+                }
+            }
+            _ => return Err(format!("unexpected {:?}", token)),
+        }
+    }
+    let schedule = schedule.ok_or("missing schedule (every <interval>, cron(...) or once)")?;
+
+    // 出力先の指定がない場合、現在の天気はこれまでと同じ処理、それ以外はファイルに追記する
+    if sinks.is_empty() {
+        sinks.push(match endpoint {
+            Endpoint::Weather => Sink::Collector,
+            _ => Sink::Jsonl,
+        });
+    }
+    if endpoint != Endpoint::Weather && sinks.contains(&Sink::Collector) {
+        return Err(String::from("the collector sink is only for weather"));
+    }
+
+    Ok(Job {
+        name: name.unwrap_or_else(|| endpoint.name().to_string()),
+        endpoint,
+        locations,
+        schedule,
+        runs,
+        sinks,
+    })
+}
+
+// 地点の緯度経度を地名検索で求める
+async fn resolve(
+    api_client: &ApiClient,
+    location_name: &str,
+) -> Result<(f64, f64), Box<dyn std::error::Error>> {
+    if let (_, Some(coord)) = store::parse_location(location_name) {
+        return Ok(coord);
+    }
+    let body = api_client.get_geocoding(location_name, 1).await?;
+    let value: Value = serde_json::from_str(&body)?;
+    let first = value
+        .get(0)
+        .ok_or_else(|| format!("location not found: {}", location_name))?;
+    let lat = first.get("lat").and_then(|v| v.as_f64()).unwrap_or(0.0);
+    let lon = first.get("lon").and_then(|v| v.as_f64()).unwrap_or(0.0);
+    Ok((lat, lon))
+}
+
+// APIのレスポンスを確認する（codやHTTPエラー時のmessageを返す）
+pub fn check_response(body: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    let value: Value = serde_json::from_str(body)?;
+    let cod = match value.get("cod") {
+        Some(Value::String(s)) => s.parse().unwrap_or(0),
+        Some(v) => v.as_i64().unwrap_or(0),
+        None => 200,
+    };
+    if cod != 200 {
+        let message = value.get("message").and_then(|v| v.as_str()).unwrap_or("");
+        return Err(format!("not 200 status ({}: {})", cod, message).into());
+    }
+    Ok(value)
+}
+
+// ジョブの実行中の状態
+struct JobRunner {
+    job: Job,
+    locations: Vec<String>,
+    api_client: Arc<ApiClient>,
+    collector: Arc<Mutex<Collector>>,
+    // 地名検索の結果（大気汚染の取得に使う）
+    coordinates: HashMap<String, (f64, f64)>,
+    // 実行回数と次の実行時刻を記録する
+    state: Arc<StateFile>,
+}
+
+impl JobRunner {
+    // 1地点分を取得して出力先に渡す
+    async fn run_location(
+        &mut self,
+        location_name: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let api_client = &self.api_client;
+        let data = match self.job.endpoint {
+            Endpoint::Weather => {
+                // collectorに渡す場合は地点ごとの取得結果を状態ファイルに記録する
+                let collect = self.job.sinks.contains(&Sink::Collector);
+                let record = match do_get_weather(api_client, location_name).await {
+                    Ok(v) => v,
+                    Err(e) => {
+                        if collect {
+                            self.state.failed(location_name, &e.to_string());
+                        }
+                        return Err(e);
+                    }
+                };
+                if collect {
+                    self.state.fetched(location_name, record.dt);
+                    self.collector.lock().await.process(location_name, &record);
+                }
+                serde_json::to_value(&record)?
+            }
+            Endpoint::Forecast => check_response(&api_client.get_forecast(location_name).await?)?,
+            Endpoint::Geocoding => {
+                check_response(&api_client.get_geocoding(location_name, 5).await?)?
+            }
+            Endpoint::AirQuality => {
+                let (lat, lon) = match self.coordinates.get(location_name) {
+                    Some(v) => *v,
+                    None => {
+                        let v = resolve(api_client, location_name).await?;
+                        self.coordinates.insert(location_name.to_string(), v);
+                        v
+                    }
+                };
+                check_response(&api_client.get_air_quality(lat, lon).await?)?
+            }
+        };
+
+        let line = json!({
+            "job": self.job.name,
+            "endpoint": self.job.endpoint.name(),
+            "location": location_name,
+            "fetched_at": Local::now().timestamp(),
+            "data": data,
+        })
+        .to_string();
+        for sink in &self.job.sinks {
+            match sink {
+                Sink::Collector => {}
+                Sink::Stdout => println!("{}", line),
+                Sink::Jsonl => {
+                    let path = Path::new(store::LOG_DIR).join(format!("{}.jsonl", self.job.name));
+                    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                    writeln!(file, "{}", line)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // スケジュールに従って終了条件に達するまで実行する
+    // 終了を指示された場合は取得中の地点まで処理して終了する
+    async fn run(mut self, mut scheduler: Scheduler, mut stop: watch::Receiver<bool>) {
+        let mut run = 0;
+        loop {
+            run += 1;
+            for location_name in self.locations.clone() {
+                if *stop.borrow() {
+                    return;
+                }
+                let span = tracing::info_span!("job", job = %self.job.name, run);
+                if let Err(e) = self.run_location(&location_name).instrument(span).await {
+                    tracing::warn!(
+                        location = %location_name,
+                        error = %secrets::redact(&e.to_string()),
+                        "job failed"
+                    );
+                }
+            }
+            scheduler.mark_run();
+
+            let next = scheduler.next(Local::now());
+            self.state.scheduled(Some(&self.job.name), &scheduler, next);
+            match next {
+                Some(next) => tokio::select! {
+                    _ = tokio::time::sleep(schedule::wait_time(next)) => {}
+                    _ = stop.changed() => return,
+                },
+                None => break,
+            }
+        }
+    }
+}
+
+// 全てのジョブをそれぞれのスケジュールで並行して実行し、全て終了するまで待つ
+// APIの回数制限はapi_clientを通して全てのジョブで共有する
+// シグナルを受けた場合は全てのジョブに終了を指示し、取得中の処理を待ってから戻る
+pub async fn run(
+    jobs: Vec<(Job, Scheduler)>,
+    api_client: Arc<ApiClient>,
+    collector: Arc<Mutex<Collector>>,
+    default_locations: &[String],
+    signals: &mut Signals,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (stop, stop_rx) = watch::channel(false);
+    let mut tasks = JoinSet::new();
+    let state = Arc::clone(&collector.lock().await.state);
+    for (job, mut scheduler) in jobs {
+        // 前回の実行が終了していない場合は開始時刻と実行回数を引き継ぐ
+        state.resume(Some(&job.name), &mut scheduler);
+        let locations = if job.locations.is_empty() {
+            default_locations.to_vec()
+        } else {
+            job.locations.clone()
+        };
+        let runner = JobRunner {
+            job,
+            locations,
+            api_client: Arc::clone(&api_client),
+            collector: Arc::clone(&collector),
+            coordinates: HashMap::new(),
+            state: Arc::clone(&state),
+        };
+        tasks.spawn(runner.run(scheduler, stop_rx.clone()));
+    }
+
+    // ジョブが異常終了した場合も残りのジョブに終了を指示し、終了を待ってからエラーを返す
+    let mut failure = None;
+    while !signals.interrupted() {
+        tokio::select! {
+            result = tasks.join_next() => match result {
+                Some(Ok(())) => {}
+                Some(Err(e)) => {
+                    failure = Some(e);
+                    break;
+                }
+                None => return Ok(()),
+            },
+            _ = signals.recv() => {}
+        }
+    }
+
+    let _ = stop.send(true);
+    let finished = signals
+        .guard(async { while tasks.join_next().await.is_some() {} })
+        .await;
+    if finished.is_none() {
+        tasks.shutdown().await;
+    }
+    match failure {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_job_list() {
+        let jobs = parse_jobs(
+            "current: weather every 10m; forecast for Osaka,Kyoto every 1h runs 3 to jsonl,stdout; \
+             air_quality cron(*/30 * * * *); geocoding once",
+        )
+        .unwrap();
+        assert_eq!(jobs.len(), 4);
+
+        assert_eq!(jobs[0].name, "current");
+        assert_eq!(jobs[0].endpoint, Endpoint::Weather);
+        assert!(jobs[0].locations.is_empty());
+        assert!(matches!(
+            jobs[0].schedule,
+            Schedule::Interval {
+                secs: 600,
+                aligned: true
+            }
+        ));
+        assert_eq!(jobs[0].runs, None);
+        assert_eq!(jobs[0].sinks, vec![Sink::Collector]);
+
+        // 名前を省略した場合はエンドポイント名
+        assert_eq!(jobs[1].name, "forecast");
+        assert_eq!(jobs[1].locations, vec!["Osaka", "Kyoto"]);
+        assert_eq!(jobs[1].runs, Some(3));
+        assert_eq!(jobs[1].sinks, vec![Sink::Jsonl, Sink::Stdout]);
+
+        assert_eq!(jobs[2].endpoint, Endpoint::AirQuality);
+        assert!(matches!(jobs[2].schedule, Schedule::Cron(_)));
+        assert_eq!(jobs[2].sinks, vec![Sink::Jsonl]);
+
+        assert_eq!(jobs[3].runs, Some(1));
+        assert!(matches!(
+            jobs[3].schedule,
+            Schedule::Interval { aligned: false, .. }
+        ));
+
+        // 空の定義は無視する
+        assert!(parse_jobs(" ; ").unwrap().is_empty());
+        // 名前と「:」の間に空白がなくてもよい
+        assert_eq!(parse_jobs("now:weather once").unwrap()[0].name, "now");
+    }
+
+    #[test]
+    fn parse_job_errors() {
+        let cases = [
+            ("every 1h", "unknown endpoint \"every\""),
+            ("weather", "missing schedule"),
+            ("weather every", "missing interval after \"every\""),
+            ("weather every 0s", "must be greater than 0"),
+            ("weather cron(* * *", "missing \")\" after cron("),
+            ("weather once runs x", "invalid number \"x\""),
+            ("weather once to file", "unknown sink \"file\""),
+            (
+                "forecast once to collector",
+                "the collector sink is only for weather",
+            ),
+            ("weather once hourly", "unexpected \"hourly\""),
+            (
+                "weather once; weather every 1h",
+                "duplicate name \"weather\"",
+            ),
+        ];
+        for (value, expected) in cases {
+            let error = parse_jobs(value).err().unwrap();
+            assert!(error.contains(expected), "{:?}: {:?}", value, error);
+        }
+    }
+}
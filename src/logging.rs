@@ -0,0 +1,124 @@
+use std::fs::OpenOptions;
+use std::sync::{Mutex, OnceLock};
+
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
+
+use crate::daemon;
+use crate::env_or;
+
+// 出力する水準（SIGHUPで変更できるよう保持する）
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+// LOG_LEVEL（error / warn / info / debug / trace、または「openweather_client=debug」などの指定）
+fn filter() -> Result<EnvFilter, String> {
+    parse_filter(&env_or("LOG_LEVEL", "info"))
+}
+
+fn parse_filter(level: &str) -> Result<EnvFilter, String> {
+    EnvFilter::try_new(level).map_err(|e| format!("LOG_LEVEL: {}: {}", level, e))
+}
+
+// LOG_FORMAT（text / json）。jsonの場合はtrue
+fn parse_format(value: &str) -> Result<bool, String> {
+    match value {
+        "text" => Ok(false),
+        "json" => Ok(true),
+        v => Err(format!("LOG_FORMAT: invalid value {:?} (text, json)", v)),
+    }
+}
+
+// ログの出力を開始する
+// 天気の表示（標準出力）と混ざらないよう、ログは標準エラー出力（デーモンの場合はLOG_FILE）に書く
+// ダッシュボードは画面全体を使うため、LOG_FILEに追記する
+// LOG_FORMATがjsonの場合は1行1件のJSON、それ以外は人が読む形式で出力する
+pub fn init() -> Result<(), String> {
+    let (filter, handle) = reload::Layer::new(filter()?);
+    let json = parse_format(&env_or("LOG_FORMAT", "text"))?;
+    let (writer, ansi) = if env_or("DISPLAY_MODE", "plain") == "dashboard" {
+        let path = daemon::log_path();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("LOG_FILE {}: {}", path.display(), e))?;
+        (BoxMakeWriter::new(Mutex::new(file)), false)
+    } else {
+        (
+            BoxMakeWriter::new(std::io::stderr),
+            termion::is_tty(&std::io::stderr()) && !daemon::is_enabled(),
+        )
+    };
+    let output = if json {
+        tracing_subscriber::fmt::layer()
+            .json()
+            .with_writer(writer)
+            .boxed()
+    } else {
+        tracing_subscriber::fmt::layer()
+            .with_writer(writer)
+            .with_ansi(ansi)
+            .boxed()
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(output)
+        .try_init()
+        .map_err(|e| e.to_string())?;
+    let _ = FILTER.set(handle);
+    Ok(())
+}
+
+// 設定を読み込み直した後にLOG_LEVELを反映する（LOG_FORMATは起動時のまま）
+pub fn reload() -> Result<(), String> {
+    match FILTER.get() {
+        Some(handle) => handle.reload(filter()?).map_err(|e| e.to_string()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_level() {
+        for level in [
+            "error",
+            "warn",
+            "info",
+            "debug",
+            "trace",
+            "openweather_client=debug",
+        ] {
+            assert!(parse_filter(level).is_ok(), "{}", level);
+        }
+        assert_eq!(
+            parse_filter("openweather_client=trace,warn")
+                .unwrap()
+                .to_string(),
+            "openweather_client=trace,warn"
+        );
+        let error = parse_filter("openweather_client=loud").unwrap_err();
+        assert!(
+            error.starts_with("LOG_LEVEL: openweather_client=loud: "),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn parse_log_format() {
+        assert_eq!(parse_format("text"), Ok(false));
+        assert_eq!(parse_format("json"), Ok(true));
+        assert_eq!(
+            parse_format("JSON"),
+            Err(String::from(
+                "LOG_FORMAT: invalid value \"JSON\" (text, json)"
+            ))
+        );
+        assert!(parse_format("").is_err());
+    }
+}
@@ -1,72 +1,257 @@
 use chrono::{DateTime, Local, TimeZone};
-use dotenvy::dotenv;
+use clap::Parser;
 use reqwest::Client;
 use std::collections::HashMap;
-use std::env;
 use std::fs::File;
 use std::io::stdout;
 use std::io::Write;
+use std::path::{Path, PathBuf};
 
 use termion::clear;
 
 use serde_json::{json, Value};
 
-use viuer::{print_from_file, Config};
-
+mod alerts;
 mod api;
+mod backfill;
+mod bar;
+mod chart;
+mod cli;
+mod config;
+mod daemon;
+mod dashboard;
+mod forecast;
+mod icon;
+mod import;
+mod jobs;
+mod logging;
+mod mqtt;
+mod notify;
+mod ratelimit;
+mod report;
+mod schedule;
+mod secrets;
+mod server;
+mod setup;
+mod signals;
+mod state;
+mod store;
+mod systemd;
+mod template;
 use api::OpenWeaterToTsv;
-/* use api::Clouds;
-use api::Coord;
-use api::Main;
-use api::OpenWeatherResponse;
-use api::Sys;
-use api::Weather;
-use api::Wind; */
 
-use std::time::Instant;
-use std::{thread, time};
+use std::sync::Arc;
+
+use tokio::time::Instant;
+use tracing::Instrument;
+
+// 現在の設定値を取得する。未設定または空の場合はデフォルト値を返す
+pub fn env_or(key: &str, default: &str) -> String {
+    config::current().or(key, default)
+}
 
 // クライアント定義
 struct ApiClient {
     server: String,
     client: Client,
+    // metric / imperial / standard
+    units: String,
+    api_key: String,
+    // 全ての呼び出しで共有する回数制限
+    rate_limiter: Arc<ratelimit::RateLimiter>,
+}
+
+// 地点の指定をクエリパラメータにする（座標を指定した場合は地名で検索しない）
+fn location_params(location_name: &str) -> HashMap<&'static str, String> {
+    let mut params = HashMap::new();
+    match store::parse_location(location_name) {
+        (_, Some((lat, lon))) => {
+            params.insert("lat", lat.to_string());
+            params.insert("lon", lon.to_string());
+        }
+        (name, None) => {
+            params.insert("q", name.to_string());
+        }
+    }
+    params
 }
 
 // クライアント実装
 impl ApiClient {
-    async fn get_weather(&self) -> Result<String, Box<dyn std::error::Error>> {
-        // APIキーをebvファイルを取得する。
-        let api_key = env::var("API_KEY").expect("API KEY env error...");
-        let location_name = env::var("LOCATION_NAME").expect("LOCATION NAME env error...");
+    fn new(
+        server: String,
+        units: String,
+        api_key: String,
+        rate_limiter: Arc<ratelimit::RateLimiter>,
+    ) -> Self {
+        ApiClient {
+            server,
+            client: Client::new(),
+            units,
+            api_key,
+            rate_limiter,
+        }
+    }
+
+    // 回数制限を守ってAPIを呼び出し、レスポンスを文字列で返す
+    // APIキー、単位、言語は共通で付ける
+    // 呼び出しごとにエンドポイント、地点、所要時間、ステータス、サイズをログに出力する
+    async fn get(
+        &self,
+        endpoint: &'static str,
+        server: &str,
+        mut params: HashMap<&str, String>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let location = match (params.get("q"), params.get("lat"), params.get("lon")) {
+            (Some(q), _, _) => q.clone(),
+            (None, Some(lat), Some(lon)) => format!("{},{}", lat, lon),
+            _ => String::new(),
+        };
+        params.insert("units", self.units.clone());
+        params.insert("lang", env_or("WEATHER_LANG", "ja"));
+        params.insert("appid", self.api_key.clone());
+        self.rate_limiter.acquire().await;
+
+        let span = tracing::info_span!("fetch", endpoint = %endpoint, location = %location);
+        async {
+            let started = Instant::now();
+            // 非同期でJSONデータを取得。
+            let resp = match self.client.get(server).query(&params).send().await {
+                Ok(v) => v,
+                Err(e) => {
+                    tracing::warn!(
+                        latency_ms = started.elapsed().as_millis() as u64,
+                        error = %secrets::redact(&e.to_string()),
+                        "request failed"
+                    );
+                    return Err(e.into());
+                }
+            };
+            let status = resp.status();
+            // レスポンスから文字列で受け取る。
+            let body = resp.text().await?;
+            let latency_ms = started.elapsed().as_millis() as u64;
+            if status.is_success() {
+                tracing::info!(
+                    latency_ms,
+                    status = status.as_u16(),
+                    bytes = body.len(),
+                    "fetched"
+                );
+            } else {
+                tracing::warn!(
+                    latency_ms,
+                    status = status.as_u16(),
+                    bytes = body.len(),
+                    "fetched"
+                );
+            }
+
+            Ok(body)
+        }
+        .instrument(span)
+        .await
+    }
+
+    async fn get_weather(&self, location_name: &str) -> Result<String, Box<dyn std::error::Error>> {
         // HashMapにQueryParamを設定。
+        let params = location_params(location_name);
+        self.get("weather", &self.server, params).await
+    }
+
+    // 5日間/3時間ごとの予報を取得する
+    async fn get_forecast(
+        &self,
+        location_name: &str,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let server = env_or(
+            "FORECAST_URL",
+            "https://api.openweathermap.org/data/2.5/forecast",
+        );
+        let params = location_params(location_name);
+        self.get("forecast", &server, params).await
+    }
+
+    // 地名を検索する
+    async fn get_geocoding(
+        &self,
+        location_name: &str,
+        limit: u32,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let server = env_or(
+            "GEOCODING_URL",
+            "https://api.openweathermap.org/geo/1.0/direct",
+        );
         let mut params = HashMap::new();
-        params.insert("q", location_name);
-        params.insert("units", "metric".to_string());
-        params.insert("lang", "ja".to_string());
-        params.insert("appid", api_key);
-        // 非同期でJSONデータを取得。
-        let resp = self
-            .client
-            .get(format!("{}", self.server))
-            .query(&params)
-            .send()
-            .await?;
-        // レスポンスから文字列で受け取る。
-        let body = resp.text().await?;
-
-        Ok(body)
+        params.insert("q", location_name.to_string());
+        params.insert("limit", limit.to_string());
+        self.get("geocoding", &server, params).await
+    }
+
+    // 大気汚染（AQI、PM2.5など）を取得する
+    async fn get_air_quality(
+        &self,
+        lat: f64,
+        lon: f64,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let server = env_or(
+            "AIR_POLLUTION_URL",
+            "https://api.openweathermap.org/data/2.5/air_pollution",
+        );
+        let mut params = HashMap::new();
+        params.insert("lat", lat.to_string());
+        params.insert("lon", lon.to_string());
+        self.get("air_quality", &server, params).await
+    }
+
+    // 指定した時刻の天気を取得する（One Call API 3.0のtimemachine）
+    async fn get_timemachine(
+        &self,
+        lat: f64,
+        lon: f64,
+        dt: i64,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let server = env_or(
+            "ONECALL_URL",
+            "https://api.openweathermap.org/data/3.0/onecall/timemachine",
+        );
+        let mut params = HashMap::new();
+        params.insert("lat", lat.to_string());
+        params.insert("lon", lon.to_string());
+        params.insert("dt", dt.to_string());
+        self.get("timemachine", &server, params).await
     }
-}
 
-async fn do_get_weather(api_client: ApiClient) -> Result<(), Box<dyn std::error::Error>> {
-    let body = api_client.get_weather().await?;
-    // JSON文字列を構造体にデシリアライズする。
-    /* let deserialize: OpenWeatherResponse =
-        serde_json::from_str(&body).expect("deserialize error...");
+    // 期間内の1時間ごとの天気を取得する（History API）
+    async fn get_history(
+        &self,
+        lat: f64,
+        lon: f64,
+        start: i64,
+        end: i64,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let server = env_or(
+            "HISTORY_URL",
+            "https://history.openweathermap.org/data/2.5/history/city",
+        );
+        let mut params = HashMap::new();
+        params.insert("lat", lat.to_string());
+        params.insert("lon", lon.to_string());
+        params.insert("type", String::from("hour"));
+        params.insert("start", start.to_string());
+        params.insert("end", end.to_string());
+        self.get("history", &server, params).await
+    }
+}
 
-    println!("response to struct: {:?}", deserialize); */
+async fn do_get_weather(
+    api_client: &ApiClient,
+    location_name: &str,
+) -> Result<OpenWeaterToTsv, Box<dyn std::error::Error>> {
+    let body = api_client.get_weather(location_name).await?;
 
     // JSON文字列を取り出せるようにvalueにする
+    // 構造体をやめたjson valueで取る(OpenWeatherのAPIのJson定義で1hという名前で構造体を作成できず。。。)
     let deserialize: Value = serde_json::from_str(&body)?;
     //println!("test struct: {:?}", deserialize);
 
@@ -75,11 +260,18 @@ async fn do_get_weather(api_client: ApiClient) -> Result<(), Box<dyn std::error:
     // 内部パラメータ ステータスコード200じゃない場合は終了
     let cod = deserialize.get("cod");
     if let Some(v) = cod {
-        openweather_to_tsv.cod = v.as_i64().unwrap();
-        println!("cod: {}", v.as_i64().unwrap());
-        if v.as_i64().unwrap() != 200 {
-            println!("not 200 status\ncheck config");
-            panic!();
+        // エラー時は文字列で返ってくることがある
+        let cod = match v {
+            Value::String(s) => s.parse().unwrap_or(0),
+            _ => v.as_i64().unwrap_or(0),
+        };
+        openweather_to_tsv.cod = cod;
+        if cod != 200 {
+            let message = deserialize
+                .get("message")
+                .and_then(|v| v.as_str())
+                .unwrap_or("");
+            return Err(format!("not 200 status ({}: {})\ncheck config", cod, message).into());
         }
     }
 
@@ -87,14 +279,12 @@ async fn do_get_weather(api_client: ApiClient) -> Result<(), Box<dyn std::error:
     let lat = deserialize.get("coord").and_then(|v| v.get("lat"));
     if let Some(v) = lat {
         openweather_to_tsv.lat = v.as_f64().unwrap();
-        println!("lat: {:?}", v.as_f64().unwrap());
     }
 
     // 都市の地理的位置、緯度
     let lon = deserialize.get("coord").and_then(|v| v.get("lon"));
     if let Some(v) = lon {
         openweather_to_tsv.lon = v.as_f64().unwrap();
-        println!("lon: {:?}", v.as_f64().unwrap());
     }
 
     let weather = deserialize.get("weather");
@@ -104,198 +294,168 @@ async fn do_get_weather(api_client: ApiClient) -> Result<(), Box<dyn std::error:
         for v in weather_vec {
             weather_val = v
         }
-        println!("weather info count: {}", weather_vec.len());
     }
 
     // 気象条件ID
     let weather_id = weather_val.get("id");
     if let Some(v) = weather_id {
         openweather_to_tsv.weather_to_id = v.as_i64().unwrap();
-        println!("id: {}", v.as_i64().unwrap());
     }
 
     // 気象パラメータのグループ（雨、雪、極端など）
     let weather_main = weather_val.get("main");
     if let Some(v) = weather_main {
         openweather_to_tsv.weather_to_main = v.as_str().unwrap().to_string();
-        println!("main: {}", v.as_str().unwrap());
     }
 
     // グループ内の気象条件。あなたの言語で出力を得ることができます。
     let weather_description = weather_val.get("description");
     if let Some(v) = weather_description {
         openweather_to_tsv.description = v.as_str().unwrap().to_string();
-        println!("description: {}", v.as_str().unwrap());
     }
 
     // Weather icon id
     let weather_icon = weather_val.get("icon");
-    let mut icon_name: &str = "";
     if let Some(v) = weather_icon {
         openweather_to_tsv.icon = v.as_str().unwrap().to_string();
-        println!("icon: {}", v.as_str().unwrap());
-        icon_name = v.as_str().unwrap();
     }
 
     // 内部パラメータ
     let base = deserialize.get("base");
     if let Some(v) = base {
         openweather_to_tsv.base = v.as_str().unwrap().to_string();
-        println!("base: {}", v.as_str().unwrap());
     }
 
     // 温度。単位デフォルト：ケルビン、メートル法：摂氏、インペリアル：華氏。
     let temp = deserialize.get("main").and_then(|v| v.get("temp"));
     if let Some(v) = temp {
         openweather_to_tsv.temp = v.as_f64().unwrap();
-        println!("temp: {}", v.as_f64().unwrap());
     }
 
     // 温度。この温度パラメータは、人間の天気の知覚を説明します。単位デフォルト：ケルビン、メートル法：摂氏、インペリアル：華氏。
     let feels_like = deserialize.get("main").and_then(|v| v.get("feels_like"));
     if let Some(v) = feels_like {
         openweather_to_tsv.feels_like = v.as_f64().unwrap();
-        println!("feels_like: {}", v.as_f64().unwrap());
     }
 
     // 最低気温
     let temp_min = deserialize.get("main").and_then(|v| v.get("temp_min"));
     if let Some(v) = temp_min {
         openweather_to_tsv.temp_min = v.as_f64().unwrap();
-        println!("temp_min: {}", v.as_f64().unwrap());
     }
 
     // 最高気温
     let temp_max = deserialize.get("main").and_then(|v| v.get("temp_max"));
     if let Some(v) = temp_max {
         openweather_to_tsv.temp_max = v.as_f64().unwrap();
-        println!("temp_max: {}", v.as_f64().unwrap());
     }
 
     // 大気圧（sea_levelまたはgrnd_levelデータがない場合は、海面上）、hPa
     let pressure = deserialize.get("main").and_then(|v| v.get("pressure"));
     if let Some(v) = pressure {
         openweather_to_tsv.pressure = v.as_i64().unwrap();
-        println!("pressure: {}", v.as_i64().unwrap());
     }
 
     // 海面の大気圧、hPa
     let sea_level = deserialize.get("main").and_then(|v| v.get("sea_level"));
     if let Some(v) = sea_level {
         openweather_to_tsv.sea_level = v.as_i64().unwrap();
-        println!("sea_level: {}", v.as_i64().unwrap());
     }
 
     // 地表面の大気圧、hPa
     let grnd_level = deserialize.get("main").and_then(|v| v.get("grnd_level"));
     if let Some(v) = grnd_level {
         openweather_to_tsv.grnd_level = v.as_i64().unwrap();
-        println!("grnd_level: {}", v.as_f64().unwrap());
     }
 
     // 湿度、％
     let humidity = deserialize.get("main").and_then(|v| v.get("humidity"));
     if let Some(v) = humidity {
         openweather_to_tsv.humidity = v.as_i64().unwrap();
-        println!("humidity: {}", v.as_f64().unwrap());
     }
 
     // 視程、メーター。視程の最大値は10kmです
     let visibility = deserialize.get("visibility");
     if let Some(v) = visibility {
         openweather_to_tsv.visibility = v.as_i64().unwrap();
-        println!("visibility: {}", v.as_i64().unwrap());
     }
 
     // 風速。単位推：メートル/秒、メートル法：メートル/秒、インペリアル：マイル/時。
     let speed = deserialize.get("wind").and_then(|v| v.get("speed"));
     if let Some(v) = speed {
         openweather_to_tsv.speed = v.as_f64().unwrap();
-        println!("speed: {}", v.as_f64().unwrap());
     }
 
     // 風向、度（気象）
     let deg = deserialize.get("wind").and_then(|v| v.get("deg"));
     if let Some(v) = deg {
         openweather_to_tsv.deg = v.as_i64().unwrap();
-        println!("deg: {}", v.as_i64().unwrap());
     }
 
     // 突風。単位デフォルト：メートル/秒、メートル法：メートル/秒、インペリアル：マイル/時
     let gust = deserialize.get("wind").and_then(|v| v.get("gust"));
     if let Some(v) = gust {
         openweather_to_tsv.gust = v.as_f64().unwrap();
-        println!("gust: {}", v.as_f64().unwrap());
     }
 
     // 曇り、％
     let clouds_all = deserialize.get("clouds").and_then(|v| v.get("all"));
     if let Some(v) = clouds_all {
         openweather_to_tsv.all = v.as_i64().unwrap();
-        println!("clouds all: {}", v.as_i64().unwrap());
     }
 
     // 過去1時間の雨量、mm
-    let rain_h1 = deserialize.get("rain").and_then(|v| v.get("h1"));
+    let rain_h1 = deserialize.get("rain").and_then(|v| v.get("1h"));
     if let Some(v) = rain_h1 {
         openweather_to_tsv.rain_1h = v.as_f64().unwrap();
-        println!("rain h1: {}", v.as_i64().unwrap());
     }
 
     // 過去3時間の雨量、mm
-    let rain_h3 = deserialize.get("rain").and_then(|v| v.get("h3"));
+    let rain_h3 = deserialize.get("rain").and_then(|v| v.get("3h"));
     if let Some(v) = rain_h3 {
         openweather_to_tsv.rain_3h = v.as_f64().unwrap();
-        println!("rain h3: {}", v.as_f64().unwrap());
     }
 
     // 過去1時間の積雪量、mm
-    let snow_h1 = deserialize.get("snow").and_then(|v| v.get("h1"));
+    let snow_h1 = deserialize.get("snow").and_then(|v| v.get("1h"));
     if let Some(v) = snow_h1 {
         openweather_to_tsv.snow_h1 = v.as_f64().unwrap();
-        println!("sbow h1: {}", v.as_f64().unwrap());
     }
 
     // 過去3時間の積雪量、mm
-    let snow_h3 = deserialize.get("snow").and_then(|v| v.get("h3"));
+    let snow_h3 = deserialize.get("snow").and_then(|v| v.get("3h"));
     if let Some(v) = snow_h3 {
         openweather_to_tsv.snow_h3 = v.as_f64().unwrap();
-        println!("snow h3: {}", v.as_f64().unwrap());
     }
 
     // データ計算の時間、UNIX、UTC
     let dt = deserialize.get("dt");
     if let Some(v) = dt {
         openweather_to_tsv.dt = v.as_i64().unwrap();
-        println!("dt: {}", v.as_i64().unwrap());
     }
 
     // 内部パラメータ
     let sys_type = deserialize.get("sys").and_then(|v| v.get("type"));
     if let Some(v) = sys_type {
         openweather_to_tsv.r#type = v.as_i64().unwrap();
-        println!("sys type: {}", v.as_i64().unwrap());
     }
 
     // 内部パラメータ
     let sys_id = deserialize.get("sys").and_then(|v| v.get("id"));
     if let Some(v) = sys_id {
         openweather_to_tsv.sys_to_id = v.as_i64().unwrap();
-        println!("sys id: {}", v.as_i64().unwrap());
     }
 
     // 内部パラメータ
     let sys_message = deserialize.get("sys").and_then(|v| v.get("message"));
     if let Some(v) = sys_message {
         openweather_to_tsv.message = v.as_f64().unwrap();
-        println!("sys message: {:?}", v.as_f64());
     }
 
     // Country code (GB, JP etc.)
     let sys_country = deserialize.get("sys").and_then(|v| v.get("country"));
     if let Some(v) = sys_country {
         openweather_to_tsv.country = v.as_str().unwrap().to_string();
-        println!("sys country: {}", v.as_str().unwrap());
     }
 
     // 日の出時刻、UNIX、UTC
@@ -303,7 +463,6 @@ async fn do_get_weather(api_client: ApiClient) -> Result<(), Box<dyn std::error:
     if let Some(v) = sys_sunrise {
         let dt1: DateTime<Local> = Local.timestamp(v.as_i64().unwrap(), 0);
         openweather_to_tsv.sunrise = dt1.format("%H:%M:%S").to_string();
-        println!("sunrise: {}", dt1.format("%H:%M:%S"));
     }
 
     // 日没時間、UNIX、UTC
@@ -311,402 +470,1101 @@ async fn do_get_weather(api_client: ApiClient) -> Result<(), Box<dyn std::error:
     if let Some(v) = sys_sunset {
         let dt1: DateTime<Local> = Local.timestamp(v.as_i64().unwrap(), 0);
         openweather_to_tsv.sunset = dt1.format("%H:%M:%S").to_string();
-        println!("sunset: {}", dt1.format("%H:%M:%S"));
     }
 
     // UTCから秒単位でシフト
     let timezone = deserialize.get("timezone");
     if let Some(v) = timezone {
         openweather_to_tsv.timezone = v.as_i64().unwrap();
-        println!("timezone: {}", v.as_i64().unwrap());
     }
 
     // City ID
     let id = deserialize.get("id");
     if let Some(v) = id {
         openweather_to_tsv.id = v.as_i64().unwrap();
-        println!("id: {}", v.as_i64().unwrap());
     }
 
     // City name
     let name = deserialize.get("name");
     if let Some(v) = name {
         openweather_to_tsv.name = v.as_str().unwrap().to_string();
-        println!("name: {}", v.as_str().unwrap());
-    }
-
-    // 天気アイコンを表示
-    if icon_name != "" {
-        let icon_path = format!("./assets/{}.png", icon_name);
-        let conf = Config {
-            width: Some(20),
-            height: Some(10),
-            x: 30,
-            y: 1,
-            ..Default::default()
-        };
-        print_from_file(icon_path, &conf).expect("icon error");
+    }
+
+    Ok(openweather_to_tsv)
+}
+
+// 取得結果を標準出力に表示する
+fn print_weather(
+    openweather_to_tsv: &OpenWeaterToTsv,
+    icons: &icon::IconSet,
+) -> std::io::Result<()> {
+    let v = openweather_to_tsv;
+    println!("cod: {}", v.cod);
+    println!("lat: {:?}", v.lat);
+    println!("lon: {:?}", v.lon);
+    println!("id: {}", v.weather_to_id);
+    println!("main: {}", v.weather_to_main);
+    println!("description: {}", v.description);
+    println!("icon: {}", v.icon);
+    println!("base: {}", v.base);
+    println!("temp: {}", v.temp);
+    println!("feels_like: {}", v.feels_like);
+    println!("temp_min: {}", v.temp_min);
+    println!("temp_max: {}", v.temp_max);
+    println!("pressure: {}", v.pressure);
+    println!("sea_level: {}", v.sea_level);
+    println!("grnd_level: {}", v.grnd_level);
+    println!("humidity: {}", v.humidity);
+    println!("visibility: {}", v.visibility);
+    println!("speed: {}", v.speed);
+    println!("deg: {}", v.deg);
+    println!("gust: {}", v.gust);
+    println!("clouds all: {}", v.all);
+    println!("rain h1: {}", v.rain_1h);
+    println!("rain h3: {}", v.rain_3h);
+    println!("snow h1: {}", v.snow_h1);
+    println!("snow h3: {}", v.snow_h3);
+    println!("dt: {}", v.dt);
+    println!("sys type: {}", v.r#type);
+    println!("sys id: {}", v.sys_to_id);
+    println!("sys message: {:?}", v.message);
+    println!("sys country: {}", v.country);
+    println!("sunrise: {}", v.sunrise);
+    println!("sunset: {}", v.sunset);
+    println!("timezone: {}", v.timezone);
+    println!("id: {}", v.id);
+    println!("name: {}", v.name);
+
+    // 天気アイコンを表示（端末の場合は右上に、それ以外は続けて出力する）
+    let mut stdout = stdout();
+    if termion::is_tty(&stdout) {
+        // 描画後はカーソルを元の位置（一覧の末尾）に戻す
+        write!(stdout, "{}", termion::cursor::Save)?;
+        icons.draw(&mut stdout, &v.icon, 31, 2)?;
+        write!(stdout, "{}", termion::cursor::Restore)?;
+        stdout.flush()?;
     } else {
-        println!("No Icon");
+        icons.print_plain(&v.icon);
     }
+    Ok(())
+}
 
-    // 構造体をやめたjson valueで取る(OpenWeatherのAPIのJson定義で1hという名前で構造体を作成できず。。。)
-    // Coord Info
-    /* let coord = deserialize.coord;
-    let coord = match coord {
-        None => Coord { lon: 0.0, lat: 0.0 },
-        Some(val) => val,
-    };
-    let lon = coord.lon;
-    let lat = coord.lat;
-    println!("lo: {}, lat: {}", lon, lat);
+// 1行表示のテンプレート（OUTPUT_TEMPLATE）。デフォルトの気温の単位は取得時の指定に従う
+fn output_template(units: &str) -> String {
+    let (temp_unit, _) = api::unit_labels(units);
+    env_or(
+        "OUTPUT_TEMPLATE",
+        &format!("{{name}} {{temp:.1}}{} {{description}}", temp_unit),
+    )
+}
 
-    // Weater Info
-    let westher_vec = deserialize.weather;
-    let westher_vec = match westher_vec {
-        None => {
-            let weather_none = Weather {
-                id: 0,
-                main: String::from(""),
-                description: String::from(""),
-                icon: String::from(""),
-            };
-            let mut weather_none_vec = vec![];
-            weather_none_vec.push(weather_none);
-            weather_none_vec
+// 現在の天気を1回だけ取得して表示する
+// 保存やアラート評価は行わない
+async fn print_now(
+    api_client: &ApiClient,
+    locations: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let display_mode = env_or("DISPLAY_MODE", "plain");
+    let output_template = output_template(&api_client.units);
+
+    let mut records = Vec::new();
+    for location_name in locations {
+        records.push(do_get_weather(api_client, location_name).await?);
+    }
+
+    match display_mode.as_str() {
+        // テンプレートで1行に整形する（tmuxやプロンプトへの埋め込み用）
+        "template" => {
+            for record in &records {
+                let value = template::record_value(record);
+                println!("{}", template::render(&output_template, &value));
+            }
         }
-        Some(val) => val,
-    };
-    let weather = &westher_vec[0];
-    let weather_to_id = weather.id;
-    let weather_to_main = &weather.main;
-    let description = &weather.description;
-    let icon = &weather.icon;
-    println!(
-        "id: {}, main: {}, description: {}, icon: {}",
-        weather_to_id, weather_to_main, description, icon
-    );
+        "json" => {
+            for record in &records {
+                println!("{}", serde_json::to_string(record)?);
+            }
+        }
+        "waybar" => {
+            let value = bar::waybar(&records, &output_template, &api_client.units);
+            println!("{}", value);
+        }
+        "i3bar" => {
+            let value = bar::i3bar(&records, &output_template, &api_client.units);
+            println!("{}", value);
+        }
+        "polybar" => println!("{}", bar::polybar(&records, &output_template)),
+        _ => {
+            let icons = icon::IconSet::from_values(&config::current())
+                .map_err(|e| format!("ICON_THEME_DIR: {}", e))?;
+            for record in &records {
+                print_weather(record, &icons)?;
+            }
+        }
+    }
 
-    // Base Info
-    let base = &deserialize.base;
-    let base = match base {
-        None => "",
-        Some(val) => val,
-    };
-    println!("base: {}", base);
-
-    // Main Info
-    let main = deserialize.main;
-    let main = match main {
-        None => Main {
-            temp: 0.0,
-            feels_like: 0.0,
-            temp_min: 0.0,
-            temp_max: 0.0,
-            pressure: 0,
-            humidity: 0,
-        },
-        Some(val) => val,
-    };
-    let temp = main.temp;
-    let feels_like = main.feels_like;
-    let temp_min = main.temp_min;
-    let temp_max = main.temp_max;
-    let pressure = main.pressure;
-    let humidity = main.humidity;
-    println!(
-        "temp: {}, feels_like: {}, temp_min: {}, temp_max: {}, pressure: {}, humidity: {}",
-        temp, feels_like, temp_min, temp_max, pressure, humidity
-    );
+    Ok(())
+}
 
-    // Visibility Info
-    let visibility = deserialize.visibility;
-    let visibility = match visibility {
-        None => 0,
-        Some(val) => val,
-    };
-    println!("visibility: {}", visibility);
+// 予報を取得して表示する
+async fn print_forecast(
+    api_client: &ApiClient,
+    locations: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    for location_name in locations {
+        let body = api_client.get_forecast(location_name).await?;
+        let entries = forecast::parse(&body)?;
+        forecast::print(location_name, &entries, &api_client.units);
+    }
 
-    // Wind Info
-    let wind = deserialize.wind;
-    let wind = match wind {
-        None => Wind { speed: 0.0, deg: 0 },
-        Some(val) => val,
-    };
-    let speed = wind.speed;
-    let deg = wind.deg;
-    println!("speed: {}, deg: {}", speed, deg);
-
-    // Clouds Info
-    let clouds = deserialize.clouds;
-    let clouds = match clouds {
-        None => Clouds { all: 0 },
-        Some(val) => val,
-    };
-    let all = clouds.all;
-    println!("all {}", all);
-
-    // Dt Info
-    let dt = deserialize.dt;
-    let dt = match dt {
-        None => 0,
-        Some(val) => val,
+    Ok(())
+}
+
+// 保存済みの取得結果を書き出す（DISPLAY_MODEがjson/csvの場合はその形式、それ以外はtsv）
+fn export_records(
+    locations: &[String],
+    from: Option<String>,
+    to: Option<String>,
+    output: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let from = match from {
+        Some(v) => store::parse_time(&v).ok_or(format!("invalid time: {}", v))?,
+        None => i64::MIN,
     };
-    println!("dt: {}", dt);
-
-    // Sys Info
-    let sys = deserialize.sys;
-    let sys = match sys {
-        None => Sys {
-            r#type: 0,
-            id: 0,
-            message: Some(0.0),
-            country: String::from(""),
-            sunrise: 0,
-            sunset: 0,
-        },
-        Some(val) => val,
+    let to = match to {
+        Some(v) => store::parse_time(&v).ok_or(format!("invalid time: {}", v))?,
+        None => i64::MAX,
     };
-    let r#type = sys.r#type;
-    let sys_to_id = sys.id;
-    let message = sys.message;
-    let message = match message {
-        None => 0.0,
-        Some(val) => val,
+    let records: Vec<OpenWeaterToTsv> = store::load_records(Path::new(store::LOG_DIR))?
+        .into_iter()
+        .filter(|v| locations.iter().any(|l| store::is_location(v, l)))
+        .filter(|v| v.dt >= from && v.dt <= to)
+        .collect();
+
+    let out: Box<dyn Write> = match output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(stdout()),
     };
-    let country = &sys.country;
-    let sunrise = sys.sunrise;
-    let sunset = sys.sunset;
-    println!(
-        "type: {}, sys_to_id: {}, message: {}, country: {}, sunrise: {}, sunset: {}",
-        r#type, sys_to_id, message, country, sunrise, sunset
-    );
+    match env_or("DISPLAY_MODE", "tsv").as_str() {
+        "json" => {
+            let mut out = out;
+            for record in &records {
+                writeln!(out, "{}", serde_json::to_string(record)?)?;
+            }
+            out.flush()?;
+        }
+        format => {
+            let delimiter = if format == "csv" { b',' } else { b'\t' };
+            let mut wtr = csv::WriterBuilder::new()
+                .delimiter(delimiter)
+                .from_writer(out);
+            for record in records {
+                wtr.serialize(record)?;
+            }
+            wtr.flush()?;
+        }
+    }
 
-    // Timezone Info
-    let timezone = deserialize.timezone;
-    let timezone = match timezone {
-        None => 0,
-        Some(val) => val,
-    };
-    println!("timezone: {}", timezone);
+    Ok(())
+}
 
-    // Id Info
-    let id = deserialize.id;
-    let id = match id {
-        None => 0,
-        Some(val) => val,
-    };
-    println!("id: {}", id);
+// 現在の設定を表示する（APIキーは伏せる）
+fn print_config(loaded_config: Option<&config::LoadedConfig>, key_source: Option<&str>) {
+    match loaded_config {
+        Some(v) => println!(
+            "config file: {} (profile: {})",
+            v.path.display(),
+            v.profile.as_deref().unwrap_or("-")
+        ),
+        None => println!(
+            "config file: none ({})",
+            config::default_path()
+                .map(|v| v.display().to_string())
+                .unwrap_or_default()
+        ),
+    }
+    let keys = [
+        "OPEN_WEATHER_URL",
+        "FORECAST_URL",
+        "GEOCODING_URL",
+        "API_KEY_FILE",
+        "API_KEY_COMMAND",
+        "LOCATION_NAME",
+        "WEATHER_UNITS",
+        "WEATHER_LANG",
+        "DISPLAY_MODE",
+        "OUTPUT_TEMPLATE",
+        "INTERVAL",
+        "ALIGN",
+        "SCHEDULE",
+        "DURATION",
+        "UNTIL",
+        "RUNS",
+        "SHUTDOWN_TIMEOUT",
+        "JOBS",
+        "RATE_LIMIT",
+        "DAEMON",
+        "PID_FILE",
+        "LOG_FILE",
+        "LOG_MAX_SIZE",
+        "LOG_KEEP",
+        "LOG_LEVEL",
+        "LOG_FORMAT",
+        "STATE_FILE",
+        "RESUME",
+        "BACKFILL",
+        "BACKFILL_API",
+        "BACKFILL_WINDOW",
+        "ONECALL_URL",
+        "HISTORY_URL",
+        "REPORT_TIME",
+        "REPORT_PERIOD",
+        "TSV_OUT",
+        "SERVER_ADDR",
+        "MQTT_HOST",
+        "ALERT_RULES",
+        "HISTORY_WINDOW",
+        "CHART_STYLE",
+        "ICON_MODE",
+        "ICON_THEME_DIR",
+    ];
+    // APIキーは読み込み元のみ表示する
+    match key_source {
+        Some(source) => println!("{:<18}########### ({})", "API_KEY:", source),
+        None => println!("{:<18}(not set)", "API_KEY:"),
+    }
+    let values = config::current();
+    for key in keys {
+        let value = values.get(key).unwrap_or_default();
+        println!("{:<18}{}", format!("{}:", key), secrets::redact(value));
+    }
+}
 
-    // Name Info
-    let name = &deserialize.name;
-    let name = match name {
-        None => "",
-        Some(val) => val,
+// 状態ファイルの内容を表示する（DISPLAY_MODEがjsonの場合はそのまま出力する）
+fn print_status() -> Result<(), Box<dyn std::error::Error>> {
+    let path = state::path();
+    let state = match state::load(&path)? {
+        Some(v) => v,
+        None => {
+            println!("no state: {} (not started yet)", path.display());
+            return Ok(());
+        }
     };
-    println!("name: {}", name);
+    if env_or("DISPLAY_MODE", "plain") == "json" {
+        println!("{}", serde_json::to_string(&state)?);
+        return Ok(());
+    }
 
-    // Cod Info
-    let cod = deserialize.cod;
-    let cod = match cod {
-        None => 0,
-        Some(val) => val,
+    let time = |t: Option<i64>| match t {
+        Some(t) => Local
+            .timestamp(t, 0)
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string(),
+        None => String::from("-"),
     };
-    println!("cod: {}", cod);
-
-    let openweather_to_tsv = OpenWeaterToTsv::new(
-        lon,
-        lat,
-        weather_to_id,
-        weather_to_main,
-        description,
-        icon,
-        base,
-        temp,
-        feels_like,
-        temp_min,
-        temp_max,
-        pressure,
-        humidity,
-        visibility,
-        speed,
-        deg,
-        all,
-        dt,
-        r#type,
-        sys_to_id,
-        message,
-        country,
-        sunrise,
-        sunset,
-        timezone,
-        id,
-        name,
-        cod,
+    let running = if daemon::is_running(state.pid as i32) {
+        "running"
+    } else {
+        "not running"
+    };
+    println!("{:<12}{}", "state file:", path.display());
+    println!("{:<12}{}", "updated:", time(Some(state.updated)));
+    println!("{:<12}{} ({})", "pid:", state.pid, running);
+    let mut schedules: Vec<(String, &state::ScheduleState)> = Vec::new();
+    if let Some(schedule) = &state.schedule {
+        schedules.push((String::from("schedule:"), schedule));
+    }
+    for (name, schedule) in &state.jobs {
+        schedules.push((format!("job {}:", name), schedule));
+    }
+    for (label, schedule) in schedules {
+        let next = match schedule.next_run {
+            Some(_) => format!("next {}", time(schedule.next_run)),
+            None => String::from("finished"),
+        };
+        println!(
+            "{:<12}started {}, {} runs, {}",
+            label,
+            time(Some(schedule.started)),
+            schedule.runs,
+            next
+        );
+    }
+    if let Some(stopped) = &state.stopped {
+        println!(
+            "{:<12}{} ({}, {} notifications canceled)",
+            "stopped:",
+            time(Some(stopped.at)),
+            stopped.reason,
+            stopped.canceled
+        );
+    }
+
+    println!();
+    println!(
+        "{:<16}{:<21}{:<21}{:<10}last error",
+        "location", "last success", "last dt", "failures"
     );
+    for (name, location) in &state.locations {
+        println!(
+            "{:<16}{:<21}{:<21}{:<10}{}",
+            name,
+            time(location.last_success),
+            time(location.last_dt),
+            location.failures,
+            // 1行に収める
+            location
+                .last_error
+                .as_deref()
+                .unwrap_or("")
+                .replace('\n', " ")
+        );
+    }
 
-    */
+    Ok(())
+}
 
-    // 環境設定ファイルで出力するかを判定
-    let tsv_out_flg = env::var("TSV_OUT").expect("env error...");
-    if PartialEq::eq(&tsv_out_flg, "1") {
-        // tsvファイルの作成
-        weather_write_to_tsv(openweather_to_tsv).expect("tsv to write error...");
+// 保存済みの取得結果から地点ごとの推移をグラフで表示する
+fn print_history(locations: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let window = alerts::parse_duration(&env_or("HISTORY_WINDOW", "24h"))?;
+    let style = chart::ChartStyle::parse(&env_or("CHART_STYLE", "braille"));
+    let (width, _) = termion::terminal_size().unwrap_or((80, 24));
+
+    let records = store::load_records(Path::new(store::LOG_DIR))?;
+    for location_name in locations {
+        let records: Vec<OpenWeaterToTsv> = records
+            .iter()
+            .filter(|v| store::is_location(v, location_name))
+            .cloned()
+            .collect();
+        println!(
+            "{} (last {})",
+            location_name,
+            env_or("HISTORY_WINDOW", "24h")
+        );
+        println!();
+        for line in chart::render_history(&records, window, width as usize, 6, style) {
+            println!("{}", line);
+        }
     }
 
     Ok(())
 }
 
-// 環境設定し直す
-fn re_setting() -> std::io::Result<()> {
-    println!("\n");
-    println!("Enter the API KEY.");
-    let mut input_api_key = String::new();
-    std::io::stdin().read_line(&mut input_api_key).ok();
-    let result_apikey = input_api_key.trim().to_string();
-    println!("\n");
-    println!("Enter the API URL.");
-    let mut input_url = String::new();
-    std::io::stdin().read_line(&mut input_url).ok();
-    let result_url = input_url.trim().to_string();
-    println!("\n");
-    println!("Enter the API LOCATION.");
-    let mut input_location = String::new();
-    std::io::stdin().read_line(&mut input_location).ok();
-    let result_location = input_location.trim().to_string();
-
-    let env_file = format!(
-        "OPEN_WEATHER_URL={}\nAPI_KEY={}\nLOCATION_NAME={}",
-        result_url, result_apikey, result_location
-    );
+// 取得後の処理（配信・アラート評価・通知）をまとめたもの
+pub struct Collector {
+    api_client: Arc<ApiClient>,
+    app_state: server::AppState,
+    alert_engine: alerts::AlertEngine,
+    notifier: Arc<notify::Notifier>,
+    // 地点ごとの取得結果とスケジュールを記録する状態ファイル
+    state: Arc<state::StateFile>,
+}
 
-    let mut input_ans = String::new();
-    println!("\n");
-    println!("{}", env_file);
-    println!("Is it okay to reflect it in the settings? y or n");
-    std::io::stdin().read_line(&mut input_ans).ok();
-    let result_ans = input_ans.trim();
-
-    match result_ans {
-        "Y" | "y" => {
-            let file = File::create("./.env");
-            let mut file = match file {
-                Err(_) => panic!("not found filepath..."),
-                Ok(val) => val,
-            };
-            write!(file, "{}", env_file)?;
-            file.flush()?;
-            println!("\n");
-            println!("The settings have been reflected.");
-            println!("\n");
+impl Collector {
+    // 地点の天気を取得し、購読者への配信とアラート評価を行う
+    // 失敗した場合は連続して失敗した回数を記録してエラーを返す（呼び出し側は次の地点に進む）
+    pub async fn collect(
+        &mut self,
+        location_name: &str,
+    ) -> Result<(OpenWeaterToTsv, Vec<alerts::AlertEvent>), Box<dyn std::error::Error>> {
+        // 非同期でデータを受け取る
+        let openweather_to_tsv = match do_get_weather(&self.api_client, location_name).await {
+            Ok(v) => v,
+            Err(e) => {
+                let failures = self.state.failed(location_name, &e.to_string());
+                tracing::warn!(
+                    location = %location_name,
+                    failures,
+                    error = %secrets::redact(&e.to_string()),
+                    "fetch failed"
+                );
+                return Err(e);
+            }
+        };
+        self.state.fetched(location_name, openweather_to_tsv.dt);
+        let events = self.process(location_name, &openweather_to_tsv);
+
+        Ok((openweather_to_tsv, events))
+    }
+
+    // 取得結果を保存し、購読者への配信とアラート評価を行う
+    // 最新の取得結果は設定した地点名（location_name）ごとに保持する
+    pub fn process(
+        &mut self,
+        location_name: &str,
+        openweather_to_tsv: &OpenWeaterToTsv,
+    ) -> Vec<alerts::AlertEvent> {
+        // 環境設定ファイルで出力するかを判定
+        let tsv_out_flg = env_or("TSV_OUT", "0");
+        if PartialEq::eq(&tsv_out_flg, "1") {
+            // tsvファイルの作成（書き込めなくても取得は続ける）
+            if let Err(e) = weather_write_to_tsv(openweather_to_tsv.clone()) {
+                tracing::error!(
+                    location = %openweather_to_tsv.name,
+                    error = %e,
+                    "tsv write failed"
+                );
+            }
         }
-        _ => {
-            println!("\n");
-            println!("Does not reflect the settings.");
-            println!("\n");
+
+        self.app_state.update(location_name, openweather_to_tsv);
+
+        // systemdに最新の取得結果を通知し、ログファイルが大きくなっていれば切り替える
+        systemd::notify(&format!(
+            "STATUS=last reading: {} {:.1}°C {} ({})",
+            openweather_to_tsv.name,
+            openweather_to_tsv.temp,
+            openweather_to_tsv.description,
+            Local
+                .timestamp(openweather_to_tsv.dt, 0)
+                .format("%Y-%m-%d %H:%M")
+        ));
+        daemon::rotate_log();
+
+        // アラートルールを評価する
+        let events = self.alert_engine.evaluate(openweather_to_tsv);
+        for event in &events {
+            self.notifier.notify_alert(event);
         }
-    }
 
-    Ok(())
+        events
+    }
 }
 
 fn weather_write_to_tsv(openweather_to_tsv: OpenWeaterToTsv) -> Result<(), std::io::Error> {
     let local: DateTime<Local> = Local::now();
-    let local_datetime = local.format("%Y-%m-%d%H:%M:%S").to_string();
-
+    let local_datetime = local.format("%Y-%m-%d%H:%M:%S%.3f").to_string();
+
+    // 同じ秒に複数地点を書き込んでも上書きしないよう、地点名を付ける
+    let location = openweather_to_tsv
+        .name
+        .to_lowercase()
+        .replace(|c: char| c.is_whitespace() || c == '/', "_");
+    // 書き込み途中のファイルが残らないよう、一時ファイルに書いてから名前を変える
+    let path = format!("{}/{}-{}.tsv", store::LOG_DIR, &local_datetime, location);
+    let tmp_path = format!("{}.tmp", path);
     let mut wtr = csv::WriterBuilder::new()
         // 区切りにする
         .delimiter(b'\t')
-        .from_path(format!("./weatherlog/{}.tsv", &local_datetime))
-        .expect("Path not found...");
+        .from_path(&tmp_path)?;
     // 天気情報の構造体をシリアライズ化して追加する
-    wtr.serialize(openweather_to_tsv)
-        .expect("weather info serialize error...");
-    wtr.flush().expect("Write error...");
+    wtr.serialize(openweather_to_tsv)?;
+    wtr.flush()?;
+    std::fs::rename(&tmp_path, &path)?;
 
     Ok(())
 }
 
+// 設定の読み込み元（SIGHUPで読み込み直すために保持する）
+struct Settings {
+    cli: cli::Cli,
+    // 起動時に設定されていた環境変数（設定ファイルや.envより優先する）
+    external: config::Values,
+}
+
+impl Settings {
+    fn new(cli: cli::Cli) -> Self {
+        Settings {
+            cli,
+            external: config::Values::from_process(),
+        }
+    }
+
+    // 環境変数・設定ファイル・.env・コマンドラインから設定値を作る（プロセスの環境変数は書き換えない）
+    // 設定ファイルの値は環境変数が未設定の項目のみ使い、.envより優先する
+    fn load(&self) -> Result<(Option<config::LoadedConfig>, config::Values), String> {
+        let profile = self
+            .cli
+            .profile
+            .clone()
+            .or_else(|| self.external.get("PROFILE").map(String::from));
+        let loaded_config = config::load(self.cli.config.as_deref(), profile.as_deref())?;
+
+        let mut values = self.external.clone();
+        if let Some(loaded_config) = &loaded_config {
+            loaded_config.apply(&mut values);
+        }
+        if let Ok(iter) = dotenvy::dotenv_iter() {
+            for (key, value) in iter.flatten() {
+                values.set_default(&key, &value);
+            }
+        }
+        // コマンドラインで指定した値を優先する
+        self.cli.apply(&mut values);
+        // 設定ファイル以外で指定した値も同じ確認を行う
+        config::validate(&values)?;
+        Ok((loaded_config, values))
+    }
+
+    // 設定を読み込み直す（SIGHUP）
+    // 全て検証してから差し替え、失敗した場合はそれまでの設定のまま変えない
+    // APIキーは読み込み直さない
+    fn reload(&self) -> Result<WatchSettings, String> {
+        let (_, values) = self.load()?;
+        let watch_settings = WatchSettings::from_values(&values)?;
+        config::replace(values);
+        Ok(watch_settings)
+    }
+}
+
+// 取得の繰り返しに関する設定（読み込み直しの対象）
+struct LoopSettings {
+    // JOBSのジョブとそれぞれのスケジュール（空の場合は通常の取得）
+    jobs: Vec<(jobs::Job, schedule::Scheduler)>,
+    icons: icon::IconSet,
+    scheduler: schedule::Scheduler,
+    // ダッシュボードの履歴の期間（秒）
+    history_window: i64,
+}
+
+// 定期的な取得の設定（読み込み直しの対象）
+struct WatchSettings {
+    url: String,
+    locations: Vec<String>,
+    rules: Vec<alerts::Rule>,
+    // アラートの通知先
+    notifier: notify::Notifier,
+    looping: LoopSettings,
+}
+
+impl WatchSettings {
+    // 設定値から作成する。不正な値がある場合は項目名を含めたエラーを返す
+    fn from_values(values: &config::Values) -> Result<Self, String> {
+        let rules = alerts::parse_rules(&values.or("ALERT_RULES", ""))
+            .map_err(|e| format!("ALERT_RULES: {}", e))?;
+        let scheduler = schedule::Scheduler::from_values(values)?;
+        let mut job_list = Vec::new();
+        if let Some(specs) = values.get("JOBS") {
+            for job in jobs::parse_jobs(specs).map_err(|e| format!("JOBS: {}", e))? {
+                let job_scheduler = scheduler
+                    .with_schedule(job.schedule.clone(), job.runs)
+                    .map_err(|e| format!("JOBS: job {}: {}", job.name, e))?;
+                job_list.push((job, job_scheduler));
+            }
+        }
+        let icons =
+            icon::IconSet::from_values(values).map_err(|e| format!("ICON_THEME_DIR: {}", e))?;
+        let history_window = alerts::parse_duration(&values.or("HISTORY_WINDOW", "24h"))
+            .map_err(|e| format!("HISTORY_WINDOW: {}", e))?;
+
+        Ok(WatchSettings {
+            url: values.or(
+                "OPEN_WEATHER_URL",
+                "https://api.openweathermap.org/data/2.5/weather",
+            ),
+            locations: parse_locations(&values.or("LOCATION_NAME", "osaka")),
+            rules,
+            notifier: notify::Notifier::from_values(values)?,
+            looping: LoopSettings {
+                jobs: job_list,
+                icons,
+                scheduler,
+                history_window,
+            },
+        })
+    }
+}
+
+// 「,」区切りで複数地点を指定できる
+fn parse_locations(location: &str) -> Vec<String> {
+    location
+        .split(',')
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    std::fs::create_dir_all("./weatherlog").expect("dir create error");
-    dotenv().ok();
-
-    let api_key = env::var("API_KEY");
-    let _api_key = match api_key {
-        Err(_) => String::from("4378163cb4675f5aeff249a30842c89e"),
-        Ok(val) => val,
+async fn main() {
+    // エラーメッセージに含まれるAPIキーは伏せて表示する
+    if let Err(e) = run().await {
+        eprintln!("Error: {}", secrets::redact(&e.to_string()));
+        std::process::exit(1);
+    }
+}
+
+async fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let settings = Settings::new(cli::Cli::parse());
+    std::fs::create_dir_all(store::LOG_DIR).expect("dir create error");
+    let loaded_config = match settings.load() {
+        Ok((loaded_config, values)) => {
+            config::replace(values);
+            loaded_config
+        }
+        Err(e) => {
+            eprintln!("config error: {}", e);
+            std::process::exit(2);
+        }
     };
-    let url = env::var("OPEN_WEATHER_URL");
-    let url = match url {
-        Err(_) => String::from("https://api.openweathermap.org/data/2.5/weather"),
-        Ok(val) => val,
+    if let Err(e) = logging::init() {
+        eprintln!("logging error: {}", e);
+        std::process::exit(2);
+    }
+
+    // APIキー（API_KEY / API_KEY_FILE / API_KEY_COMMAND）
+    let api_key = match secrets::load_api_key() {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("api key error: {}", e);
+            std::process::exit(2);
+        }
     };
-    let location = env::var("LOCATION_NAME");
-    let location = match location {
-        Err(_) => String::from("osaka"),
-        Ok(val) => val,
+    let key_source = api_key.as_ref().map(|(_, source)| source.to_string());
+    let api_key = api_key.map(|(key, _)| key).unwrap_or_default();
+    let url = env_or(
+        "OPEN_WEATHER_URL",
+        "https://api.openweathermap.org/data/2.5/weather",
+    );
+    let location = env_or("LOCATION_NAME", "osaka");
+    let locations = parse_locations(&location);
+
+    // APIの回数制限（全ての取得で共有し、読み込み直しの対象外）
+    let rate_limiter = Arc::new(ratelimit::RateLimiter::from_values(&config::current())?);
+
+    // 1回だけ取得する場合の単位はWEATHER_UNITSに従う
+    let api_client = ApiClient::new(
+        url.clone(),
+        env_or("WEATHER_UNITS", "metric"),
+        api_key.clone(),
+        Arc::clone(&rate_limiter),
+    );
+
+    // 対話できない場合は設定の不足をここで報告して終了する
+    let interactive = settings.cli.is_interactive();
+    let command = settings.cli.command.clone().unwrap_or(cli::Command::Watch);
+    if !interactive {
+        if let Err(e) = check_config(&command, &locations, &api_key) {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    }
+
+    // デーモンの場合はpidファイルを書き、以降の出力をログファイルに切り替える（終了時にpidファイルを削除する）
+    let _daemon = match command {
+        cli::Command::Watch if daemon::is_enabled() => match daemon::start() {
+            Ok(v) => Some(v),
+            Err(e) => {
+                eprintln!("daemon error: {}", e);
+                std::process::exit(2);
+            }
+        },
+        _ => None,
     };
-    println!("Do you want to set it up?");
-    println!("Currently set of: ");
-    println!("API KEY:         {}", "###########");
-    println!("OpenWeather URL: {}", url);
-    println!("Location name:   {}", location);
-    println!("Please enter Y or N : ");
-    loop {
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input).ok();
-        let result = input.trim();
-        match result {
-            "Y" | "y" => {
-                let _ = re_setting();
-                break;
+
+    match command {
+        cli::Command::Now => print_now(&api_client, &locations).await,
+        cli::Command::Forecast => print_forecast(&api_client, &locations).await,
+        cli::Command::History { .. } => print_history(&locations),
+        cli::Command::Export { from, to, output } => export_records(&locations, from, to, output),
+        cli::Command::Report { period, from, to } => report::print(&locations, period, from, to),
+        cli::Command::Status => print_status(),
+        cli::Command::Import {
+            files,
+            name,
+            data_units,
+        } => import::run(&files, name.as_deref(), &data_units),
+        cli::Command::Config { setup } => {
+            if setup {
+                if !interactive {
+                    return Err("config --setup needs an interactive terminal".into());
+                }
+                setup::run(&url, &api_key, &location, rate_limiter).await?;
+            } else {
+                print_config(loaded_config.as_ref(), key_source.as_deref());
             }
-            "N" | "n" => {
+            Ok(())
+        }
+        cli::Command::Watch => {
+            watch(
+                &settings,
+                url,
+                location,
+                locations,
+                api_key,
+                rate_limiter,
+                interactive,
+            )
+            .await
+        }
+    }
+}
+
+// 対話できない場合に必要な設定が揃っているか確認する
+// 不足している設定をまとめてエラーにする
+fn check_config(command: &cli::Command, locations: &[String], api_key: &str) -> Result<(), String> {
+    let mut missing = Vec::new();
+    // APIを呼び出すコマンドのみAPIキーなどが必要
+    let fetch = matches!(
+        command,
+        cli::Command::Now | cli::Command::Forecast | cli::Command::Watch
+    );
+    if fetch {
+        if api_key.is_empty() {
+            missing.push(String::from(
+                "no API key (set API_KEY_FILE, API_KEY_COMMAND or API_KEY)",
+            ));
+        }
+        if locations.is_empty() {
+            missing.push(String::from("LOCATION_NAME has no location"));
+        }
+    }
+    if let cli::Command::Watch = command {
+        match config::current().get("TSV_OUT") {
+            Some("0") | Some("1") => {}
+            Some(v) => missing.push(format!("TSV_OUT must be 0 or 1 (got {:?})", v)),
+            None => missing.push(String::from("TSV_OUT is not set")),
+        }
+        if env_or("DISPLAY_MODE", "plain") == "dashboard" {
+            missing.push(String::from(
+                "DISPLAY_MODE=dashboard needs an interactive terminal",
+            ));
+        }
+    }
+    if missing.is_empty() {
+        return Ok(());
+    }
+    Err(format!(
+        "configuration incomplete (non-interactive mode):\n  {}\nset them in .env, the environment or on the command line",
+        missing.join("\n  ")
+    ))
+}
+
+// 定期的に取得する
+async fn watch(
+    settings: &Settings,
+    url: String,
+    location: String,
+    locations: Vec<String>,
+    mut api_key: String,
+    rate_limiter: Arc<ratelimit::RateLimiter>,
+    interactive: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // 保存済みの取得結果をグラフで表示して終了する
+    if env_or("DISPLAY_MODE", "plain") == "history" {
+        print_history(&locations)?;
+        return Ok(());
+    }
+
+    // 取得結果を1行で表示して終了する
+    if env_or("DISPLAY_MODE", "plain") == "template" {
+        let units = env_or("WEATHER_UNITS", "metric");
+        let api_client = ApiClient::new(url, units, api_key, rate_limiter);
+        return print_now(&api_client, &locations).await;
+    }
+
+    // ステータスバー向けの出力（標準出力はバーが読むため、設定の確認は行わない）
+    let bar_format = bar::BarFormat::parse(&env_or("DISPLAY_MODE", "plain"));
+
+    // APIキーが全く設定されていない場合のみ、設定するか確認する（対話できない場合は確認しない）
+    // 設定した場合は書き換えた.envとキーファイルを読み込み直して開始する
+    if bar_format.is_none() && interactive && api_key.is_empty() {
+        println!("No API key is configured. Do you want to set it up?");
+        println!("OpenWeather URL: {}", url);
+        println!("Location name:   {}", location);
+        println!("Please enter Y or N : ");
+        loop {
+            let mut input = String::new();
+            // 入力が閉じられた場合は設定しない
+            if let Ok(0) | Err(_) = std::io::stdin().read_line(&mut input) {
                 break;
             }
-            _ => {
-                println!("Please try again.");
-                println!("enter Y(y) or N(n) : ");
+            match input.trim() {
+                "Y" | "y" => {
+                    setup::run(&url, &api_key, &location, Arc::clone(&rate_limiter)).await?;
+                    settings.reload()?;
+                    if let Some((key, _)) = secrets::load_api_key()? {
+                        api_key = key;
+                    }
+                    break;
+                }
+                "N" | "n" => {
+                    break;
+                }
+                _ => {
+                    println!("Please try again.");
+                    println!("enter Y(y) or N(n) : ");
+                }
             }
         }
     }
 
-    // 30分毎に取得する
-    let thirty_minutes = time::Duration::from_secs(1800);
-    // 7日間で自動停止
-    let end_time = time::Duration::from_secs(604800);
-    let start = Instant::now();
+    // 読み込み直しの対象になる設定（不正な値がある場合は開始しない）
+    let mut watch_settings = WatchSettings::from_values(&config::current())?;
+
+    // 終了（SIGINT/SIGTERM）と設定の読み込み直し（SIGHUP）
+    let mut signals = signals::Signals::new()?;
+
+    // 取得結果をHTTPで公開する（SERVER_ADDRが設定されている場合のみ）
+    // サーバーとMQTTの接続先は読み込み直しの対象外
+    let app_state = server::AppState::new(PathBuf::from(store::LOG_DIR));
+    let addr = env_or("SERVER_ADDR", "");
+    if !addr.is_empty() {
+        let addr = addr.parse().expect("SERVER_ADDR env error...");
+        server::spawn(addr, app_state.clone());
+    }
+
+    // 取得結果をMQTTブローカーに送信する（MQTT_HOSTが設定されている場合のみ）
+    let mqtt = mqtt::MqttConfig::from_env()?
+        .and_then(|mqtt_config| mqtt::spawn(mqtt_config, app_state.observations.subscribe()));
+
+    if bar_format == Some(bar::BarFormat::I3bar) {
+        println!("{}", bar::i3bar_header());
+    }
+
+    // systemdに起動の完了を通知する
+    systemd::spawn_watchdog();
+    systemd::notify("READY=1");
+
+    // 取得の状態（読み込み直しても引き継ぐ）
+    let state = Arc::new(state::StateFile::open());
+
+    // 停止中に取得できなかった時間帯を過去のデータで埋める（取得中に終了のシグナルを受けた場合は取り消す）
+    let backfill_client = ApiClient::new(
+        url.clone(),
+        String::from("metric"),
+        api_key.clone(),
+        Arc::clone(&rate_limiter),
+    );
+    let backfilled = signals
+        .guard(backfill::run(&backfill_client, &locations))
+        .instrument(tracing::info_span!("backfill"))
+        .await;
+    if let Some(Err(e)) = backfilled {
+        tracing::warn!(error = %secrets::redact(&e.to_string()), "backfill skipped");
+    }
+
+    // 読み込み直すたびに作り直した通知先（終了時に全て送信を待つ）
+    let mut notifiers = Vec::new();
+
+    // 毎日決まった時刻に集計を通知する（REPORT_TIMEが設定されている場合のみ、読み込み直しの対象外）
+    let report_notifier = Arc::new(notify::Notifier::from_values(&config::current())?);
+    report::spawn(Arc::clone(&report_notifier), locations.clone())?;
+    notifiers.push(report_notifier);
+    // 取得を続けられなくなった原因
+    let mut failure = None;
     loop {
-        let client = Client::new();
-        let server = env::var("OPEN_WEATHER_URL");
-        let server = match server {
-            Err(_) => String::from("https://api.openweathermap.org/data/2.5/weather"),
-            Ok(val) => val,
+        let WatchSettings {
+            url,
+            locations,
+            rules,
+            notifier,
+            looping,
+        } = watch_settings;
+        let collector = Collector {
+            // 保存する値の単位を揃えるため、取得は常にメートル法で行う
+            api_client: Arc::new(ApiClient::new(
+                url,
+                String::from("metric"),
+                api_key.clone(),
+                Arc::clone(&rate_limiter),
+            )),
+            app_state: app_state.clone(),
+            // 閾値アラートのルール
+            alert_engine: alerts::AlertEngine::new(rules),
+            notifier: Arc::new(notifier),
+            state: Arc::clone(&state),
         };
-        let api_client = ApiClient {
-            server: server,
-            client: client,
+        notifiers.push(Arc::clone(&collector.notifier));
+
+        // 続けられないエラーの場合も通知の送信を待ってから終了する
+        if let Err(e) = watch_loop(collector, &locations, looping, bar_format, &mut signals).await {
+            tracing::error!(error = %secrets::redact(&e.to_string()), "watch failed");
+            failure = Some(e);
+            break;
+        }
+
+        if signals.stopped().is_some() || !signals.take_reload() {
+            break;
+        }
+        // 設定を読み込み直す。失敗した場合はそれまでの設定で続ける
+        systemd::notify("RELOADING=1");
+        let reloaded = settings.reload();
+        systemd::notify("READY=1");
+        watch_settings = match reloaded {
+            Ok(v) => {
+                if let Err(e) = logging::reload() {
+                    tracing::error!(error = %e, "log level not changed");
+                }
+                tracing::info!("received SIGHUP, configuration reloaded");
+                v
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "received SIGHUP, reload failed");
+                // 検証済みの設定なので、作り直せない場合（テーマの画像が消えたなど）のみ終了する
+                match WatchSettings::from_values(&config::current()) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        tracing::error!(error = %e, "previous configuration is no longer valid");
+                        break;
+                    }
+                }
+            }
         };
+    }
 
-        // 標準出力
-        let mut stdout = stdout();
+    // 送信中の通知とMQTTの送信を待ってから終了する
+    // 待つ時間は全体でSHUTDOWN_TIMEOUTまでとし、残りの時間をそれぞれに渡す
+    systemd::notify("STOPPING=1");
+    let deadline = Instant::now() + signals.timeout();
+    let mut canceled = 0;
+    for notifier in &notifiers {
+        canceled += notifier
+            .flush(deadline.saturating_duration_since(Instant::now()))
+            .await;
+    }
+    if canceled > 0 {
+        tracing::warn!(canceled, "notifications canceled");
+    }
+    if let Some(mqtt) = mqtt {
+        if !mqtt
+            .flush(deadline.saturating_duration_since(Instant::now()))
+            .await
+        {
+            tracing::warn!("mqtt pending messages were not sent");
+        }
+    }
+    let _ = stdout().flush();
+    let reason = match (&failure, signals.stopped()) {
+        (Some(_), _) => "error",
+        (None, Some(signal)) => signal.name(),
+        (None, None) => "schedule finished",
+    };
+    state.stopped(reason, canceled);
+    tracing::info!(reason, canceled, "stopped");
 
-        // 表示を一旦クリア
-        write!(stdout, "{}", clear::All)?;
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
 
-        // 非同期でデータを受け取る
-        do_get_weather(api_client).await?;
+// 終了条件に達するかシグナルを受けるまで取得を繰り返す
+// 取得の失敗では終了せず、出力できない場合（標準出力が閉じられたなど）のみエラーを返す
+// 表示の形式は起動時のDISPLAY_MODEのまま変えない
+async fn watch_loop(
+    mut collector: Collector,
+    locations: &[String],
+    looping: LoopSettings,
+    bar_format: Option<bar::BarFormat>,
+    signals: &mut signals::Signals,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // エンドポイントごとの取得ジョブ（JOBSが設定されている場合は通常の表示の代わりに実行する）
+    if !looping.jobs.is_empty() {
+        let api_client = Arc::clone(&collector.api_client);
+        let collector = Arc::new(tokio::sync::Mutex::new(collector));
+        return jobs::run(looping.jobs, api_client, collector, locations, signals).await;
+    }
 
-        // 経過時間を取得
-        let end = start.elapsed();
+    // 天気アイコン（テーマのディレクトリは読み込み時に検証済み）
+    let icons = looping.icons;
+
+    // 取得スケジュール（デフォルトは毎時0分と30分、7日間で自動停止）
+    // 前回の取得が終了していない場合は開始時刻と実行回数を引き継ぐ
+    let mut scheduler = looping.scheduler;
+    collector.state.resume(None, &mut scheduler);
+
+    // 全画面のダッシュボード表示
+    if env_or("DISPLAY_MODE", "plain") == "dashboard" {
+        return dashboard::run(
+            &mut collector,
+            locations.to_vec(),
+            icons,
+            looping.history_window,
+            scheduler,
+            signals,
+        )
+        .await;
+    }
 
-        // 経過時間が閾値を超えていた場合終了
-        if end > end_time {
-            break;
+    let output_template = output_template(&collector.api_client.units);
+
+    let mut run = 0;
+    while !signals.interrupted() {
+        run += 1;
+        let cycle = async {
+            if let Some(bar_format) = bar_format {
+                // アラートは通知先にのみ送り、標準出力にはバー向けの1行だけを出力する
+                // 取得できなかった地点は除き、全て失敗した場合は前回の表示のままにする
+                let mut records = Vec::new();
+                for location_name in locations {
+                    if let Ok((openweather_to_tsv, _)) = collector.collect(location_name).await {
+                        records.push(openweather_to_tsv);
+                    }
+                }
+                if !records.is_empty() {
+                    let units = &collector.api_client.units;
+                    println!(
+                        "{}",
+                        bar::line(bar_format, &records, &output_template, units)
+                    );
+                    stdout().flush()?;
+                }
+            } else if daemon::is_enabled() {
+                // ログファイル向けに時刻を付けて1地点1行で出力する
+                // 取得できなかった地点はログに記録済みなので次の地点に進む
+                for location_name in locations {
+                    let (openweather_to_tsv, events) = match collector.collect(location_name).await
+                    {
+                        Ok(v) => v,
+                        Err(_) => continue,
+                    };
+                    let now = Local::now().format("%Y-%m-%d %H:%M:%S");
+                    println!(
+                        "{} {}",
+                        now,
+                        template::render(
+                            &output_template,
+                            &template::record_value(&openweather_to_tsv)
+                        )
+                    );
+                    for event in events {
+                        println!("{} alert {}", now, event);
+                    }
+                }
+            } else {
+                // 標準出力
+                let mut stdout = stdout();
+
+                // 表示を一旦クリア
+                write!(stdout, "{}", clear::All)?;
+
+                for location_name in locations {
+                    let (openweather_to_tsv, events) = match collector.collect(location_name).await
+                    {
+                        Ok(v) => v,
+                        Err(e) => {
+                            println!("{}: {}", location_name, secrets::redact(&e.to_string()));
+                            continue;
+                        }
+                    };
+                    print_weather(&openweather_to_tsv, &icons)?;
+                    for event in events {
+                        println!("alert {}", event);
+                    }
+                }
+            }
+            Ok::<(), Box<dyn std::error::Error>>(())
+        }
+        .instrument(tracing::info_span!("cycle", run));
+        // 取得中に終了のシグナルを受けた場合はSHUTDOWN_TIMEOUTまで完了を待ち、過ぎたら取り消す
+        match signals.guard(cycle).await {
+            Some(result) => result?,
+            None => break,
+        }
+        scheduler.mark_run();
+
+        // 次の実行時刻まで待つ。終了条件に達していた場合終了
+        let next = scheduler.next(Local::now());
+        collector.state.scheduled(None, &scheduler, next);
+        match next {
+            Some(next) => {
+                signals
+                    .sleep_until(Instant::now() + schedule::wait_time(next))
+                    .await
+            }
+            None => break,
         }
-        // スレッドを指定した時間sleepする
-        thread::sleep(thirty_minutes);
     }
 
     Ok(())
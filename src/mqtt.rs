@@ -0,0 +1,306 @@
+use std::time::Duration;
+
+use rumqttc::{AsyncClient, Event, MqttOptions, Outgoing, QoS, Transport};
+use serde_json::{json, Value};
+use tokio::sync::{broadcast, oneshot};
+use tokio::task::JoinHandle;
+
+use crate::api::OpenWeaterToTsv;
+use crate::env_or;
+
+// MQTTの設定。MQTT_HOSTが空の場合は送信しない
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub tls: bool,
+    // TLSで使うCA証明書(PEM)。未指定の場合はOSの証明書を使う
+    pub ca_file: String,
+    pub username: String,
+    pub password: String,
+    pub client_id: String,
+    // 送信先トピック。{location}は地点名に置き換える
+    pub topic: String,
+    // Home AssistantのMQTT Discoveryのプレフィックス。空の場合はDiscoveryを送信しない
+    pub discovery_prefix: String,
+}
+
+impl MqttConfig {
+    // MQTT_*の設定から作成する。ポート番号が不正な場合はエラー
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let host = env_or("MQTT_HOST", "");
+        if host.is_empty() {
+            return Ok(None);
+        }
+        let tls = env_or("MQTT_TLS", "0") == "1";
+        let port = env_or("MQTT_PORT", if tls { "8883" } else { "1883" });
+        let port = port
+            .parse()
+            .map_err(|_| format!("MQTT_PORT: invalid number {:?}", port))?;
+
+        Ok(Some(MqttConfig {
+            host,
+            port,
+            tls,
+            ca_file: env_or("MQTT_CA_FILE", ""),
+            username: env_or("MQTT_USERNAME", ""),
+            password: env_or("MQTT_PASSWORD", ""),
+            client_id: env_or("MQTT_CLIENT_ID", "openweather-client"),
+            topic: env_or("MQTT_TOPIC", "openweather/{location}/state"),
+            discovery_prefix: env_or("MQTT_DISCOVERY_PREFIX", "homeassistant"),
+        }))
+    }
+
+    fn options(&self) -> Result<MqttOptions, std::io::Error> {
+        let mut options = MqttOptions::new(&self.client_id, &self.host, self.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        if !self.username.is_empty() {
+            options.set_credentials(&self.username, &self.password);
+        }
+        if self.tls {
+            if self.ca_file.is_empty() {
+                options.set_transport(Transport::tls_with_default_config());
+            } else {
+                let ca = std::fs::read(&self.ca_file)?;
+                options.set_transport(Transport::tls(ca, None, None));
+            }
+        }
+        Ok(options)
+    }
+
+    fn state_topic(&self, location: &str) -> String {
+        self.topic.replace("{location}", location)
+    }
+}
+
+// トピックやIDに使えるように地点名を変換する
+fn slug(name: &str) -> String {
+    name.to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+// Home Assistantに登録するセンサー（キー、表示名、device_class、単位）
+const SENSORS: [(&str, &str, &str, &str); 6] = [
+    ("temp", "Temperature", "temperature", "°C"),
+    ("humidity", "Humidity", "humidity", "%"),
+    ("pressure", "Pressure", "atmospheric_pressure", "hPa"),
+    ("speed", "Wind speed", "wind_speed", "m/s"),
+    ("gust", "Wind gust", "wind_speed", "m/s"),
+    ("deg", "Wind direction", "", "°"),
+];
+
+// Home AssistantのMQTT Discovery設定（センサーごとのトピックとペイロード）
+fn discovery(config: &MqttConfig, record: &OpenWeaterToTsv) -> Vec<(String, Value)> {
+    let location = slug(&record.name);
+    let device_id = format!("openweather_{}", location);
+    let mut messages = Vec::new();
+    for (key, name, device_class, unit) in SENSORS {
+        let unique_id = format!("{}_{}", device_id, key);
+        let mut payload = json!({
+            "name": name,
+            "unique_id": unique_id,
+            "state_topic": config.state_topic(&location),
+            "value_template": format!("{{{{ value_json.{} }}}}", key),
+            "unit_of_measurement": unit,
+            "state_class": "measurement",
+            "device": {
+                "identifiers": [device_id],
+                "name": format!("OpenWeather {}", record.name),
+                "manufacturer": "OpenWeather",
+            },
+        });
+        if !device_class.is_empty() {
+            payload["device_class"] = json!(device_class);
+        }
+        let topic = format!("{}/sensor/{}/config", config.discovery_prefix, unique_id);
+        messages.push((topic, payload));
+    }
+    messages
+}
+
+// Home AssistantのMQTT Discovery設定を送信する
+async fn publish_discovery(
+    client: &AsyncClient,
+    config: &MqttConfig,
+    record: &OpenWeaterToTsv,
+) -> Result<(), rumqttc::ClientError> {
+    for (topic, payload) in discovery(config, record) {
+        client
+            .publish(topic, QoS::AtLeastOnce, true, payload.to_string())
+            .await?;
+    }
+    Ok(())
+}
+
+// 送信タスクを終了させるためのハンドル
+pub struct MqttHandle {
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl MqttHandle {
+    // 受け取り済みの取得結果を送信してから切断する。timeoutまでに終わらない場合はfalse
+    pub async fn flush(self, timeout: Duration) -> bool {
+        let _ = self.shutdown.send(());
+        tokio::time::timeout(timeout, self.task).await.is_ok()
+    }
+}
+
+// 取得結果を購読してMQTTブローカーに送信する
+pub fn spawn(
+    config: MqttConfig,
+    mut rx: broadcast::Receiver<OpenWeaterToTsv>,
+) -> Option<MqttHandle> {
+    let options = match config.options() {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::error!(error = %e, "mqtt config error");
+            return None;
+        }
+    };
+    let (client, mut eventloop) = AsyncClient::new(options, 16);
+
+    // 接続の維持（再接続も含む）はイベントループをpollし続けることで行われる
+    // 切断を送信したら終了する
+    let connection = tokio::spawn(async move {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Outgoing(Outgoing::Disconnect)) => break,
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::warn!(error = %e, "mqtt connection error");
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+            }
+        }
+    });
+
+    let (shutdown, mut shutdown_rx) = oneshot::channel();
+    let task = tokio::spawn(async move {
+        // Discoveryは地点ごとに一度だけ送信する（retainで保持される）
+        let mut discovered: Vec<String> = Vec::new();
+        loop {
+            let record = tokio::select! {
+                record = rx.recv() => match record {
+                    Ok(v) => v,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = &mut shutdown_rx => break,
+            };
+            publish(&client, &config, &mut discovered, &record).await;
+        }
+
+        // 受け取り済みの分を送信してから切断する
+        while let Ok(record) = rx.try_recv() {
+            publish(&client, &config, &mut discovered, &record).await;
+        }
+        if client.disconnect().await.is_ok() {
+            let _ = connection.await;
+        }
+    });
+
+    Some(MqttHandle { shutdown, task })
+}
+
+async fn publish(
+    client: &AsyncClient,
+    config: &MqttConfig,
+    discovered: &mut Vec<String>,
+    record: &OpenWeaterToTsv,
+) {
+    let location = slug(&record.name);
+
+    if !config.discovery_prefix.is_empty() && !discovered.contains(&location) {
+        match publish_discovery(client, config, record).await {
+            Ok(_) => discovered.push(location.clone()),
+            Err(e) => tracing::warn!(error = %e, "mqtt discovery failed"),
+        }
+    }
+
+    let payload = match serde_json::to_string(record) {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::error!(error = %e, "mqtt serialize failed");
+            return;
+        }
+    };
+    if let Err(e) = client
+        .publish(
+            config.state_topic(&location),
+            QoS::AtLeastOnce,
+            true,
+            payload,
+        )
+        .await
+    {
+        tracing::warn!(error = %e, "mqtt publish failed");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> MqttConfig {
+        MqttConfig {
+            host: String::from("localhost"),
+            port: 1883,
+            tls: false,
+            ca_file: String::new(),
+            username: String::new(),
+            password: String::new(),
+            client_id: String::from("openweather-client"),
+            topic: String::from("openweather/{location}/state"),
+            discovery_prefix: String::from("homeassistant"),
+        }
+    }
+
+    #[test]
+    fn discovery_topics_and_payloads() {
+        let mut record = OpenWeaterToTsv::new();
+        record.name = String::from("New York");
+        let messages = discovery(&config(), &record);
+        assert_eq!(messages.len(), SENSORS.len());
+
+        let (topic, payload) = &messages[0];
+        assert_eq!(
+            topic,
+            "homeassistant/sensor/openweather_new_york_temp/config"
+        );
+        assert_eq!(
+            payload,
+            &json!({
+                "name": "Temperature",
+                "unique_id": "openweather_new_york_temp",
+                "state_topic": "openweather/new_york/state",
+                "value_template": "{{ value_json.temp }}",
+                "unit_of_measurement": "°C",
+                "state_class": "measurement",
+                "device_class": "temperature",
+                "device": {
+                    "identifiers": ["openweather_new_york"],
+                    "name": "OpenWeather New York",
+                    "manufacturer": "OpenWeather",
+                },
+            })
+        );
+
+        // device_classがないセンサーは項目を含めない
+        let (topic, payload) = messages.last().unwrap();
+        assert_eq!(
+            topic,
+            "homeassistant/sensor/openweather_new_york_deg/config"
+        );
+        assert!(payload.get("device_class").is_none());
+        assert_eq!(payload["unit_of_measurement"], "°");
+    }
+
+    #[test]
+    fn slug_location_names() {
+        assert_eq!(slug("Osaka"), "osaka");
+        assert_eq!(slug("New York"), "new_york");
+        assert_eq!(slug("Kyoto@35.0116:135.7681"), "kyoto_35_0116_135_7681");
+    }
+}
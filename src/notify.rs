@@ -0,0 +1,425 @@
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use reqwest::Client;
+use serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::task::JoinSet;
+
+use crate::alerts::AlertEvent;
+use crate::config::Values;
+use crate::template;
+
+type NotifyError = Box<dyn std::error::Error + Send + Sync>;
+
+// SMTPの設定
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    // none / starttls / tls
+    pub tls: String,
+    pub username: String,
+    pub password: String,
+    pub from: String,
+    // 「,」区切りで複数指定できる
+    pub to: String,
+}
+
+// 通知先
+pub enum Channel {
+    // イベントをJSONでPOSTする
+    Webhook(String),
+    // Slack/Mattermostの Incoming Webhook
+    Slack(String),
+    Smtp(SmtpConfig),
+    // コマンドを実行し、標準入力にイベントのJSONを渡す
+    Exec(String),
+}
+
+impl Channel {
+    fn name(&self) -> &'static str {
+        match self {
+            Channel::Webhook(_) => "webhook",
+            Channel::Slack(_) => "slack",
+            Channel::Smtp(_) => "smtp",
+            Channel::Exec(_) => "exec",
+        }
+    }
+
+    async fn send(&self, client: &Client, payload: &Value) -> Result<(), NotifyError> {
+        let subject = template::lookup(payload, "subject");
+        let body = template::lookup(payload, "body");
+        match self {
+            Channel::Webhook(url) => {
+                client
+                    .post(url)
+                    .json(payload)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+            Channel::Slack(url) => {
+                client
+                    .post(url)
+                    .json(&json!({ "text": body }))
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+            Channel::Smtp(config) => {
+                let mut message = Message::builder()
+                    .from(config.from.parse::<Mailbox>()?)
+                    .subject(subject);
+                for to in config.to.split(',').filter(|v| !v.trim().is_empty()) {
+                    message = message.to(to.trim().parse::<Mailbox>()?);
+                }
+                let message = message.body(body)?;
+
+                let mut transport = match config.tls.as_str() {
+                    "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+                    "starttls" => {
+                        AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?
+                    }
+                    _ => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+                }
+                .port(config.port);
+                if !config.username.is_empty() {
+                    transport = transport.credentials(Credentials::new(
+                        config.username.clone(),
+                        config.password.clone(),
+                    ));
+                }
+                transport.build().send(message).await?;
+            }
+            Channel::Exec(command) => {
+                let mut child = Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .env_remove("API_KEY")
+                    .stdin(Stdio::piped())
+                    .spawn()?;
+                if let Some(mut stdin) = child.stdin.take() {
+                    stdin.write_all(payload.to_string().as_bytes()).await?;
+                }
+                let status = child.wait().await?;
+                if !status.success() {
+                    return Err(format!("command exited with {}", status).into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// 数値の設定を読み取る（設定の検証と同じ書式のエラーにする）
+fn parse_number<T: std::str::FromStr>(
+    values: &Values,
+    key: &str,
+    default: &str,
+) -> Result<T, String> {
+    let value = values.or(key, default);
+    value
+        .parse()
+        .map_err(|_| format!("{}: invalid number {:?}", key, value))
+}
+
+pub struct Notifier {
+    client: Client,
+    channels: Vec<Arc<Channel>>,
+    // 失敗した場合の再試行回数
+    retry: u32,
+    // アラート通知の件名と本文のテンプレート
+    subject_template: String,
+    body_template: String,
+    // 送信中の通知（終了時に完了を待つ）
+    pending: Mutex<JoinSet<()>>,
+}
+
+// 再試行までの待ち時間（1秒から倍にしていき、64秒で止める）
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_secs(1u64 << attempt.min(6))
+}
+
+impl Notifier {
+    // NOTIFY_*の設定から作成する
+    pub fn from_values(values: &Values) -> Result<Self, String> {
+        let mut channels = Vec::new();
+
+        let webhook = values.or("NOTIFY_WEBHOOK_URL", "");
+        if !webhook.is_empty() {
+            channels.push(Channel::Webhook(webhook));
+        }
+        let slack = values.or("NOTIFY_SLACK_URL", "");
+        if !slack.is_empty() {
+            channels.push(Channel::Slack(slack));
+        }
+        let smtp_host = values.or("NOTIFY_SMTP_HOST", "");
+        let smtp_to = values.or("NOTIFY_SMTP_TO", "");
+        if !smtp_host.is_empty() && smtp_to.split(',').all(|v| v.trim().is_empty()) {
+            // 宛先がないと毎回失敗して再試行になるので、SMTPは使わない
+            tracing::error!(host = %smtp_host, "NOTIFY_SMTP_TO is empty, smtp notifier disabled");
+        } else if !smtp_host.is_empty() {
+            channels.push(Channel::Smtp(SmtpConfig {
+                host: smtp_host,
+                port: parse_number(values, "NOTIFY_SMTP_PORT", "25")?,
+                tls: values.or("NOTIFY_SMTP_TLS", "none"),
+                username: values.or("NOTIFY_SMTP_USERNAME", ""),
+                password: values.or("NOTIFY_SMTP_PASSWORD", ""),
+                from: values.or(
+                    "NOTIFY_SMTP_FROM",
+                    "openweather-client <openweather@localhost>",
+                ),
+                to: smtp_to,
+            }));
+        }
+        let exec = values.or("NOTIFY_EXEC", "");
+        if !exec.is_empty() {
+            channels.push(Channel::Exec(exec));
+        }
+
+        Ok(Notifier {
+            client: Client::new(),
+            channels: channels.into_iter().map(Arc::new).collect(),
+            retry: parse_number(values, "NOTIFY_RETRY", "3")?,
+            subject_template: values.or("NOTIFY_SUBJECT", "[openweather] {location}: {rule} {kind}"),
+            body_template: values.or(
+                "NOTIFY_TEMPLATE",
+                "{location}: {rule} {kind} ({field} = {value}) {record.description} {record.temp}°C",
+            ),
+            pending: Mutex::new(JoinSet::new()),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+
+    // 全ての通知先に並行して送信し、全て終わるまで待つ
+    // 再試行は通知先ごとに行い、失敗した通知先が他の通知先の送信を遅らせないようにする
+    pub async fn send(self: &Arc<Self>, subject: String, body: String, event: Value) {
+        let payload = Arc::new(json!({
+            "subject": subject,
+            "body": body,
+            "event": event,
+        }));
+        // 取り消された場合（dropされた場合）は送信中の通知先も取り消される
+        let mut sending = JoinSet::new();
+        for channel in &self.channels {
+            let notifier = Arc::clone(self);
+            let channel = Arc::clone(channel);
+            let payload = Arc::clone(&payload);
+            sending.spawn(async move { notifier.send_to(&channel, &payload).await });
+        }
+        while sending.join_next().await.is_some() {}
+    }
+
+    // 1つの通知先に送信する。失敗した場合は間隔を空けて再試行する
+    async fn send_to(&self, channel: &Channel, payload: &Value) {
+        let mut attempt = 0;
+        loop {
+            match channel.send(&self.client, payload).await {
+                Ok(_) => break,
+                Err(e) if attempt < self.retry => {
+                    tracing::warn!(
+                        channel = channel.name(),
+                        retry = attempt + 1,
+                        error = %e,
+                        "notify failed"
+                    );
+                    tokio::time::sleep(backoff(attempt)).await;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    tracing::error!(channel = channel.name(), error = %e, "notify failed");
+                    break;
+                }
+            }
+        }
+    }
+
+    // アラートイベントをテンプレートで整形し、取得ループを止めないよう別タスクで送信する
+    pub fn notify_alert(self: &Arc<Self>, event: &AlertEvent) {
+        if self.is_empty() {
+            return;
+        }
+        let event = serde_json::to_value(event).unwrap_or_default();
+        let subject = template::render(&self.subject_template, &event);
+        let body = template::render(&self.body_template, &event);
+        self.notify(subject, body, event);
+    }
+
+    // 整形済みの件名と本文を別タスクで送信する（集計の通知など）
+    pub fn notify(self: &Arc<Self>, subject: String, body: String, event: Value) {
+        if self.is_empty() {
+            return;
+        }
+        let notifier = Arc::clone(self);
+        let mut pending = self.pending.lock().unwrap();
+        // 完了した通知は取り除いておく
+        while pending.try_join_next().is_some() {}
+        pending.spawn(async move {
+            notifier.send(subject, body, event).await;
+        });
+    }
+
+    // 送信中の通知の完了を待つ。timeoutを過ぎた場合は取り消し、取り消した件数を返す
+    pub async fn flush(&self, timeout: Duration) -> usize {
+        let mut pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        let wait = async { while pending.join_next().await.is_some() {} };
+        if tokio::time::timeout(timeout, wait).await.is_ok() {
+            return 0;
+        }
+        let canceled = pending.len();
+        pending.shutdown().await;
+        canceled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notifier(channels: Vec<Channel>) -> Arc<Notifier> {
+        Arc::new(Notifier {
+            client: Client::new(),
+            channels: channels.into_iter().map(Arc::new).collect(),
+            retry: 0,
+            subject_template: String::from("{location}: {rule} {kind}"),
+            body_template: String::from("{location} {field} = {value}"),
+            pending: Mutex::new(JoinSet::new()),
+        })
+    }
+
+    #[tokio::test]
+    async fn exec_receives_payload_on_stdin() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("payload.json");
+        let notifier = notifier(vec![Channel::Exec(format!("cat > {}", out.display()))]);
+
+        notifier.notify(
+            String::from("subject"),
+            String::from("body"),
+            json!({ "location": "Tokyo" }),
+        );
+        assert_eq!(notifier.flush(Duration::from_secs(5)).await, 0);
+
+        let payload: Value = serde_json::from_str(&std::fs::read_to_string(&out).unwrap()).unwrap();
+        assert_eq!(payload["subject"], "subject");
+        assert_eq!(payload["body"], "body");
+        assert_eq!(payload["event"]["location"], "Tokyo");
+    }
+
+    #[tokio::test]
+    async fn exec_failure_does_not_stop_other_channels() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("payload.json");
+        let notifier = notifier(vec![
+            Channel::Exec(String::from("exit 1")),
+            Channel::Exec(format!("cat > {}", out.display())),
+        ]);
+
+        notifier
+            .send(String::from("subject"), String::from("body"), json!({}))
+            .await;
+        assert!(out.exists());
+    }
+
+    #[tokio::test]
+    async fn retry_does_not_delay_other_channels() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("payload.json");
+        let mut notifier = notifier(vec![
+            Channel::Exec(String::from("exit 1")),
+            Channel::Exec(format!("cat > {}", out.display())),
+        ]);
+        Arc::get_mut(&mut notifier).unwrap().retry = 3;
+
+        notifier.notify(String::from("subject"), String::from("body"), json!({}));
+        // 1つ目の通知先が再試行を待っている間に2つ目の通知先に届く
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(out.exists());
+        assert_eq!(notifier.flush(Duration::from_millis(100)).await, 1);
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        assert_eq!(backoff(0), Duration::from_secs(1));
+        assert_eq!(backoff(3), Duration::from_secs(8));
+        assert_eq!(backoff(6), Duration::from_secs(64));
+        assert_eq!(backoff(64), Duration::from_secs(64));
+        assert_eq!(backoff(u32::MAX), Duration::from_secs(64));
+    }
+
+    #[test]
+    fn invalid_numbers_are_errors() {
+        let mut values = Values::default();
+        values.set("NOTIFY_RETRY", "many");
+        assert_eq!(
+            Notifier::from_values(&values).err().unwrap(),
+            "NOTIFY_RETRY: invalid number \"many\""
+        );
+
+        let mut values = Values::default();
+        values.set("NOTIFY_SMTP_HOST", "localhost");
+        values.set("NOTIFY_SMTP_TO", "ops@example.com");
+        values.set("NOTIFY_SMTP_PORT", "70000");
+        assert_eq!(
+            Notifier::from_values(&values).err().unwrap(),
+            "NOTIFY_SMTP_PORT: invalid number \"70000\""
+        );
+
+        values.set("NOTIFY_SMTP_PORT", "2525");
+        let notifier = Notifier::from_values(&values).unwrap();
+        assert_eq!(notifier.channels.len(), 1);
+        assert_eq!(notifier.retry, 3);
+    }
+
+    #[tokio::test]
+    async fn webhook_posts_payload() {
+        use tokio::io::AsyncReadExt;
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            // ヘッダーとJSON本文を受け取るまで読む
+            loop {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request);
+                if n == 0 || text.ends_with('}') {
+                    break;
+                }
+            }
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let notifier = notifier(vec![Channel::Webhook(url)]);
+        notifier
+            .send(
+                String::from("subject"),
+                String::from("body"),
+                json!({ "rule": "hot" }),
+            )
+            .await;
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /hook "));
+        let body = &request[request.find("\r\n\r\n").unwrap() + 4..];
+        let payload: Value = serde_json::from_str(body).unwrap();
+        assert_eq!(payload["subject"], "subject");
+        assert_eq!(payload["event"]["rule"], "hot");
+    }
+}
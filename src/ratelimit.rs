@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+use crate::config::Values;
+
+// APIの呼び出し回数を制限する（呼び出しの間隔を一定以上空ける）
+// 起動時に1つだけ作成し、全てのApiClient（ジョブ、補完、読み込み直した後の取得）で共有する
+pub struct RateLimiter {
+    // 呼び出しの最小間隔。0の場合は制限しない
+    min_interval: Duration,
+    // 次に呼び出せる時刻
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    // 1分あたりの呼び出し回数から作成する
+    pub fn per_minute(calls: u32) -> Self {
+        let min_interval = if calls == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs(60) / calls
+        };
+        RateLimiter {
+            min_interval,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    // RATE_LIMIT（1分あたりの呼び出し回数、デフォルトは無料プランの60回）から作成する
+    pub fn from_values(values: &Values) -> Result<Self, String> {
+        let calls = values.or("RATE_LIMIT", "60");
+        let calls = calls
+            .parse()
+            .map_err(|_| format!("RATE_LIMIT: invalid number {:?}", calls))?;
+        Ok(RateLimiter::per_minute(calls))
+    }
+
+    // 呼び出せるまで待つ
+    pub async fn acquire(&self) {
+        if self.min_interval.is_zero() {
+            return;
+        }
+        let wait = {
+            let mut next = self.next.lock().await;
+            let now = Instant::now();
+            let slot = (*next).max(now);
+            *next = slot + self.min_interval;
+            slot
+        };
+        tokio::time::sleep_until(wait).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn interval_from_rate() {
+        assert_eq!(
+            RateLimiter::per_minute(60).min_interval,
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            RateLimiter::per_minute(600).min_interval,
+            Duration::from_millis(100)
+        );
+        assert!(RateLimiter::per_minute(0).min_interval.is_zero());
+
+        let mut values = Values::default();
+        assert_eq!(
+            RateLimiter::from_values(&values).unwrap().min_interval,
+            Duration::from_secs(1)
+        );
+        values.set("RATE_LIMIT", "-1");
+        assert_eq!(
+            RateLimiter::from_values(&values).err().unwrap(),
+            "RATE_LIMIT: invalid number \"-1\""
+        );
+    }
+
+    #[tokio::test]
+    async fn shared_limiter_spaces_calls() {
+        // 3回の呼び出しは、複数のタスクから呼んでも間隔を2回分空ける
+        let limiter = Arc::new(RateLimiter::per_minute(600));
+        let started = Instant::now();
+        let mut tasks = tokio::task::JoinSet::new();
+        for _ in 0..3 {
+            let limiter = Arc::clone(&limiter);
+            tasks.spawn(async move { limiter.acquire().await });
+        }
+        while tasks.join_next().await.is_some() {}
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn unlimited_does_not_wait() {
+        let limiter = RateLimiter::per_minute(0);
+        let started = Instant::now();
+        for _ in 0..100 {
+            limiter.acquire().await;
+        }
+        assert!(started.elapsed() < Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,658 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, TimeZone};
+use serde::Serialize;
+use unicode_width::UnicodeWidthStr;
+
+use crate::api::{self, OpenWeaterToTsv};
+use crate::env_or;
+use crate::notify::Notifier;
+use crate::schedule::{self, Cron};
+use crate::store;
+
+// 1件の観測が代表する時間の上限（取得が止まっていた間を雨量や日照に数えない）
+const MAX_GAP: i64 = 3 * 3600;
+
+// 集計の単位
+#[derive(Clone, Copy, PartialEq)]
+pub enum Period {
+    Daily,
+    // 月曜日から日曜日まで
+    Weekly,
+}
+
+impl Period {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "daily" => Ok(Period::Daily),
+            "weekly" => Ok(Period::Weekly),
+            _ => Err(format!("invalid period {:?} (daily, weekly)", value)),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Period::Daily => "daily",
+            Period::Weekly => "weekly",
+        }
+    }
+
+    // 時刻が含まれる期間の初日
+    fn first_day(self, t: DateTime<Local>) -> NaiveDate {
+        let date = t.naive_local().date();
+        match self {
+            Period::Daily => date,
+            Period::Weekly => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+        }
+    }
+
+    // 期間の表示名（日別は日付、週別はISO週番号）
+    fn label(self, first_day: NaiveDate) -> String {
+        match self {
+            Period::Daily => first_day.format("%Y-%m-%d").to_string(),
+            Period::Weekly => {
+                let week = first_day.iso_week();
+                format!("{}-W{:02}", week.year(), week.week())
+            }
+        }
+    }
+
+    // 最高・最低気温の時刻の書式
+    fn time_format(self) -> &'static str {
+        match self {
+            Period::Daily => "%H:%M",
+            Period::Weekly => "%a %H:%M",
+        }
+    }
+}
+
+// 地点・期間ごとの集計
+#[derive(Serialize)]
+pub struct Summary {
+    pub location: String,
+    pub period: String,
+    // 観測の件数
+    pub count: usize,
+    pub temp_min: f64,
+    pub temp_min_at: i64,
+    pub temp_max: f64,
+    pub temp_max_at: i64,
+    pub temp_mean: f64,
+    // 降水量・降雪量の合計、mm
+    pub rain: f64,
+    pub snow: f64,
+    pub gust_max: f64,
+    pub humidity_mean: f64,
+    // 最も長く続いた天気（weather main）
+    pub condition: String,
+    // 日照時間の推定、時間
+    pub sunshine_hours: f64,
+}
+
+// 1時間あたりの量（1時間の値がない場合は3時間の値から求める）
+fn per_hour(h1: f64, h3: f64) -> f64 {
+    if h1 > 0.0 {
+        h1
+    } else {
+        h3 / 3.0
+    }
+}
+
+// 1地点・1期間の観測を集計する。weightsは各観測が代表する秒数
+fn summarize_group(period: String, records: &[&OpenWeaterToTsv], weights: &[i64]) -> Summary {
+    let count = records.len();
+    let min = records
+        .iter()
+        .min_by(|a, b| a.temp.total_cmp(&b.temp))
+        .unwrap();
+    let max = records
+        .iter()
+        .max_by(|a, b| a.temp.total_cmp(&b.temp))
+        .unwrap();
+    let mut rain = 0.0;
+    let mut snow = 0.0;
+    let mut sunshine = 0.0;
+    let mut conditions: HashMap<&str, i64> = HashMap::new();
+    for (record, weight) in records.iter().zip(weights) {
+        let hours = *weight as f64 / 3600.0;
+        rain += per_hour(record.rain_1h, record.rain_3h) * hours;
+        snow += per_hour(record.snow_h1, record.snow_h3) * hours;
+        // 昼間（アイコンが「d」）の晴れている割合（雲量の残り）を日照とみなす
+        if record.icon.ends_with('d') {
+            sunshine += (1.0 - record.all as f64 / 100.0).clamp(0.0, 1.0) * hours;
+        }
+        let condition = if record.weather_to_main.is_empty() {
+            record.description.as_str()
+        } else {
+            record.weather_to_main.as_str()
+        };
+        // 時間が分からない観測も1件として数える
+        *conditions.entry(condition).or_default() += (*weight).max(1);
+    }
+    let condition = conditions
+        .into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(a.0)))
+        .map(|v| v.0.to_string())
+        .unwrap_or_default();
+
+    Summary {
+        location: min.name.clone(),
+        period,
+        count,
+        temp_min: min.temp,
+        temp_min_at: min.dt,
+        temp_max: max.temp,
+        temp_max_at: max.dt,
+        temp_mean: records.iter().map(|v| v.temp).sum::<f64>() / count as f64,
+        rain,
+        snow,
+        gust_max: records.iter().map(|v| v.gust).fold(0.0, f64::max),
+        humidity_mean: records.iter().map(|v| v.humidity as f64).sum::<f64>() / count as f64,
+        condition,
+        sunshine_hours: sunshine,
+    }
+}
+
+// 保存済みの取得結果を地点・期間ごとに集計する（fromからtoまでの観測を対象にする）
+pub fn summarize(
+    records: &[OpenWeaterToTsv],
+    locations: &[String],
+    period: Period,
+    from: i64,
+    to: i64,
+) -> Vec<Summary> {
+    let mut summaries = Vec::new();
+    for location in locations {
+        // 同じ観測時刻の取得結果（取得と補完の重複など）は1件にする
+        let mut list: Vec<&OpenWeaterToTsv> = records
+            .iter()
+            .filter(|v| store::is_location(v, location))
+            .collect();
+        list.sort_by_key(|v| v.dt);
+        list.dedup_by_key(|v| v.dt);
+
+        // 各観測は次の観測までを代表する（最後の観測は直前の間隔を使う）
+        let mut weights: Vec<i64> = list
+            .windows(2)
+            .map(|v| (v[1].dt - v[0].dt).min(MAX_GAP))
+            .collect();
+        weights.push(weights.last().copied().unwrap_or(0));
+
+        let mut groups: BTreeMap<NaiveDate, (Vec<&OpenWeaterToTsv>, Vec<i64>)> = BTreeMap::new();
+        for (record, weight) in list.into_iter().zip(weights) {
+            if record.dt < from || record.dt > to {
+                continue;
+            }
+            // 範囲外の観測時刻は集計しない
+            let observed = match Local.timestamp_opt(record.dt, 0).single() {
+                Some(v) => v,
+                None => continue,
+            };
+            let first_day = period.first_day(observed);
+            let group = groups.entry(first_day).or_default();
+            group.0.push(record);
+            group.1.push(weight);
+        }
+        for (first_day, (records, weights)) in groups {
+            summaries.push(summarize_group(period.label(first_day), &records, &weights));
+        }
+    }
+    summaries
+}
+
+// 保存済みの値（メートル法）を単位の指定（WEATHER_UNITS）に合わせて変換する
+fn convert_temp(celsius: f64, units: &str) -> f64 {
+    match units {
+        "imperial" => celsius * 9.0 / 5.0 + 32.0,
+        "standard" => celsius + 273.15,
+        _ => celsius,
+    }
+}
+
+fn convert_speed(meter_per_sec: f64, units: &str) -> f64 {
+    match units {
+        "imperial" => meter_per_sec * 2.236_936,
+        _ => meter_per_sec,
+    }
+}
+
+// 見出しと各行のセル（気温と風速は単位の指定に合わせる）
+fn cells(summaries: &[Summary], period: Period, units: &str) -> (Vec<String>, Vec<Vec<String>>) {
+    let (temp_unit, speed_unit) = api::unit_labels(units);
+    let header = vec![
+        String::from("location"),
+        String::from("period"),
+        String::from("n"),
+        format!("min {}", temp_unit),
+        String::from("at"),
+        format!("max {}", temp_unit),
+        String::from("at"),
+        format!("mean {}", temp_unit),
+        String::from("rain mm"),
+        String::from("snow mm"),
+        format!("gust {}", speed_unit),
+        String::from("humidity %"),
+        String::from("condition"),
+        String::from("sun h"),
+    ];
+    let time = |t: i64| {
+        Local
+            .timestamp_opt(t, 0)
+            .single()
+            .map(|v| v.format(period.time_format()).to_string())
+    };
+    let temp = |v: f64| format!("{:.1}", convert_temp(v, units));
+    // 時刻を表示できない行は出力しない
+    let rows = summaries
+        .iter()
+        .filter_map(|v| {
+            Some(vec![
+                v.location.clone(),
+                v.period.clone(),
+                v.count.to_string(),
+                temp(v.temp_min),
+                time(v.temp_min_at)?,
+                temp(v.temp_max),
+                time(v.temp_max_at)?,
+                temp(v.temp_mean),
+                format!("{:.1}", v.rain),
+                format!("{:.1}", v.snow),
+                format!("{:.1}", convert_speed(v.gust_max, units)),
+                format!("{:.0}", v.humidity_mean),
+                v.condition.clone(),
+                format!("{:.1}", v.sunshine_hours),
+            ])
+        })
+        .collect();
+    (header, rows)
+}
+
+// 出力の形式（table / tsv / markdown）で整形する
+pub fn render(summaries: &[Summary], period: Period, format: &str, units: &str) -> String {
+    let (header, rows) = cells(summaries, period, units);
+    let mut out = String::new();
+    match format {
+        "tsv" => {
+            for row in std::iter::once(&header).chain(&rows) {
+                out.push_str(&row.join("\t"));
+                out.push('\n');
+            }
+        }
+        "markdown" => {
+            out.push_str(&format!("| {} |\n", header.join(" | ")));
+            out.push_str(&format!("|{}\n", "---|".repeat(header.len())));
+            for row in &rows {
+                out.push_str(&format!("| {} |\n", row.join(" | ")));
+            }
+        }
+        _ => {
+            // 列ごとの幅に揃える（全角文字は幅2として数える）
+            let mut widths: Vec<usize> = header.iter().map(|v| v.width()).collect();
+            for row in &rows {
+                for (width, cell) in widths.iter_mut().zip(row) {
+                    *width = (*width).max(cell.width());
+                }
+            }
+            for row in std::iter::once(&header).chain(&rows) {
+                let line: Vec<String> = row
+                    .iter()
+                    .zip(&widths)
+                    .map(|(cell, width)| format!("{}{}", cell, " ".repeat(width - cell.width())))
+                    .collect();
+                out.push_str(line.join("  ").trim_end());
+                out.push('\n');
+            }
+        }
+    }
+    out
+}
+
+// 保存済みの取得結果を集計して表示する（DISPLAY_MODEがtsv/markdownの場合はその形式、それ以外は表）
+pub fn print(
+    locations: &[String],
+    period: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let period = Period::parse(&period.unwrap_or_else(|| env_or("REPORT_PERIOD", "daily")))?;
+    let from = match from {
+        Some(v) => store::parse_time(&v).ok_or(format!("invalid time: {}", v))?,
+        None => i64::MIN,
+    };
+    let to = match to {
+        Some(v) => store::parse_time(&v).ok_or(format!("invalid time: {}", v))?,
+        None => i64::MAX,
+    };
+    let records = store::load_records(Path::new(store::LOG_DIR))?;
+    let summaries = summarize(&records, locations, period, from, to);
+    print!(
+        "{}",
+        render(
+            &summaries,
+            period,
+            &env_or("DISPLAY_MODE", "table"),
+            &env_or("WEATHER_UNITS", "metric")
+        )
+    );
+    Ok(())
+}
+
+// REPORT_TIME（「07:00」など）の設定からcron式を作る
+// 日別は毎日、週別は毎週月曜日のその時刻に送る
+pub fn parse_time(value: &str, period: Period) -> Result<Cron, String> {
+    let (hour, minute) = value
+        .split_once(':')
+        .and_then(|(h, m)| Some((h.parse::<u32>().ok()?, m.parse::<u32>().ok()?)))
+        .filter(|(h, m)| *h < 24 && *m < 60)
+        .ok_or_else(|| format!("invalid time {:?} (HH:MM)", value))?;
+    let weekday = match period {
+        Period::Daily => "*",
+        Period::Weekly => "1",
+    };
+    Cron::parse(&format!("{} {} * * {}", minute, hour, weekday))
+}
+
+// 毎日REPORT_TIMEに前日（週別の場合は前週）の集計を通知先に送る
+// 通知先と地点は開始時の設定のまま変えない
+pub fn spawn(notifier: Arc<Notifier>, locations: Vec<String>) -> Result<(), String> {
+    let time = env_or("REPORT_TIME", "");
+    if time.is_empty() {
+        return Ok(());
+    }
+    let period = Period::parse(&env_or("REPORT_PERIOD", "daily"))
+        .map_err(|e| format!("REPORT_PERIOD: {}", e))?;
+    let cron = parse_time(&time, period).map_err(|e| format!("REPORT_TIME: {}", e))?;
+    let units = env_or("WEATHER_UNITS", "metric");
+    if notifier.is_empty() {
+        tracing::warn!("REPORT_TIME is set but no notification channel is configured");
+        return Ok(());
+    }
+
+    tokio::spawn(async move {
+        while let Some(next) = cron.next_after(Local::now()) {
+            tokio::time::sleep(schedule::wait_time(next)).await;
+
+            // 送る時刻の前日が含まれる期間（週別の場合は月曜日に送るので前週）
+            let first_day = period.first_day(next - Duration::days(1));
+            let label = period.label(first_day);
+            let records = match store::load_records(Path::new(store::LOG_DIR)) {
+                Ok(v) => v,
+                Err(e) => {
+                    tracing::error!(error = %e, "report failed");
+                    continue;
+                }
+            };
+            let summaries: Vec<Summary> =
+                summarize(&records, &locations, period, i64::MIN, i64::MAX)
+                    .into_iter()
+                    .filter(|v| v.period == label)
+                    .collect();
+            if summaries.is_empty() {
+                tracing::info!(period = %label, "report skipped, no records");
+                continue;
+            }
+            let subject = format!("[openweather] {} report {}", period.name(), label);
+            let body = render(&summaries, period, "markdown", &units);
+            let event = serde_json::to_value(&summaries).unwrap_or_default();
+            notifier.notify(subject, body, event);
+            tracing::info!(period = %label, "report queued");
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ローカル時間の時刻（タイムゾーンに依らないよう1月の日中を使う）
+    fn at(day: u32, hour: u32) -> i64 {
+        Local.ymd(2024, 1, day).and_hms(hour, 0, 0).timestamp()
+    }
+
+    fn record(name: &str, dt: i64, temp: f64) -> OpenWeaterToTsv {
+        let mut record = OpenWeaterToTsv::new();
+        record.name = String::from(name);
+        record.dt = dt;
+        record.temp = temp;
+        record
+    }
+
+    #[test]
+    fn group_weights() {
+        let mut rain = record("Kyoto", at(8, 9), 5.0);
+        rain.rain_1h = 2.0;
+        rain.weather_to_main = String::from("Rain");
+        rain.icon = String::from("10d");
+        rain.all = 100;
+        rain.humidity = 90;
+        // 1時間の値がない場合は3時間の値を使う
+        let mut rain_3h = record("Kyoto", at(8, 10), 7.0);
+        rain_3h.rain_3h = 3.0;
+        rain_3h.snow_h3 = 6.0;
+        rain_3h.weather_to_main = String::from("Rain");
+        rain_3h.icon = String::from("13d");
+        rain_3h.all = 100;
+        rain_3h.gust = 8.5;
+        rain_3h.humidity = 80;
+        let mut clear = record("Kyoto", at(8, 11), 12.0);
+        clear.weather_to_main = String::from("Clear");
+        clear.icon = String::from("01d");
+        clear.all = 25;
+        clear.humidity = 40;
+        // 夜間は日照に数えない
+        let mut night = record("Kyoto", at(8, 20), 3.0);
+        night.weather_to_main = String::from("Clear");
+        night.icon = String::from("01n");
+        night.humidity = 50;
+
+        let records = [&rain, &rain_3h, &clear, &night];
+        let summary = summarize_group(
+            String::from("2024-01-08"),
+            &records,
+            &[1800, 3600, 7200, 3600],
+        );
+
+        assert_eq!(summary.location, "Kyoto");
+        assert_eq!(summary.count, 4);
+        assert_eq!((summary.temp_min, summary.temp_min_at), (3.0, at(8, 20)));
+        assert_eq!((summary.temp_max, summary.temp_max_at), (12.0, at(8, 11)));
+        assert_eq!(summary.temp_mean, 6.75);
+        assert!((summary.rain - 2.0).abs() < 1e-9);
+        assert!((summary.snow - 2.0).abs() < 1e-9);
+        assert!((summary.sunshine_hours - 1.5).abs() < 1e-9);
+        assert_eq!(summary.gust_max, 8.5);
+        assert_eq!(summary.humidity_mean, 65.0);
+        // 件数ではなく続いた時間で選ぶ（Rain 5400秒、Clear 10800秒）
+        assert_eq!(summary.condition, "Clear");
+    }
+
+    #[test]
+    fn group_condition_tie() {
+        let mut rain = record("Kyoto", at(8, 9), 5.0);
+        rain.weather_to_main = String::from("Rain");
+        let mut clouds = record("Kyoto", at(8, 10), 5.0);
+        clouds.weather_to_main = String::from("Clouds");
+        // weather mainがない場合は説明を使う
+        let mut mist = record("Kyoto", at(8, 11), 5.0);
+        mist.description = String::from("mist");
+
+        let summary = summarize_group(String::new(), &[&rain, &clouds, &mist], &[3600, 3600, 0]);
+        // 同じ時間の場合は名前順
+        assert_eq!(summary.condition, "Clouds");
+        let summary = summarize_group(String::new(), &[&mist], &[0]);
+        assert_eq!(summary.condition, "mist");
+    }
+
+    #[test]
+    fn summarize_caps_gaps() {
+        let mut records: Vec<OpenWeaterToTsv> = [at(8, 0), at(8, 1), at(8, 10), at(8, 12)]
+            .iter()
+            .map(|dt| {
+                let mut v = record("Kyoto", *dt, 5.0);
+                v.rain_1h = 1.0;
+                v
+            })
+            .collect();
+        // 同じ観測時刻の重複と別の地点は数えない
+        records.push(records[1].clone());
+        records.push(record("Nara", at(8, 2), 5.0));
+
+        let summaries = summarize(
+            &records,
+            &[String::from("kyoto")],
+            Period::Daily,
+            i64::MIN,
+            i64::MAX,
+        );
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].count, 4);
+        assert_eq!(summaries[0].period, "2024-01-08");
+        // 1時間 + 3時間（9時間の間隔を切り詰め） + 2時間 + 2時間（最後は直前の間隔）
+        assert!((summaries[0].rain - 8.0).abs() < 1e-9);
+
+        // 範囲外の観測を除いても重みは前後の観測から決める
+        let summaries = summarize(
+            &records,
+            &[String::from("Kyoto")],
+            Period::Daily,
+            at(8, 1),
+            at(8, 1),
+        );
+        assert_eq!(summaries[0].count, 1);
+        assert!((summaries[0].rain - 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn summarize_weeks_start_on_monday() {
+        // 2024-01-07は日曜日、2024-01-08は月曜日
+        let records = vec![
+            record("Kyoto", at(1, 12), 1.0),
+            record("Kyoto", at(7, 23), 2.0),
+            record("Kyoto", at(8, 1), 3.0),
+            record("Kyoto", at(14, 12), 4.0),
+        ];
+        let locations = [String::from("Kyoto")];
+
+        let weekly = summarize(&records, &locations, Period::Weekly, i64::MIN, i64::MAX);
+        let periods: Vec<(&str, usize)> = weekly
+            .iter()
+            .map(|v| (v.period.as_str(), v.count))
+            .collect();
+        assert_eq!(periods, vec![("2024-W01", 2), ("2024-W02", 2)]);
+
+        let daily = summarize(&records, &locations, Period::Daily, i64::MIN, i64::MAX);
+        let periods: Vec<&str> = daily.iter().map(|v| v.period.as_str()).collect();
+        assert_eq!(
+            periods,
+            vec!["2024-01-01", "2024-01-07", "2024-01-08", "2024-01-14"]
+        );
+    }
+
+    fn summary() -> Summary {
+        Summary {
+            location: String::from("Kyoto"),
+            period: String::from("2024-W02"),
+            count: 3,
+            temp_min: -1.25,
+            temp_min_at: at(8, 6),
+            temp_max: 9.96,
+            temp_max_at: at(10, 14),
+            temp_mean: 4.0,
+            rain: 1.04,
+            snow: 0.0,
+            gust_max: 12.3,
+            humidity_mean: 66.6,
+            condition: String::from("Clouds"),
+            sunshine_hours: 2.5,
+        }
+    }
+
+    #[test]
+    fn render_markdown() {
+        let out = render(&[summary()], Period::Weekly, "markdown", "metric");
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("| location | period | n | min °C | at |"));
+        assert_eq!(lines[1], format!("|{}", "---|".repeat(14)));
+        assert_eq!(
+            lines[2],
+            "| Kyoto | 2024-W02 | 3 | -1.2 | Mon 06:00 | 10.0 | Wed 14:00 | 4.0 | 1.0 | 0.0 | 12.3 | 67 | Clouds | 2.5 |"
+        );
+    }
+
+    #[test]
+    fn render_tsv() {
+        let out = render(&[summary()], Period::Daily, "tsv", "metric");
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].split('\t').count(), 14);
+        assert_eq!(
+            lines[1],
+            "Kyoto\t2024-W02\t3\t-1.2\t06:00\t10.0\t14:00\t4.0\t1.0\t0.0\t12.3\t67\tClouds\t2.5"
+        );
+        // 集計がない場合は見出しのみ
+        assert_eq!(
+            render(&[], Period::Daily, "tsv", "metric").lines().count(),
+            1
+        );
+    }
+
+    #[test]
+    fn render_in_units() {
+        let out = render(&[summary()], Period::Daily, "tsv", "imperial");
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines[0],
+            "location\tperiod\tn\tmin °F\tat\tmax °F\tat\tmean °F\train mm\tsnow mm\tgust mph\thumidity %\tcondition\tsun h"
+        );
+        assert_eq!(
+            lines[1],
+            "Kyoto\t2024-W02\t3\t29.8\t06:00\t49.9\t14:00\t39.2\t1.0\t0.0\t27.5\t67\tClouds\t2.5"
+        );
+        let out = render(&[summary()], Period::Daily, "tsv", "standard");
+        assert!(out.starts_with("location\tperiod\tn\tmin K\tat\tmax K"));
+    }
+
+    #[test]
+    fn skip_out_of_range_times() {
+        let mut bad = summary();
+        bad.temp_max_at = i64::MAX;
+        let out = render(&[bad, summary()], Period::Daily, "tsv", "metric");
+        assert_eq!(out.lines().count(), 2);
+
+        // 範囲外の観測時刻の取得結果は集計しない
+        let mut record = OpenWeaterToTsv::new();
+        record.name = String::from("Kyoto");
+        record.dt = at(8, 6);
+        let mut bad = record.clone();
+        bad.dt = i64::MAX;
+        let locations = [String::from("Kyoto")];
+        let summaries = summarize(
+            &[record, bad],
+            &locations,
+            Period::Daily,
+            i64::MIN,
+            i64::MAX,
+        );
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].count, 1);
+    }
+
+    #[test]
+    fn render_table_aligns_wide_names() {
+        let mut wide = summary();
+        wide.location = String::from("京都");
+        let out = render(&[summary(), wide], Period::Daily, "table", "metric");
+        let lines: Vec<&str> = out.lines().collect();
+        // 全角文字を幅2として揃える
+        let column = |line: &str| line.find("2024-W02").map(|i| line[..i].width());
+        assert_eq!(column(lines[1]), column(lines[2]));
+        assert_eq!(
+            lines[0].find("period").map(|i| lines[0][..i].width()),
+            column(lines[1])
+        );
+    }
+}
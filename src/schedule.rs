@@ -0,0 +1,606 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Local, TimeZone, Timelike};
+
+use crate::alerts;
+use crate::config::Values;
+use crate::store;
+
+// cron式（分 時 日 月 曜日）
+// 各項目は「*」「*/n」「a」「a-b」「a-b/n」とその「,」区切りを受け付ける
+#[derive(Clone)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // 日と曜日の両方が指定されている場合はどちらかに一致すれば実行する
+    day_or_weekday: bool,
+}
+
+// cronの1項目をビット集合に変換する
+fn parse_field(field: &str, min: u32, max: u32, name: &str) -> Result<(u64, bool), String> {
+    let mut bits = 0u64;
+    let mut restricted = true;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step
+                    .parse()
+                    .map_err(|_| format!("{}: invalid step {:?}", name, part))?;
+                if step == 0 {
+                    return Err(format!("{}: invalid step {:?}", name, part));
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let (from, to) = if range == "*" {
+            if step == 1 {
+                restricted = false;
+            }
+            (min, max)
+        } else {
+            let parse = |v: &str| {
+                v.parse::<u32>()
+                    .map_err(|_| format!("{}: invalid value {:?}", name, part))
+            };
+            match range.split_once('-') {
+                Some((from, to)) => (parse(from)?, parse(to)?),
+                // 「5/15」は5から最大値まで
+                None if step > 1 => (parse(range)?, max),
+                None => {
+                    let v = parse(range)?;
+                    (v, v)
+                }
+            }
+        };
+        if from < min || to > max || from > to {
+            return Err(format!(
+                "{}: {:?} is out of range {}-{}",
+                name, part, min, max
+            ));
+        }
+        for v in (from..=to).step_by(step as usize) {
+            bits |= 1 << v;
+        }
+    }
+    Ok((bits, restricted))
+}
+
+impl Cron {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = value.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!(
+                "cron expression needs 5 fields (minute hour day month weekday): {:?}",
+                value
+            ));
+        }
+        let (minutes, _) = parse_field(fields[0], 0, 59, "minute")?;
+        let (hours, _) = parse_field(fields[1], 0, 23, "hour")?;
+        let (days, days_restricted) = parse_field(fields[2], 1, 31, "day")?;
+        let (months, _) = parse_field(fields[3], 1, 12, "month")?;
+        // 日曜日は0と7のどちらでも指定できる
+        let (mut weekdays, weekdays_restricted) = parse_field(fields[4], 0, 7, "weekday")?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Cron {
+            minutes,
+            hours,
+            days,
+            months,
+            weekdays,
+            day_or_weekday: days_restricted && weekdays_restricted,
+        })
+    }
+
+    // 日付（日、月、曜日）が一致するか
+    fn matches_date(&self, t: &DateTime<Local>) -> bool {
+        let day = self.days & (1 << t.day()) != 0;
+        let weekday = self.weekdays & (1 << t.weekday().num_days_from_sunday()) != 0;
+        let date = if self.day_or_weekday {
+            day || weekday
+        } else {
+            day && weekday
+        };
+        self.months & (1 << t.month()) != 0 && date
+    }
+
+    // tより後で最初に一致する時刻（1年以内に一致しない場合はNone）
+    // 日付や時が一致しない場合は翌日や次の時まで進めて探す
+    pub fn next_after(&self, t: DateTime<Local>) -> Option<DateTime<Local>> {
+        let start = t.timestamp() - t.timestamp().rem_euclid(60) + 60;
+        let limit = start + 366 * 24 * 3600;
+        let mut next = Local.timestamp(start, 0);
+        while next.timestamp() < limit {
+            if !self.matches_date(&next) {
+                next = next_day(next);
+                continue;
+            }
+            if self.hours & (1 << next.hour()) == 0 {
+                next = next_hour(next);
+                continue;
+            }
+            // 同じ時の中で次に一致する分
+            match (next.minute()..60).find(|m| self.minutes & (1 << m) != 0) {
+                Some(m) if m == next.minute() => return Some(next),
+                Some(m) => {
+                    next = Local.timestamp(next.timestamp() + (m - next.minute()) as i64 * 60, 0)
+                }
+                None => next = next_hour(next),
+            }
+        }
+        None
+    }
+}
+
+// 次の時の0分
+fn next_hour(t: DateTime<Local>) -> DateTime<Local> {
+    Local.timestamp(t.timestamp() - t.minute() as i64 * 60 + 3600, 0)
+}
+
+// 翌日の0時（夏時間の切り替えで0時がない場合は次の時）
+fn next_day(t: DateTime<Local>) -> DateTime<Local> {
+    let midnight = t.naive_local().date().succ().and_hms(0, 0, 0);
+    Local
+        .from_local_datetime(&midnight)
+        .earliest()
+        .unwrap_or_else(|| next_hour(t))
+}
+
+// 取得のスケジュール
+#[derive(Clone)]
+pub enum Schedule {
+    // 一定間隔（秒）。alignedの場合はローカル時間の0時を起点に区切る（30分なら毎時0分と30分）
+    Interval { secs: i64, aligned: bool },
+    Cron(Cron),
+}
+
+impl Schedule {
+    // 一定間隔のスケジュール（「30m」など）
+    pub fn interval(value: &str, aligned: bool) -> Result<Self, String> {
+        let secs = alerts::parse_duration(value)?;
+        if secs <= 0 {
+            return Err(String::from("must be greater than 0"));
+        }
+        Ok(Schedule::Interval { secs, aligned })
+    }
+
+    // SCHEDULE（cron式）、INTERVAL、ALIGNから作成する
+    pub fn from_values(values: &Values) -> Result<Self, String> {
+        match values.get("SCHEDULE") {
+            Some(cron) => Ok(Schedule::Cron(
+                Cron::parse(cron).map_err(|e| format!("SCHEDULE: {}", e))?,
+            )),
+            None => Schedule::interval(
+                &values.or("INTERVAL", "30m"),
+                values.or("ALIGN", "1") == "1",
+            )
+            .map_err(|e| format!("INTERVAL: {}", e)),
+        }
+    }
+
+    // startを起点に、nowより後の次の実行時刻を求める
+    // 前回の実行時刻ではなく起点から計算するため、取得にかかった時間でずれない
+    pub fn next_after(
+        &self,
+        start: DateTime<Local>,
+        now: DateTime<Local>,
+    ) -> Option<DateTime<Local>> {
+        match self {
+            Schedule::Interval { secs, aligned } => {
+                let anchor = if *aligned {
+                    // ローカル時間の0時（UNIX時間0）に揃える
+                    -(now.offset().local_minus_utc() as i64)
+                } else {
+                    start.timestamp()
+                };
+                let elapsed = now.timestamp() - anchor;
+                let next = anchor + (elapsed.div_euclid(*secs) + 1) * secs;
+                Some(Local.timestamp(next, 0))
+            }
+            Schedule::Cron(cron) => cron.next_after(now),
+        }
+    }
+}
+
+// スケジュールと終了条件
+#[derive(Clone)]
+pub struct Scheduler {
+    schedule: Schedule,
+    start: DateTime<Local>,
+    // 開始からの期間（DURATION、秒）
+    duration: Option<i64>,
+    // 終了時刻（UNTIL）
+    until: Option<DateTime<Local>>,
+    // 実行回数の上限
+    runs: Option<u64>,
+    count: u64,
+}
+
+impl Scheduler {
+    // SCHEDULE（cron式）、INTERVAL、ALIGN、DURATION、UNTIL、RUNSから作成する
+    pub fn from_values(values: &Values) -> Result<Self, String> {
+        let schedule = Schedule::from_values(values)?;
+
+        let runs = values.or("RUNS", "0");
+        let runs = if runs == "0" {
+            None
+        } else {
+            Some(
+                runs.parse::<u64>()
+                    .map_err(|_| format!("RUNS: invalid number: {}", runs))?,
+            )
+        };
+
+        let (duration, until) = limits(values)?;
+        Scheduler::with_limits(schedule, Local::now(), duration, until, runs)
+    }
+
+    // 別のスケジュールと実行回数の上限で作成する
+    // 開始時刻と終了時刻（DURATIONとUNTIL）は全てのジョブで共通
+    pub fn with_schedule(&self, schedule: Schedule, runs: Option<u64>) -> Result<Self, String> {
+        Scheduler::with_limits(schedule, self.start, self.duration, self.until, runs)
+    }
+
+    fn with_limits(
+        schedule: Schedule,
+        start: DateTime<Local>,
+        duration: Option<i64>,
+        until: Option<DateTime<Local>>,
+        runs: Option<u64>,
+    ) -> Result<Self, String> {
+        if schedule.next_after(start, start).is_none() {
+            return Err(String::from("SCHEDULE: cron expression never fires"));
+        }
+
+        Ok(Scheduler {
+            schedule,
+            start,
+            duration,
+            until,
+            runs,
+            count: 0,
+        })
+    }
+
+    // 前回の開始時刻と実行回数から続ける（終了時刻もDURATIONに従って開始時刻から数え直す）
+    // 前回の分で既に終了条件に達している場合は引き継がずにfalseを返す
+    pub fn resume(&mut self, start: DateTime<Local>, count: u64) -> bool {
+        let resumed = Scheduler {
+            start,
+            count,
+            ..self.clone()
+        };
+        if resumed.next(Local::now()).is_none() {
+            return false;
+        }
+        *self = resumed;
+        true
+    }
+
+    // 終了時刻（DURATIONとUNTILの早い方）
+    fn end(&self) -> Option<DateTime<Local>> {
+        let end = self
+            .duration
+            .map(|secs| Local.timestamp(self.start.timestamp() + secs, 0));
+        match (end, self.until) {
+            (Some(end), Some(until)) => Some(end.min(until)),
+            (end, until) => end.or(until),
+        }
+    }
+
+    // 取得を開始した時刻
+    pub fn start(&self) -> DateTime<Local> {
+        self.start
+    }
+
+    // 実行した回数
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    // 1回実行したことを記録する
+    pub fn mark_run(&mut self) {
+        self.count += 1;
+    }
+
+    // 次の実行時刻。終了条件に達した場合はNone
+    pub fn next(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        if let Some(runs) = self.runs {
+            if self.count >= runs {
+                return None;
+            }
+        }
+        let next = self.schedule.next_after(self.start, now)?;
+        match self.end() {
+            Some(end) if next > end => None,
+            _ => Some(next),
+        }
+    }
+}
+
+// DURATIONとUNTILを読み込む
+fn limits(values: &Values) -> Result<(Option<i64>, Option<DateTime<Local>>), String> {
+    let duration = values.or("DURATION", "7d");
+    // 「0」の場合は時間で終了しない
+    let duration = if duration != "0" {
+        Some(alerts::parse_duration(&duration).map_err(|e| format!("DURATION: {}", e))?)
+    } else {
+        None
+    };
+    let until = match values.get("UNTIL") {
+        Some(until) => {
+            let until = store::parse_time(until)
+                .ok_or_else(|| format!("UNTIL: invalid time: {}", until))?;
+            Some(Local.timestamp(until, 0))
+        }
+        None => None,
+    };
+    Ok((duration, until))
+}
+
+// 時刻までの待ち時間（過ぎている場合は0）
+pub fn wait_time(t: DateTime<Local>) -> Duration {
+    (t - Local::now()).to_std().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(y: i32, m: u32, d: u32, h: u32, mi: u32) -> DateTime<Local> {
+        Local.ymd(y, m, d).and_hms(h, mi, 0)
+    }
+
+    fn next(cron: &str, t: DateTime<Local>) -> Option<DateTime<Local>> {
+        Cron::parse(cron).unwrap().next_after(t)
+    }
+
+    fn interval(secs: i64) -> Schedule {
+        Schedule::Interval {
+            secs,
+            aligned: true,
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(Cron::parse("* * * *").err().unwrap().contains("5 fields"));
+        assert_eq!(
+            Cron::parse("60 * * * *").err().unwrap(),
+            "minute: \"60\" is out of range 0-59"
+        );
+        assert_eq!(
+            Cron::parse("* 5-2 * * *").err().unwrap(),
+            "hour: \"5-2\" is out of range 0-23"
+        );
+        assert_eq!(
+            Cron::parse("* * 0 * *").err().unwrap(),
+            "day: \"0\" is out of range 1-31"
+        );
+        assert_eq!(
+            Cron::parse("*/0 * * * *").err().unwrap(),
+            "minute: invalid step \"*/0\""
+        );
+        assert_eq!(
+            Cron::parse("* * * jan *").err().unwrap(),
+            "month: invalid value \"jan\""
+        );
+    }
+
+    #[test]
+    fn every_minute_skips_to_next_whole_minute() {
+        let t = Local.ymd(2024, 1, 1).and_hms(10, 0, 30);
+        assert_eq!(next("* * * * *", t), Some(at(2024, 1, 1, 10, 1)));
+        assert_eq!(
+            next("* * * * *", at(2024, 1, 1, 10, 0)),
+            Some(at(2024, 1, 1, 10, 1))
+        );
+    }
+
+    #[test]
+    fn ranges_and_steps() {
+        let cron = "*/15 9-17 * * *";
+        assert_eq!(
+            next(cron, at(2024, 1, 1, 8, 50)),
+            Some(at(2024, 1, 1, 9, 0))
+        );
+        assert_eq!(
+            next(cron, at(2024, 1, 1, 9, 0)),
+            Some(at(2024, 1, 1, 9, 15))
+        );
+        assert_eq!(
+            next(cron, at(2024, 1, 1, 17, 45)),
+            Some(at(2024, 1, 2, 9, 0))
+        );
+        // 「5/15」は5から最大値まで
+        assert_eq!(
+            next("5/15 * * * *", at(2024, 1, 1, 9, 6)),
+            Some(at(2024, 1, 1, 9, 20))
+        );
+        assert_eq!(
+            next("5/15 * * * *", at(2024, 1, 1, 9, 50)),
+            Some(at(2024, 1, 1, 10, 5))
+        );
+        // 範囲の刻みと一覧
+        assert_eq!(
+            next("0 8-20/6,23 * * *", at(2024, 1, 1, 14, 0)),
+            Some(at(2024, 1, 1, 20, 0))
+        );
+        assert_eq!(
+            next("0 8-20/6,23 * * *", at(2024, 1, 1, 20, 0)),
+            Some(at(2024, 1, 1, 23, 0))
+        );
+    }
+
+    #[test]
+    fn day_of_month_and_month() {
+        // 毎月1日
+        assert_eq!(
+            next("30 6 1 * *", at(2024, 1, 15, 0, 0)),
+            Some(at(2024, 2, 1, 6, 30))
+        );
+        // 31日がない月は飛ばす
+        assert_eq!(
+            next("0 0 31 * *", at(2024, 4, 1, 0, 0)),
+            Some(at(2024, 5, 31, 0, 0))
+        );
+        // うるう年の2月29日は1年以内に見つかる
+        assert_eq!(
+            next("0 0 29 2 *", at(2023, 3, 1, 0, 0)),
+            Some(at(2024, 2, 29, 0, 0))
+        );
+    }
+
+    #[test]
+    fn day_of_week() {
+        // 2024-09-10は火曜日
+        assert_eq!(
+            next("0 12 * * 0", at(2024, 9, 10, 0, 0)),
+            Some(at(2024, 9, 15, 12, 0))
+        );
+        // 日曜日は7でも指定できる
+        assert_eq!(
+            next("0 12 * * 7", at(2024, 9, 10, 0, 0)),
+            Some(at(2024, 9, 15, 12, 0))
+        );
+        assert_eq!(
+            next("0 9 * * 1-5", at(2024, 9, 13, 10, 0)),
+            Some(at(2024, 9, 16, 9, 0))
+        );
+    }
+
+    #[test]
+    fn day_of_month_or_day_of_week() {
+        // 日と曜日の両方を指定した場合はどちらかに一致すれば実行する（13日または金曜日）
+        let cron = "0 0 13 * 5";
+        assert_eq!(next(cron, at(2024, 9, 1, 0, 0)), Some(at(2024, 9, 6, 0, 0)));
+        assert_eq!(
+            next(cron, at(2024, 10, 5, 0, 0)),
+            Some(at(2024, 10, 11, 0, 0))
+        );
+        assert_eq!(
+            next(cron, at(2024, 10, 11, 0, 0)),
+            Some(at(2024, 10, 13, 0, 0))
+        );
+        // 曜日が「*」の場合は日のみで判定する
+        assert_eq!(
+            next("0 0 13 * *", at(2024, 9, 1, 0, 0)),
+            Some(at(2024, 9, 13, 0, 0))
+        );
+    }
+
+    #[test]
+    fn never_fires() {
+        assert_eq!(next("0 0 31 2 *", at(2024, 1, 1, 0, 0)), None);
+        // 2月29日は次のうるう年まで1年以上ある
+        assert_eq!(next("0 0 29 2 *", at(2024, 3, 1, 0, 0)), None);
+
+        let cron = Schedule::Cron(Cron::parse("0 0 30 2 *").unwrap());
+        assert_eq!(
+            Scheduler::with_limits(cron, Local::now(), None, None, None)
+                .err()
+                .unwrap(),
+            "SCHEDULE: cron expression never fires"
+        );
+    }
+
+    #[test]
+    fn interval_next_run() {
+        let start = at(2024, 1, 1, 10, 7);
+        let aligned = interval(1800);
+        assert_eq!(
+            aligned.next_after(start, start),
+            Some(at(2024, 1, 1, 10, 30))
+        );
+        assert_eq!(
+            aligned.next_after(start, at(2024, 1, 1, 10, 30)),
+            Some(at(2024, 1, 1, 11, 0))
+        );
+        // 揃えない場合は開始時刻から数える
+        let fixed = Schedule::Interval {
+            secs: 1800,
+            aligned: false,
+        };
+        assert_eq!(
+            fixed.next_after(start, at(2024, 1, 1, 10, 20)),
+            Some(at(2024, 1, 1, 10, 37))
+        );
+        assert_eq!(
+            fixed.next_after(start, at(2024, 1, 1, 10, 37)),
+            Some(at(2024, 1, 1, 11, 7))
+        );
+    }
+
+    #[test]
+    fn ends_after_runs() {
+        let start = at(2024, 1, 1, 10, 0);
+        let mut scheduler =
+            Scheduler::with_limits(interval(600), start, None, None, Some(2)).unwrap();
+        assert_eq!(scheduler.next(start), Some(at(2024, 1, 1, 10, 10)));
+        scheduler.mark_run();
+        assert!(scheduler.next(start).is_some());
+        scheduler.mark_run();
+        assert_eq!(scheduler.count(), 2);
+        assert_eq!(scheduler.next(start), None);
+    }
+
+    #[test]
+    fn ends_at_duration_or_until() {
+        let start = at(2024, 1, 1, 10, 0);
+        let scheduler =
+            Scheduler::with_limits(interval(1800), start, Some(3600), None, None).unwrap();
+        assert_eq!(
+            scheduler.next(at(2024, 1, 1, 10, 30)),
+            Some(at(2024, 1, 1, 11, 0))
+        );
+        assert_eq!(scheduler.next(at(2024, 1, 1, 11, 0)), None);
+
+        // UNTILがDURATIONより早い場合はUNTILで終了する
+        let until = Some(at(2024, 1, 1, 10, 45));
+        let scheduler =
+            Scheduler::with_limits(interval(1800), start, Some(3600), until, None).unwrap();
+        assert_eq!(
+            scheduler.next(at(2024, 1, 1, 10, 0)),
+            Some(at(2024, 1, 1, 10, 30))
+        );
+        assert_eq!(scheduler.next(at(2024, 1, 1, 10, 30)), None);
+
+        // 期間の指定がない場合は終了しない
+        let scheduler = Scheduler::with_limits(interval(1800), start, None, None, None).unwrap();
+        assert!(scheduler.next(at(2030, 1, 1, 0, 0)).is_some());
+    }
+
+    #[test]
+    fn resume_keeps_start_and_count() {
+        let now = Local::now();
+        let mut scheduler =
+            Scheduler::with_limits(interval(1800), now, Some(7 * 86400), None, Some(10)).unwrap();
+        let started = now - chrono::Duration::days(2);
+        assert!(scheduler.resume(started, 4));
+        assert_eq!(scheduler.start(), started);
+        assert_eq!(scheduler.count(), 4);
+        // 終了時刻は前回の開始時刻から数える
+        assert_eq!(
+            scheduler.end(),
+            Some(Local.timestamp(started.timestamp() + 7 * 86400, 0))
+        );
+    }
+
+    #[test]
+    fn resume_ignores_finished_schedule() {
+        let now = Local::now();
+        let mut scheduler =
+            Scheduler::with_limits(interval(1800), now, Some(7 * 86400), None, Some(10)).unwrap();
+        // 実行回数の上限に達している
+        assert!(!scheduler.resume(now - chrono::Duration::hours(1), 10));
+        // 期間が過ぎている
+        assert!(!scheduler.resume(now - chrono::Duration::days(8), 1));
+        assert_eq!(scheduler.start(), now);
+        assert_eq!(scheduler.count(), 0);
+    }
+}
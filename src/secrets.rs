@@ -0,0 +1,216 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+
+use crate::config;
+
+// 読み込んだAPIキー（ログやエラーメッセージから伏せるために保持する）
+static API_KEY: OnceLock<String> = OnceLock::new();
+
+// APIキーの読み込み元
+pub enum KeySource {
+    // API_KEY環境変数（.envを含む）
+    Env,
+    // API_KEY_FILE
+    File(PathBuf),
+    // API_KEY_COMMAND
+    Command,
+}
+
+impl std::fmt::Display for KeySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeySource::Env => write!(f, "API_KEY"),
+            KeySource::File(path) => write!(f, "file {}", path.display()),
+            KeySource::Command => write!(f, "API_KEY_COMMAND"),
+        }
+    }
+}
+
+// APIキーを読み込む
+// API_KEY、API_KEY_FILE、API_KEY_COMMANDの順に探し、どれもなければNoneを返す
+// 子プロセス（通知のコマンドなど）にはAPI_KEY環境変数を渡さない（env_remove）
+pub fn load_api_key() -> Result<Option<(String, KeySource)>, String> {
+    let from_env = config::current().or("API_KEY", "");
+
+    let loaded = if !from_env.trim().is_empty() {
+        Some((from_env.trim().to_string(), KeySource::Env))
+    } else if let Some(path) = non_empty_env("API_KEY_FILE") {
+        let path = PathBuf::from(path);
+        Some((read_key_file(&path)?, KeySource::File(path)))
+    } else if let Some(command) = non_empty_env("API_KEY_COMMAND") {
+        Some((run_key_command(&command)?, KeySource::Command))
+    } else {
+        None
+    };
+
+    if let Some((key, _)) = &loaded {
+        let _ = API_KEY.set(key.clone());
+    }
+    Ok(loaded)
+}
+
+fn non_empty_env(key: &str) -> Option<String> {
+    config::current()
+        .get(key)
+        .filter(|v| !v.trim().is_empty())
+        .map(String::from)
+}
+
+// キーファイルを読み込む。所有者以外が読み書きできる場合はエラーにする
+fn read_key_file(path: &Path) -> Result<String, String> {
+    let metadata =
+        fs::metadata(path).map_err(|e| format!("API_KEY_FILE {}: {}", path.display(), e))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = metadata.permissions().mode();
+        if mode & 0o077 != 0 {
+            return Err(format!(
+                "API_KEY_FILE {}: permissions {:o} are too open, run: chmod 600 {}",
+                path.display(),
+                mode & 0o777,
+                path.display()
+            ));
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = metadata;
+
+    let key =
+        fs::read_to_string(path).map_err(|e| format!("API_KEY_FILE {}: {}", path.display(), e))?;
+    let key = key.trim().to_string();
+    if key.is_empty() {
+        return Err(format!("API_KEY_FILE {}: file is empty", path.display()));
+    }
+    Ok(key)
+}
+
+// コマンド（パスワードマネージャーなど）の標準出力をキーにする
+fn run_key_command(command: &str) -> Result<String, String> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env_remove("API_KEY")
+        .output()
+        .map_err(|e| format!("API_KEY_COMMAND: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("API_KEY_COMMAND exited with {}", output.status));
+    }
+    let key = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if key.is_empty() {
+        return Err(String::from("API_KEY_COMMAND printed nothing"));
+    }
+    Ok(key)
+}
+
+// 文字列に含まれるAPIキーを伏せる（エラーメッセージのURLなど）
+pub fn redact(text: &str) -> String {
+    match API_KEY.get() {
+        Some(key) if !key.is_empty() => text.replace(key.as_str(), "***"),
+        _ => text.to_string(),
+    }
+}
+
+// キーファイルの既定の場所（設定ファイルと同じディレクトリのapi_key）
+pub fn default_key_file() -> Option<PathBuf> {
+    Some(config::default_path()?.with_file_name("api_key"))
+}
+
+// キーファイルを所有者のみ読み書きできる権限で作成する
+pub fn write_key_file(path: &Path, key: &str) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    // 既存のファイルの権限も直す
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    writeln!(file, "{}", key)?;
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn reject_open_key_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("api_key");
+        fs::write(&path, "secret\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        let error = read_key_file(&path).err().unwrap();
+        assert!(error.contains("permissions 644 are too open"), "{}", error);
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(read_key_file(&path).unwrap(), "secret");
+
+        fs::write(&path, "\n").unwrap();
+        assert!(read_key_file(&path)
+            .err()
+            .unwrap()
+            .ends_with("file is empty"));
+    }
+
+    #[test]
+    fn write_key_file_as_owner_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config").join("api_key");
+        write_key_file(&path, "secret").unwrap();
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(read_key_file(&path).unwrap(), "secret");
+
+        // 既存のファイルの権限も直す
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        write_key_file(&path, "changed").unwrap();
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(read_key_file(&path).unwrap(), "changed");
+    }
+
+    #[test]
+    fn key_command_output() {
+        assert_eq!(run_key_command("echo '  secret  '").unwrap(), "secret");
+        assert_eq!(
+            run_key_command("true").err().unwrap(),
+            "API_KEY_COMMAND printed nothing"
+        );
+        assert!(run_key_command("exit 3")
+            .err()
+            .unwrap()
+            .starts_with("API_KEY_COMMAND exited with"));
+    }
+
+    #[test]
+    fn redact_hides_key() {
+        // 読み込んだキーはプロセスで1つなので、このテストのみ設定する
+        let key = API_KEY.get_or_init(|| String::from("0123456789abcdef"));
+        let text = format!(
+            "error sending request for url (http://localhost/?q=Osaka&appid={})",
+            key
+        );
+        let redacted = redact(&text);
+        assert!(!redacted.contains(key.as_str()));
+        assert_eq!(
+            redacted,
+            "error sending request for url (http://localhost/?q=Osaka&appid=***)"
+        );
+    }
+}
@@ -0,0 +1,434 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+
+use crate::api::OpenWeaterToTsv;
+use crate::store;
+
+// サーバーで共有する状態
+#[derive(Clone)]
+pub struct AppState {
+    // 保存済みtsvのディレクトリ
+    pub log_dir: PathBuf,
+    // 設定した地点名（小文字）ごとの最新の取得結果（TSV_OUT=0でも返せるように保持する）
+    // 座標を指定した地点はAPIが返す地点名が異なることがあるため、取得結果の地点名では区別しない
+    pub latest: Arc<RwLock<HashMap<String, OpenWeaterToTsv>>>,
+    // 新しい取得結果の配信用（SSE/WebSocket）
+    pub observations: broadcast::Sender<OpenWeaterToTsv>,
+}
+
+impl AppState {
+    pub fn new(log_dir: PathBuf) -> Self {
+        let (observations, _) = broadcast::channel(64);
+        AppState {
+            log_dir,
+            latest: Arc::new(RwLock::new(HashMap::new())),
+            observations,
+        }
+    }
+
+    // 取得ループから最新の結果を反映し、購読中のクライアントに配信する
+    pub fn update(&self, location: &str, record: &OpenWeaterToTsv) {
+        let mut latest = self.latest.write().unwrap();
+        latest.insert(location.to_lowercase(), record.clone());
+        // 購読者がいない場合はエラーになるが無視してよい
+        let _ = self.observations.send(record.clone());
+    }
+
+    // 指定した地点の最新の取得結果（設定した地点名のほか、履歴と同じく地点名や座標でも探す）
+    fn latest_for(&self, location: &str) -> Option<OpenWeaterToTsv> {
+        let latest = self.latest.read().unwrap();
+        latest
+            .get(&location.to_lowercase())
+            .or_else(|| {
+                latest
+                    .values()
+                    .filter(|v| store::is_location(v, location))
+                    .max_by_key(|v| v.dt)
+            })
+            .cloned()
+    }
+
+    async fn records(&self) -> Result<Vec<OpenWeaterToTsv>, ApiError> {
+        let log_dir = self.log_dir.clone();
+        // ファイル読み込みはブロッキングなので別スレッドで行う
+        let records = tokio::task::spawn_blocking(move || {
+            store::load_records(&log_dir).map_err(|e| e.to_string())
+        })
+        .await
+        .map_err(|e| ApiError::internal(e.to_string()))?
+        .map_err(ApiError::internal)?;
+
+        Ok(records)
+    }
+}
+
+// エラーレスポンス
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn not_found(message: String) -> Self {
+        ApiError {
+            status: StatusCode::NOT_FOUND,
+            message,
+        }
+    }
+
+    fn bad_request(message: String) -> Self {
+        ApiError {
+            status: StatusCode::BAD_REQUEST,
+            message,
+        }
+    }
+
+    fn internal(message: String) -> Self {
+        ApiError {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            message,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(json!({ "error": self.message }))).into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamQuery {
+    // 指定した場合はその地点の取得結果のみ配信する
+    location: Option<String>,
+}
+
+impl StreamQuery {
+    fn matches(&self, record: &OpenWeaterToTsv) -> bool {
+        match &self.location {
+            None => true,
+            Some(location) => store::is_location(record, location),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryQuery {
+    from: Option<String>,
+    to: Option<String>,
+}
+
+// 期間指定をUNIX時間に変換する
+fn parse_time(value: &str) -> Result<i64, ApiError> {
+    store::parse_time(value)
+        .ok_or_else(|| ApiError::bad_request(format!("invalid time: {}", value)))
+}
+
+// 地点一覧
+async fn locations(State(state): State<AppState>) -> Result<Json<serde_json::Value>, ApiError> {
+    let records = state.records().await?;
+
+    let mut locations: BTreeMap<String, (OpenWeaterToTsv, usize)> = BTreeMap::new();
+    for record in records {
+        let entry = locations
+            .entry(record.name.to_lowercase())
+            .or_insert((record.clone(), 0));
+        entry.1 += 1;
+        if record.dt >= entry.0.dt {
+            entry.0 = record;
+        }
+    }
+    for record in state.latest.read().unwrap().values() {
+        locations
+            .entry(record.name.to_lowercase())
+            .or_insert((record.clone(), 0));
+    }
+
+    let body: Vec<serde_json::Value> = locations
+        .values()
+        .map(|(record, count)| {
+            json!({
+                "name": record.name,
+                "country": record.country,
+                "lat": record.lat,
+                "lon": record.lon,
+                "records": count,
+                "last_dt": record.dt,
+            })
+        })
+        .collect();
+
+    Ok(Json(json!(body)))
+}
+
+// 地点の最新の取得結果
+async fn current(
+    State(state): State<AppState>,
+    Path(location): Path<String>,
+) -> Result<Json<OpenWeaterToTsv>, ApiError> {
+    if let Some(record) = state.latest_for(&location) {
+        return Ok(Json(record));
+    }
+
+    let records = state.records().await?;
+    records
+        .into_iter()
+        .rev()
+        .find(|v| store::is_location(v, &location))
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found(format!("unknown location: {}", location)))
+}
+
+// 地点の履歴。from/toで期間を絞り込む
+async fn history(
+    State(state): State<AppState>,
+    Path(location): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<Vec<OpenWeaterToTsv>>, ApiError> {
+    let from = query.from.as_deref().map(parse_time).transpose()?;
+    let to = query.to.as_deref().map(parse_time).transpose()?;
+
+    let records: Vec<OpenWeaterToTsv> = state
+        .records()
+        .await?
+        .into_iter()
+        .filter(|v| store::is_location(v, &location))
+        .collect();
+    if records.is_empty() {
+        return Err(ApiError::not_found(format!(
+            "unknown location: {}",
+            location
+        )));
+    }
+
+    let records = records
+        .into_iter()
+        .filter(|v| from.is_none_or(|from| v.dt >= from))
+        .filter(|v| to.is_none_or(|to| v.dt <= to))
+        .collect();
+
+    Ok(Json(records))
+}
+
+// 新しい取得結果をServer-Sent Eventsで配信する
+async fn stream_sse(
+    State(state): State<AppState>,
+    Query(query): Query<StreamQuery>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = BroadcastStream::new(state.observations.subscribe()).filter_map(move |v| {
+        match v {
+            Ok(record) if query.matches(&record) => Event::default()
+                .event("observation")
+                .json_data(&record)
+                .ok()
+                .map(Ok),
+            Ok(_) => None,
+            // 受信が遅れて取りこぼした場合はクライアントに通知する
+            Err(BroadcastStreamRecvError::Lagged(n)) => {
+                Some(Ok(Event::default().event("lagged").data(n.to_string())))
+            }
+        }
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+// 新しい取得結果をWebSocketで配信する
+async fn stream_ws(
+    State(state): State<AppState>,
+    Query(query): Query<StreamQuery>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    ws.on_upgrade(move |socket| ws_session(socket, state, query))
+}
+
+async fn ws_session(mut socket: WebSocket, state: AppState, query: StreamQuery) {
+    let mut rx = state.observations.subscribe();
+    loop {
+        tokio::select! {
+            received = rx.recv() => {
+                let text = match received {
+                    Ok(record) if query.matches(&record) => match serde_json::to_string(&record) {
+                        Ok(v) => v,
+                        Err(_) => continue,
+                    },
+                    Ok(_) => continue,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        json!({ "lagged": n }).to_string()
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if socket.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            // クライアントからの切断を検知する（受信内容は使わない）
+            message = socket.recv() => {
+                match message {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                }
+            }
+        }
+    }
+}
+
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/locations", get(locations))
+        .route("/current/:location", get(current))
+        .route("/history/:location", get(history))
+        .route("/stream/sse", get(stream_sse))
+        .route("/stream/ws", get(stream_ws))
+        .with_state(state)
+}
+
+// 取得ループと並行してHTTPサーバーを起動する
+pub fn spawn(addr: SocketAddr, state: AppState) {
+    let app = router(state);
+    tokio::spawn(async move {
+        tracing::info!(%addr, "api server listening");
+        if let Err(e) = axum::Server::bind(&addr)
+            .serve(app.into_make_service())
+            .await
+        {
+            tracing::error!(error = %e, "api server error");
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    fn record(name: &str, lat: f64, lon: f64, dt: i64) -> OpenWeaterToTsv {
+        let mut record = OpenWeaterToTsv::new();
+        record.name = String::from(name);
+        record.lat = lat;
+        record.lon = lon;
+        record.dt = dt;
+        record
+    }
+
+    async fn get_json(state: &AppState, uri: &str) -> (StatusCode, serde_json::Value) {
+        let response = router(state.clone())
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn current_is_keyed_by_configured_location() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = AppState::new(dir.path().to_path_buf());
+        // 座標を指定した地点はAPIが別の地点名を返すことがある
+        state.update(
+            "Kyoto@35.0116:135.7681",
+            &record("Shimogyō-ku", 35.0116, 135.7681, 100),
+        );
+        state.update("Osaka", &record("Osaka", 34.69, 135.5, 200));
+
+        let (status, body) = get_json(&state, "/current/Kyoto@35.0116:135.7681").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["name"], "Shimogyō-ku");
+        let (status, body) = get_json(&state, "/current/osaka").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["dt"], 200);
+        // 取得結果の地点名や座標でも探せる
+        let (_, body) = get_json(&state, "/current/Shimogy%C5%8D-ku").await;
+        assert_eq!(body["dt"], 100);
+        let (_, body) = get_json(&state, "/current/x@35.01:135.77").await;
+        assert_eq!(body["dt"], 100);
+
+        let (status, body) = get_json(&state, "/current/Tokyo").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"], "unknown location: Tokyo");
+    }
+
+    #[tokio::test]
+    async fn history_filters_by_location() {
+        let dir = tempfile::tempdir().unwrap();
+        let records = vec![
+            record("Osaka", 34.69, 135.5, 100),
+            record("Kyoto", 35.0116, 135.7681, 150),
+            record("Osaka", 34.69, 135.5, 200),
+            record("osaka", 34.69, 135.5, 300),
+        ];
+        let mut wtr = csv::WriterBuilder::new()
+            .delimiter(b'\t')
+            .from_path(dir.path().join("test.tsv"))
+            .unwrap();
+        for record in &records {
+            wtr.serialize(record).unwrap();
+        }
+        wtr.flush().unwrap();
+        let state = AppState::new(dir.path().to_path_buf());
+
+        let (status, body) = get_json(&state, "/history/OSAKA").await;
+        assert_eq!(status, StatusCode::OK);
+        let dts: Vec<i64> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| v["dt"].as_i64().unwrap())
+            .collect();
+        assert_eq!(dts, vec![100, 200, 300]);
+
+        let (_, body) = get_json(&state, "/history/Osaka?from=150&to=250").await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        // 座標を指定した場合は座標で絞り込む
+        let (_, body) = get_json(&state, "/history/x@35.0116:135.7681").await;
+        assert_eq!(body[0]["name"], "Kyoto");
+
+        let (status, _) = get_json(&state, "/history/Tokyo").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, body) = get_json(&state, "/history/Osaka?from=yesterday").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "invalid time: yesterday");
+    }
+
+    #[tokio::test]
+    async fn locations_merge_latest() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = AppState::new(dir.path().to_path_buf());
+        state.update("Osaka", &record("Osaka", 34.69, 135.5, 200));
+        state.update(
+            "Kyoto@35.0116:135.7681",
+            &record("Kyoto", 35.0116, 135.7681, 100),
+        );
+
+        let (status, body) = get_json(&state, "/locations").await;
+        assert_eq!(status, StatusCode::OK);
+        let names: Vec<&str> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| v["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["Kyoto", "Osaka"]);
+    }
+}
@@ -0,0 +1,378 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde_json::Value;
+
+use crate::ratelimit::RateLimiter;
+use crate::{config, do_get_weather, icon, jobs, print_weather, secrets, store, ApiClient};
+
+// 地名検索（Geocoding API）の候補
+struct Candidate {
+    name: String,
+    state: String,
+    country: String,
+    lat: f64,
+    lon: f64,
+}
+
+impl std::fmt::Display for Candidate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        if !self.state.is_empty() {
+            write!(f, ", {}", self.state)?;
+        }
+        write!(f, ", {} ({:.4}, {:.4})", self.country, self.lat, self.lon)
+    }
+}
+
+// 1行入力する。入力が閉じられた場合はNone
+fn prompt(message: &str) -> Option<String> {
+    println!("{}", message);
+    let mut input = String::new();
+    match std::io::stdin().read_line(&mut input) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(input.trim().to_string()),
+    }
+}
+
+// 地名を検索する（回数制限とログは他の呼び出しと共通）
+async fn geocode(
+    api_client: &ApiClient,
+    query: &str,
+) -> Result<Vec<Candidate>, Box<dyn std::error::Error>> {
+    let body = api_client.get_geocoding(query, 5).await?;
+    let value = jobs::check_response(&body).map_err(|e| format!("geocoding failed: {}", e))?;
+    Ok(candidates(&value))
+}
+
+// 地名検索の結果を候補の一覧にする
+fn candidates(value: &Value) -> Vec<Candidate> {
+    let text = |v: &Value, key: &str| {
+        v.get(key)
+            .and_then(|v| v.as_str())
+            .unwrap_or("")
+            .to_string()
+    };
+    value
+        .as_array()
+        .map(|list| {
+            list.iter()
+                .map(|v| Candidate {
+                    name: text(v, "name"),
+                    state: text(v, "state"),
+                    country: text(v, "country"),
+                    lat: v.get("lat").and_then(|v| v.as_f64()).unwrap_or(0.0),
+                    lon: v.get("lon").and_then(|v| v.as_f64()).unwrap_or(0.0),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// 設定ウィザード
+// APIキーと地点を実際に問い合わせて確認し、取得例を表示してから保存する
+// APIキーは.envに書かずに所有者のみ読めるキーファイルに保存し、.envの他の設定は残す
+pub async fn run(
+    current_url: &str,
+    current_key: &str,
+    current_location: &str,
+    rate_limiter: Arc<RateLimiter>,
+) -> std::io::Result<()> {
+    // 接続先
+    println!("\n");
+    let url = match prompt(&format!("Enter the API URL. (empty: keep {})", current_url)) {
+        Some(v) if !v.is_empty() => v,
+        Some(_) => current_url.to_string(),
+        None => return Ok(()),
+    };
+
+    // APIキー（実際に問い合わせて確認する）
+    // 入力した場合のみキーファイルに保存し、それ以外は今の読み込み元をそのまま使う
+    let probe = current_location
+        .split(',')
+        .map(|v| store::parse_location(v.trim()).0)
+        .find(|v| !v.is_empty())
+        .unwrap_or("osaka");
+    let mut api_key = current_key.to_string();
+    let mut typed = false;
+    loop {
+        println!("\n");
+        let message = if api_key.is_empty() {
+            "Enter the API KEY."
+        } else {
+            "Enter the API KEY. (empty: keep current)"
+        };
+        match prompt(message) {
+            Some(v) if !v.is_empty() => {
+                api_key = v;
+                typed = true;
+            }
+            Some(_) => {}
+            None => return Ok(()),
+        }
+        if api_key.is_empty() {
+            continue;
+        }
+        // 今の地点を地名検索して確認する（見つからなくてもキーが有効なら成功する）
+        println!("Checking the API KEY...");
+        let api_client = ApiClient::new(
+            url.clone(),
+            String::from("metric"),
+            api_key.clone(),
+            Arc::clone(&rate_limiter),
+        );
+        match geocode(&api_client, probe).await {
+            Ok(_) => {
+                println!("OK.");
+                break;
+            }
+            Err(e) => {
+                println!("{}", e.to_string().replace(&api_key, "***"));
+                println!("Please try again.");
+                api_key = String::new();
+            }
+        }
+    }
+
+    // 地点（地名検索の候補から選ぶ）
+    let api_client = ApiClient::new(
+        url.clone(),
+        String::from("metric"),
+        api_key.clone(),
+        rate_limiter,
+    );
+    let mut locations = Vec::new();
+    println!("\n");
+    let query = match prompt(&format!(
+        "Enter the API LOCATION. Separate multiple locations with \",\". (empty: keep {})",
+        current_location
+    )) {
+        Some(v) if !v.is_empty() => v,
+        Some(_) => current_location.to_string(),
+        None => return Ok(()),
+    };
+    for query in query.split(',').map(|v| v.trim()).filter(|v| !v.is_empty()) {
+        // 座標を指定済みの地点はそのまま使う
+        if store::parse_location(query).1.is_some() {
+            locations.push(query.to_string());
+            continue;
+        }
+        let candidates = match geocode(&api_client, query).await {
+            Ok(v) => v,
+            Err(e) => {
+                println!("{}", e.to_string().replace(&api_key, "***"));
+                return Ok(());
+            }
+        };
+        if candidates.is_empty() {
+            println!("{}: location not found.", query);
+            println!("Does not reflect the settings.");
+            return Ok(());
+        }
+        println!("\n");
+        println!("Candidates for {}:", query);
+        for (i, candidate) in candidates.iter().enumerate() {
+            println!("  {}) {}", i + 1, candidate);
+        }
+        let index = loop {
+            match prompt(&format!("Choose 1-{} : (empty: 1)", candidates.len())) {
+                None => return Ok(()),
+                Some(v) if v.is_empty() => break 0,
+                Some(v) => match v.parse::<usize>() {
+                    Ok(n) if n >= 1 && n <= candidates.len() => break n - 1,
+                    _ => println!("Please try again."),
+                },
+            }
+        };
+        // 同名の地点と区別できるよう、選んだ候補の座標を付けて保存する
+        let candidate = &candidates[index];
+        locations.push(format!(
+            "{}@{:.4}:{:.4}",
+            candidate.name, candidate.lat, candidate.lon
+        ));
+    }
+
+    // 取得例を表示する
+    let icons = icon::IconSet::from_values(&config::current()).map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("ICON_THEME_DIR: {}", e),
+        )
+    })?;
+    for location in &locations {
+        println!("\n");
+        match do_get_weather(&api_client, location).await {
+            Ok(v) => print_weather(&v, &icons)?,
+            Err(e) => {
+                println!("{}: {}", location, e.to_string().replace(&api_key, "***"));
+                println!("Does not reflect the settings.");
+                return Ok(());
+            }
+        }
+    }
+
+    let mut values = vec![
+        ("OPEN_WEATHER_URL", url),
+        ("LOCATION_NAME", locations.join(",")),
+    ];
+    let key_file = if typed {
+        let key_file = match config::current().get("API_KEY_FILE") {
+            Some(v) => PathBuf::from(v),
+            None => secrets::default_key_file().unwrap_or_else(|| PathBuf::from("./api_key")),
+        };
+        values.push(("API_KEY_FILE", key_file.display().to_string()));
+        values.push(("API_KEY", String::new()));
+        Some(key_file)
+    } else {
+        None
+    };
+
+    println!("\n");
+    for (key, value) in &values {
+        println!("{}={}", key, value);
+    }
+    match &key_file {
+        Some(path) => println!("(API KEY ########### is saved to {})", path.display()),
+        None => println!("(API KEY ########### is unchanged)"),
+    }
+    let answer = prompt("Is it okay to reflect it in the settings? y or n").unwrap_or_default();
+
+    match answer.as_str() {
+        "Y" | "y" => {
+            if let Some(path) = &key_file {
+                secrets::write_key_file(path, &api_key)?;
+            }
+            update_env_file(Path::new("./.env"), &values)?;
+            println!("\n");
+            println!("The settings have been reflected.");
+            println!("\n");
+        }
+        _ => {
+            println!("\n");
+            println!("Does not reflect the settings.");
+            println!("\n");
+        }
+    }
+
+    Ok(())
+}
+
+// .envの指定した項目だけを書き換える（ない項目は末尾に追加し、他の行はそのまま残す）
+// 書き込み中に中断しても元のファイルが壊れないよう、一時ファイルに書いてから置き換える
+fn update_env_file(path: &Path, values: &[(&str, String)]) -> std::io::Result<()> {
+    let current = fs::read_to_string(path).unwrap_or_default();
+    let mut lines: Vec<String> = current.lines().map(String::from).collect();
+    for (key, value) in values {
+        let prefix = format!("{}=", key);
+        let line = format!("{}{}", prefix, value);
+        match lines.iter_mut().find(|v| v.starts_with(&prefix)) {
+            Some(v) => *v = line,
+            None => lines.push(line),
+        }
+    }
+
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".tmp");
+    let temp = path.with_file_name(file_name);
+    let written = (|| {
+        let mut file = File::create(&temp)?;
+        // 元のファイルの権限を引き継ぐ
+        if let Ok(metadata) = fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        for line in &lines {
+            writeln!(file, "{}", line)?;
+        }
+        file.sync_all()?;
+        fs::rename(&temp, path)
+    })();
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn update(current: &str, values: &[(&str, String)]) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".env");
+        fs::write(&path, current).unwrap();
+        update_env_file(&path, values).unwrap();
+        // 一時ファイルは残さない
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        fs::read_to_string(&path).unwrap()
+    }
+
+    #[test]
+    fn replace_keys_and_keep_other_lines() {
+        let current = "# OpenWeather\nAPI_KEY=old\n\n# 地点\nLOCATION_NAME=osaka\nTSV_OUT=1\n";
+        let out = update(
+            current,
+            &[
+                ("LOCATION_NAME", String::from("Kyoto@35.0116:135.7681")),
+                ("API_KEY", String::new()),
+            ],
+        );
+        assert_eq!(
+            out,
+            "# OpenWeather\nAPI_KEY=\n\n# 地点\nLOCATION_NAME=Kyoto@35.0116:135.7681\nTSV_OUT=1\n"
+        );
+    }
+
+    #[test]
+    fn append_new_keys() {
+        let out = update(
+            "TSV_OUT=1\n",
+            &[
+                ("OPEN_WEATHER_URL", String::from("http://localhost/weather")),
+                ("API_KEY_FILE", String::from("/tmp/key")),
+            ],
+        );
+        assert_eq!(
+            out,
+            "TSV_OUT=1\nOPEN_WEATHER_URL=http://localhost/weather\nAPI_KEY_FILE=/tmp/key\n"
+        );
+
+        // .envがない場合は作成する
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".env");
+        update_env_file(&path, &[("TSV_OUT", String::from("0"))]).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "TSV_OUT=0\n");
+    }
+
+    #[test]
+    fn keep_file_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".env");
+        fs::write(&path, "API_KEY=secret\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        update_env_file(&path, &[("API_KEY", String::new())]).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn geocoding_candidates() {
+        let value = json!([
+            { "name": "Kyoto", "state": "Kyoto Prefecture", "country": "JP", "lat": 35.0116, "lon": 135.7681 },
+            { "name": "Kyoto", "country": "JP", "lat": 35.0, "lon": 135.75 },
+        ]);
+        let list: Vec<String> = candidates(&value).iter().map(|v| v.to_string()).collect();
+        assert_eq!(
+            list,
+            vec![
+                "Kyoto, Kyoto Prefecture, JP (35.0116, 135.7681)",
+                "Kyoto, JP (35.0000, 135.7500)"
+            ]
+        );
+        assert!(candidates(&json!({})).is_empty());
+    }
+}
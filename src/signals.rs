@@ -0,0 +1,138 @@
+use std::future::Future;
+use std::time::Duration;
+
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::Instant;
+
+use crate::alerts;
+use crate::env_or;
+
+// 受け取ったシグナル
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Signal {
+    // SIGINT（Ctrl-C）。終了する
+    Interrupt,
+    // SIGTERM。終了する
+    Terminate,
+    // SIGHUP。設定を読み込み直す
+    Reload,
+    // ダッシュボードでの終了の操作（qキーなど）。シグナルではないが同じように終了する
+    Quit,
+}
+
+impl Signal {
+    pub fn name(self) -> &'static str {
+        match self {
+            Signal::Interrupt => "SIGINT",
+            Signal::Terminate => "SIGTERM",
+            Signal::Reload => "SIGHUP",
+            Signal::Quit => "user quit",
+        }
+    }
+}
+
+// SIGINT、SIGTERM、SIGHUPを受け取る
+// 受け取ったシグナルは記録しておき、取得ループの区切りで確認する
+pub struct Signals {
+    interrupt: tokio::signal::unix::Signal,
+    terminate: tokio::signal::unix::Signal,
+    hangup: tokio::signal::unix::Signal,
+    // 終了のシグナル
+    stop: Option<Signal>,
+    // 設定の読み込み直しが必要か
+    reload: bool,
+    // 終了のシグナルを受けてから取得の完了や送信を待つ時間
+    timeout: Duration,
+}
+
+impl Signals {
+    // SHUTDOWN_TIMEOUTは終了時に待つ時間（デフォルト10秒）
+    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        let timeout = alerts::parse_duration(&env_or("SHUTDOWN_TIMEOUT", "10s"))
+            .map_err(|e| format!("SHUTDOWN_TIMEOUT: {}", e))?;
+        Ok(Signals {
+            interrupt: signal(SignalKind::interrupt())?,
+            terminate: signal(SignalKind::terminate())?,
+            hangup: signal(SignalKind::hangup())?,
+            stop: None,
+            reload: false,
+            timeout: Duration::from_secs(timeout.max(0) as u64),
+        })
+    }
+
+    // 次のシグナルを待って記録する
+    pub async fn recv(&mut self) -> Signal {
+        let received = tokio::select! {
+            _ = self.interrupt.recv() => Signal::Interrupt,
+            _ = self.terminate.recv() => Signal::Terminate,
+            _ = self.hangup.recv() => Signal::Reload,
+        };
+        match received {
+            Signal::Reload => self.reload = true,
+            _ => self.stop = Some(received),
+        }
+        received
+    }
+
+    // 利用者の操作で終了する
+    pub fn quit(&mut self) {
+        self.stop = Some(Signal::Quit);
+    }
+
+    // 終了のシグナルを受け取っていればそのシグナル
+    pub fn stopped(&self) -> Option<Signal> {
+        self.stop
+    }
+
+    // 終了または設定の読み込み直しが必要か
+    pub fn interrupted(&self) -> bool {
+        self.stop.is_some() || self.reload
+    }
+
+    // 設定の読み込み直しが必要か（確認すると解除される）
+    pub fn take_reload(&mut self) -> bool {
+        std::mem::take(&mut self.reload)
+    }
+
+    // 終了時に待つ時間
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    // 処理を実行する。SIGHUPを受けても処理は続ける
+    // 終了のシグナルを受けた場合はSHUTDOWN_TIMEOUTまで完了を待ち、
+    // 過ぎた場合や再度シグナルを受けた場合は取り消してNoneを返す
+    pub async fn guard<F: Future>(&mut self, future: F) -> Option<F::Output> {
+        tokio::pin!(future);
+        while self.stop.is_none() {
+            tokio::select! {
+                output = &mut future => return Some(output),
+                _ = self.recv() => {}
+            }
+        }
+
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            tokio::select! {
+                output = &mut future => return Some(output),
+                _ = tokio::time::sleep_until(deadline) => return None,
+                received = self.recv() => {
+                    if received != Signal::Reload {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+
+    // 時刻まで待つ。シグナルを受けた場合はその時点で戻る
+    pub async fn sleep_until(&mut self, deadline: Instant) {
+        if self.interrupted() {
+            return;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep_until(deadline) => {}
+            _ = self.recv() => {}
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Local, TimeZone};
+use serde::{Deserialize, Serialize};
+
+use crate::env_or;
+use crate::schedule::Scheduler;
+use crate::secrets;
+use crate::store;
+
+// 取得の状態（再起動しても続きから取得できるようにファイルに保存する）
+#[derive(Serialize, Deserialize, Default)]
+pub struct State {
+    // 最後に書き込んだプロセス
+    pub pid: u32,
+    // 最後に書き込んだ時刻（UNIX秒）
+    pub updated: i64,
+    // 通常の取得のスケジュール（JOBSを使う場合はjobs）
+    pub schedule: Option<ScheduleState>,
+    // ジョブ名ごとのスケジュール
+    #[serde(default)]
+    pub jobs: BTreeMap<String, ScheduleState>,
+    // 地点名（小文字）ごとの取得結果
+    #[serde(default)]
+    pub locations: BTreeMap<String, LocationState>,
+    // 最後に終了した時の状況（取得中はNone）
+    #[serde(default)]
+    pub stopped: Option<StopState>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ScheduleState {
+    // 取得を開始した時刻（DURATIONはここから数える）
+    pub started: i64,
+    // 実行した回数（RUNSと比べる）
+    pub runs: u64,
+    // 次の実行時刻。終了条件に達した場合はNone
+    pub next_run: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StopState {
+    // 終了した時刻
+    pub at: i64,
+    // 終了の理由（受け取ったシグナル、または「schedule finished」）
+    pub reason: String,
+    // 送信を待ちきれずに取り消した通知の件数
+    pub canceled: usize,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct LocationState {
+    // 最後に取得に成功した時刻
+    pub last_success: Option<i64>,
+    // 最後に取得した観測時刻（dt）
+    pub last_dt: Option<i64>,
+    // 連続して失敗した回数
+    pub failures: u32,
+    pub last_error: Option<String>,
+}
+
+// 状態ファイルの場所（STATE_FILE）
+pub fn path() -> PathBuf {
+    PathBuf::from(env_or(
+        "STATE_FILE",
+        &format!("{}/state.json", store::LOG_DIR),
+    ))
+}
+
+// 状態ファイルを読み込む。存在しない場合はNone
+pub fn load(path: &Path) -> Result<Option<State>, String> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(v) => v,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+    serde_json::from_str(&text)
+        .map(Some)
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// 取得中に更新する状態ファイル
+// 更新するたびに書き込む（書き込み途中のファイルが残らないよう、一時ファイルに書いてから名前を変える）
+pub struct StateFile {
+    path: PathBuf,
+    state: Mutex<State>,
+}
+
+impl StateFile {
+    // 状態ファイルを読み込む。読めない場合は新しく作る
+    pub fn open() -> Self {
+        Self::open_at(path())
+    }
+
+    fn open_at(path: PathBuf) -> Self {
+        let mut state = match load(&path) {
+            Ok(v) => v.unwrap_or_default(),
+            Err(e) => {
+                tracing::warn!(error = %e, "state file ignored");
+                State::default()
+            }
+        };
+        // 前回の終了の記録は次に書き込む時に消す
+        state.stopped = None;
+        StateFile {
+            path,
+            state: Mutex::new(state),
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut State)) {
+        let mut state = self.state.lock().unwrap();
+        f(&mut state);
+        state.pid = std::process::id();
+        state.updated = Local::now().timestamp();
+        if let Err(e) = self.save(&state) {
+            tracing::warn!(path = %self.path.display(), error = %e, "state file not saved");
+        }
+    }
+
+    fn save(&self, state: &State) -> std::io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        std::fs::write(&tmp_path, serde_json::to_string_pretty(state)?)?;
+        std::fs::rename(&tmp_path, &self.path)
+    }
+
+    // 取得に成功した
+    pub fn fetched(&self, location_name: &str, dt: i64) {
+        self.update(|state| {
+            let location = state
+                .locations
+                .entry(location_name.to_lowercase())
+                .or_default();
+            location.last_success = Some(Local::now().timestamp());
+            location.last_dt = Some(dt);
+            location.failures = 0;
+            location.last_error = None;
+        });
+    }
+
+    // 取得に失敗した（エラーメッセージのAPIキーは伏せる）
+    // 連続して失敗した回数を返す
+    pub fn failed(&self, location_name: &str, error: &str) -> u32 {
+        let mut failures = 0;
+        self.update(|state| {
+            let location = state
+                .locations
+                .entry(location_name.to_lowercase())
+                .or_default();
+            location.failures += 1;
+            location.last_error = Some(secrets::redact(error));
+            failures = location.failures;
+        });
+        failures
+    }
+
+    // 前回の取得が終了条件に達していなければ、開始時刻と実行回数を引き継ぐ（RESUME=0の場合は引き継がない）
+    // jobがNoneの場合は通常の取得、それ以外はジョブ名
+    pub fn resume(&self, job: Option<&str>, scheduler: &mut Scheduler) {
+        if env_or("RESUME", "1") != "1" {
+            return;
+        }
+        let saved = {
+            let state = self.state.lock().unwrap();
+            match job {
+                None => state.schedule.clone(),
+                Some(name) => state.jobs.get(name).cloned(),
+            }
+        };
+        let saved = match saved {
+            Some(v) if v.next_run.is_some() => v,
+            _ => return,
+        };
+        let started = Local.timestamp(saved.started, 0);
+        if scheduler.resume(started, saved.runs) {
+            tracing::info!(
+                job = job.unwrap_or("-"),
+                started = %started.format("%Y-%m-%d %H:%M:%S"),
+                runs = saved.runs,
+                "schedule resumed"
+            );
+        }
+    }
+
+    // 実行した回数と次の実行時刻を記録する
+    pub fn scheduled(
+        &self,
+        job: Option<&str>,
+        scheduler: &Scheduler,
+        next: Option<DateTime<Local>>,
+    ) {
+        let value = ScheduleState {
+            started: scheduler.start().timestamp(),
+            runs: scheduler.count(),
+            next_run: next.map(|v| v.timestamp()),
+        };
+        self.update(|state| match job {
+            None => state.schedule = Some(value),
+            Some(name) => {
+                state.jobs.insert(name.to_string(), value);
+            }
+        });
+    }
+
+    // 終了の理由を記録する
+    pub fn stopped(&self, reason: &str, canceled: usize) {
+        self.update(|state| {
+            state.stopped = Some(StopState {
+                at: Local::now().timestamp(),
+                reason: reason.to_string(),
+                canceled,
+            })
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Values;
+
+    fn scheduler() -> Scheduler {
+        let mut values = Values::default();
+        values.set("INTERVAL", "30m");
+        values.set("RUNS", "10");
+        Scheduler::from_values(&values).unwrap()
+    }
+
+    #[test]
+    fn save_and_resume() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state").join("state.json");
+        let started = Local.timestamp(Local::now().timestamp() - 3600, 0);
+
+        let file = StateFile::open_at(path.clone());
+        let mut first = scheduler();
+        assert!(first.resume(started, 3));
+        file.scheduled(None, &first, first.next(Local::now()));
+        // 終了条件に達したジョブは引き継がない
+        file.scheduled(Some("daily"), &first, None);
+        file.fetched("Tokyo", 1700000000);
+        assert_eq!(file.failed("Osaka", "timeout"), 1);
+        assert_eq!(file.failed("Osaka", "timeout"), 2);
+        file.stopped("SIGTERM", 1);
+
+        let state = load(&path).unwrap().unwrap();
+        assert_eq!(state.pid, std::process::id());
+        let schedule = state.schedule.unwrap();
+        assert_eq!(schedule.started, started.timestamp());
+        assert_eq!(schedule.runs, 3);
+        assert!(schedule.next_run.is_some());
+        assert_eq!(state.jobs["daily"].next_run, None);
+        assert_eq!(state.locations["tokyo"].last_dt, Some(1700000000));
+        assert_eq!(state.locations["tokyo"].failures, 0);
+        assert_eq!(state.locations["osaka"].failures, 2);
+        assert_eq!(
+            state.locations["osaka"].last_error.as_deref(),
+            Some("timeout")
+        );
+        assert_eq!(state.stopped.unwrap().reason, "SIGTERM");
+
+        // 再起動後に開始時刻と実行回数を引き継ぐ
+        let file = StateFile::open_at(path.clone());
+        let mut resumed = scheduler();
+        file.resume(None, &mut resumed);
+        assert_eq!(resumed.start(), started);
+        assert_eq!(resumed.count(), 3);
+
+        let mut job = scheduler();
+        let job_start = job.start();
+        file.resume(Some("daily"), &mut job);
+        file.resume(Some("hourly"), &mut job);
+        assert_eq!(job.start(), job_start);
+        assert_eq!(job.count(), 0);
+
+        // 前回の終了の記録は次の書き込みで消える
+        file.fetched("Tokyo", 1700001800);
+        let state = load(&path).unwrap().unwrap();
+        assert!(state.stopped.is_none());
+        assert_eq!(state.schedule.unwrap().runs, 3);
+        assert_eq!(state.locations["osaka"].failures, 2);
+    }
+
+    #[test]
+    fn load_missing_and_broken() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        assert!(load(&path).unwrap().is_none());
+
+        std::fs::write(&path, "{").unwrap();
+        assert!(load(&path).is_err());
+        // 読めない状態ファイルは無視して新しく作る
+        let file = StateFile::open_at(path.clone());
+        file.fetched("Tokyo", 1);
+        assert_eq!(
+            load(&path).unwrap().unwrap().locations["tokyo"].last_dt,
+            Some(1)
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use std::path::Path;
+
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
+
+use crate::api::OpenWeaterToTsv;
+
+// 取得結果を保存するディレクトリ
+pub const LOG_DIR: &str = "./weatherlog";
+
+// 保存済みのtsvファイルを全て読み込み、dtの昇順で返す
+pub fn load_records(dir: &Path) -> Result<Vec<OpenWeaterToTsv>, Box<dyn std::error::Error>> {
+    let mut records = Vec::new();
+    if !dir.exists() {
+        return Ok(records);
+    }
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|v| v.to_str()) != Some("tsv") {
+            continue;
+        }
+        let mut rdr = csv::ReaderBuilder::new()
+            // 区切りにする
+            .delimiter(b'\t')
+            .from_path(&path)?;
+        for record in rdr.deserialize() {
+            // 壊れた行は読み飛ばす
+            match record {
+                Ok(v) => records.push(v),
+                Err(e) => tracing::warn!(path = %path.display(), error = %e, "skip record"),
+            }
+        }
+    }
+    records.sort_by_key(|v: &OpenWeaterToTsv| v.dt);
+
+    Ok(records)
+}
+
+// 取得結果をまとめて1つのtsvファイル（LOG_DIR/name.tsv）に書き込む
+// 書き込み途中のファイルが残らないよう、一時ファイルに書いてから名前を変える
+pub fn write_records(
+    name: &str,
+    records: &[OpenWeaterToTsv],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = Path::new(LOG_DIR).join(format!("{}.tsv", name));
+    let tmp_path = Path::new(LOG_DIR).join(format!("{}.tsv.tmp", name));
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .from_path(&tmp_path)?;
+    for record in records {
+        wtr.serialize(record)?;
+    }
+    wtr.flush()?;
+    std::fs::rename(&tmp_path, &path)?;
+
+    Ok(())
+}
+
+// 地点の指定を地点名と緯度経度に分ける
+// 同名の地点を区別するため「名前@緯度:経度」の形式で座標を指定できる
+pub fn parse_location(location: &str) -> (&str, Option<(f64, f64)>) {
+    let coord = location.rsplit_once('@').and_then(|(name, coord)| {
+        let (lat, lon) = coord.split_once(':')?;
+        Some((name, (lat.parse().ok()?, lon.parse().ok()?)))
+    });
+    match coord {
+        Some((name, coord)) => (name, Some(coord)),
+        None => (location, None),
+    }
+}
+
+// 地点名が一致するか（大文字小文字は区別しない）
+// 座標を指定した地点は、APIが返す地点名が検索した名前と異なることがあるため座標で比べる
+pub fn is_location(record: &OpenWeaterToTsv, location: &str) -> bool {
+    match parse_location(location) {
+        (_, Some((lat, lon))) => (record.lat - lat).abs() < 0.01 && (record.lon - lon).abs() < 0.01,
+        (name, None) => record.name.eq_ignore_ascii_case(name),
+    }
+}
+
+// 期間指定をUNIX時間に変換する
+// UNIX秒、RFC3339、YYYY-MM-DD（ローカル時間の0時）を受け付ける
+pub fn parse_time(value: &str) -> Option<i64> {
+    if let Ok(v) = value.parse::<i64>() {
+        return Some(v);
+    }
+    if let Ok(v) = DateTime::parse_from_rfc3339(value) {
+        return Some(v.timestamp());
+    }
+    if let Ok(v) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        let midnight = v.and_hms_opt(0, 0, 0)?;
+        return Local
+            .from_local_datetime(&midnight)
+            .earliest()
+            .map(|v| v.timestamp());
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_location_with_coordinates() {
+        assert_eq!(parse_location("osaka"), ("osaka", None));
+        assert_eq!(
+            parse_location("Springfield@39.8017:-89.6437"),
+            ("Springfield", Some((39.8017, -89.6437)))
+        );
+        // 座標として読めない場合は全体を地点名とする
+        assert_eq!(parse_location("a@b:c"), ("a@b:c", None));
+        assert_eq!(parse_location("a@1.0"), ("a@1.0", None));
+    }
+
+    #[test]
+    fn is_location_by_name_or_coordinates() {
+        let mut record = OpenWeaterToTsv::new();
+        record.name = String::from("Springfield");
+        record.lat = 39.8;
+        record.lon = -89.64;
+
+        assert!(is_location(&record, "springfield"));
+        assert!(!is_location(&record, "osaka"));
+        assert!(is_location(&record, "Springfield@39.8017:-89.6437"));
+        // 同名の別の地点
+        assert!(!is_location(&record, "Springfield@37.2153:-93.2982"));
+        // APIが別の地点名を返しても座標が同じなら一致する
+        assert!(is_location(&record, "Capital@39.8017:-89.6437"));
+    }
+}
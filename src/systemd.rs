@@ -0,0 +1,140 @@
+use std::env;
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+// systemdに状態を通知する（sd_notify）
+// NOTIFY_SOCKETが設定されていない場合（systemdのType=notify以外から起動した場合）は何もしない
+// 例: "READY=1"、"STATUS=..."、"WATCHDOG=1"、"RELOADING=1"、"STOPPING=1"
+pub fn notify(state: &str) {
+    notify_to(socket_path().as_deref(), state);
+}
+
+// 通知先のソケット（NOTIFY_SOCKET）
+fn socket_path() -> Option<String> {
+    env::var("NOTIFY_SOCKET").ok().filter(|v| !v.is_empty())
+}
+
+// 指定したソケットに通知する（Noneの場合は何もしない）
+fn notify_to(path: Option<&str>, state: &str) {
+    let path = match path {
+        Some(v) => v,
+        None => return,
+    };
+    if let Err(e) = send(path, state) {
+        tracing::warn!(error = %e, "sd_notify failed");
+    }
+}
+
+fn send(path: &str, state: &str) -> std::io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+    // 「@」で始まる場合は抽象名前空間のソケット
+    if let Some(name) = path.strip_prefix('@') {
+        use std::os::linux::net::SocketAddrExt;
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes())?;
+        socket.send_to_addr(state.as_bytes(), &addr)?;
+    } else {
+        socket.send_to(state.as_bytes(), path)?;
+    }
+    Ok(())
+}
+
+// WATCHDOG_USECが設定されている場合、その半分の間隔でWATCHDOG=1を送る
+// 非同期の処理が止まる（ランタイムがブロックされる）と送信も止まり、systemdが再起動する
+pub fn spawn_watchdog() {
+    let usec = env::var("WATCHDOG_USEC").ok();
+    let pid = env::var("WATCHDOG_PID").ok();
+    if let Some(interval) = watchdog_interval(usec.as_deref(), pid.as_deref()) {
+        spawn_watchdog_to(socket_path(), interval);
+    }
+}
+
+// WATCHDOG=1を送る間隔（WATCHDOG_PIDが他のプロセスを指している場合は送らない）
+fn watchdog_interval(usec: Option<&str>, pid: Option<&str>) -> Option<Duration> {
+    let usec = usec
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|v| *v > 0)?;
+    if let Some(pid) = pid {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+    Some(Duration::from_micros(usec / 2))
+}
+
+fn spawn_watchdog_to(path: Option<String>, interval: Duration) {
+    tokio::spawn(async move {
+        loop {
+            notify_to(path.as_deref(), "WATCHDOG=1");
+            tokio::time::sleep(interval).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recv(socket: &UnixDatagram) -> String {
+        let mut buf = [0; 256];
+        let n = socket.recv(&mut buf).unwrap();
+        String::from_utf8_lossy(&buf[..n]).to_string()
+    }
+
+    #[tokio::test]
+    async fn notify_sends_to_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify.sock");
+        let socket = UnixDatagram::bind(&path).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let path = path.to_str().unwrap();
+
+        // 設定されていない場合は何もしない
+        notify_to(None, "READY=1");
+
+        notify_to(Some(path), "READY=1");
+        assert_eq!(recv(&socket), "READY=1");
+        notify_to(Some(path), "STATUS=last reading: Osaka 21.3°C");
+        assert_eq!(recv(&socket), "STATUS=last reading: Osaka 21.3°C");
+
+        spawn_watchdog_to(Some(path.to_string()), Duration::from_millis(100));
+        let socket = tokio::task::spawn_blocking(move || {
+            assert_eq!(recv(&socket), "WATCHDOG=1");
+            assert_eq!(recv(&socket), "WATCHDOG=1");
+            socket
+        })
+        .await
+        .unwrap();
+        drop(socket);
+    }
+
+    #[test]
+    fn watchdog_interval_from_settings() {
+        let pid = std::process::id().to_string();
+        assert_eq!(
+            watchdog_interval(Some("200000"), Some(&pid)),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(
+            watchdog_interval(Some("200000"), None),
+            Some(Duration::from_millis(100))
+        );
+        // 他のプロセス向けの場合や設定が無効な場合は送らない
+        assert_eq!(watchdog_interval(Some("200000"), Some("1")), None);
+        assert_eq!(watchdog_interval(Some("0"), None), None);
+        assert_eq!(watchdog_interval(Some("abc"), None), None);
+        assert_eq!(watchdog_interval(None, None), None);
+    }
+
+    #[test]
+    fn send_to_abstract_socket() {
+        use std::os::linux::net::SocketAddrExt;
+        let name = format!("openweather-client-test-{}", std::process::id());
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+        let socket = UnixDatagram::bind_addr(&addr).unwrap();
+
+        send(&format!("@{}", name), "STOPPING=1").unwrap();
+        assert_eq!(recv(&socket), "STOPPING=1");
+    }
+}
@@ -0,0 +1,179 @@
+use serde_json::{json, Value};
+
+use crate::api::OpenWeaterToTsv;
+use crate::icon;
+
+// 「{キー}」をJSONの値に置き換える簡易テンプレート
+// 「{record.temp}」のようにドット区切りで入れ子の値を参照できる
+// 「{temp:.1}」「{name:>10}」のように「:」の後に書式（揃え、幅、小数点以下の桁数）を指定できる
+// 「{{」「}}」はそれぞれ「{」「}」として出力する
+// 閉じていない「{」は以降をそのまま出力する（設定の読み込み時にcheckでエラーにする）
+pub fn render(template: &str, value: &Value) -> String {
+    let mut out = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                let mut key = String::new();
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        closed = true;
+                        break;
+                    }
+                    key.push(c);
+                }
+                if !closed {
+                    out.push('{');
+                    out.push_str(&key);
+                    break;
+                }
+                match key.split_once(':') {
+                    Some((key, spec)) => out.push_str(&format_spec(value, key, spec)),
+                    None => out.push_str(&lookup(value, &key)),
+                }
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+// テンプレートを確認する。閉じていない「{」がある場合はエラー
+pub fn check(template: &str) -> Result<(), String> {
+    let mut chars = template.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '{' if chars.peek().map(|v| v.1) == Some('{') => {
+                chars.next();
+            }
+            '{' if !chars.by_ref().any(|v| v.1 == '}') => {
+                return Err(format!(
+                    "unclosed \"{{\" at {} (write \"{{{{\" for a literal brace)",
+                    i
+                ));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// キーに対応する値を文字列で返す。存在しない場合は空文字
+pub fn lookup(value: &Value, key: &str) -> String {
+    let mut v = value;
+    for part in key.trim().split('.') {
+        v = match v.get(part) {
+            Some(v) => v,
+            None => return String::new(),
+        };
+    }
+    match v {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        _ => v.to_string(),
+    }
+}
+
+// 書式を指定して値を文字列にする（例: .1 / >6 / <10 / 6.2）
+fn format_spec(value: &Value, key: &str, spec: &str) -> String {
+    let text = lookup(value, key);
+    let (align, spec) = match spec.chars().next() {
+        Some(c @ ('<' | '>')) => (Some(c), &spec[1..]),
+        _ => (None, spec),
+    };
+    let (width, precision) = match spec.split_once('.') {
+        Some((width, precision)) => (width, precision.parse::<usize>().ok()),
+        None => (spec, None),
+    };
+    let width = width.parse::<usize>().unwrap_or(0);
+
+    // 数値の場合のみ小数点以下の桁数を適用する
+    let number = text.parse::<f64>().ok();
+    let text = match (number, precision) {
+        (Some(v), Some(precision)) => format!("{:.1$}", v, precision),
+        _ => text,
+    };
+    // 揃えの指定がない場合は数値を右揃え、文字列を左揃えにする
+    match align.unwrap_or(if number.is_some() { '>' } else { '<' }) {
+        '>' => format!("{:>1$}", text, width),
+        _ => format!("{:<1$}", text, width),
+    }
+}
+
+// 取得結果をテンプレートで参照できる値にする
+// 取得結果の項目に加えて、天気アイコンの絵文字を「{emoji}」で参照できる
+pub fn record_value(record: &OpenWeaterToTsv) -> Value {
+    let mut value = serde_json::to_value(record).unwrap_or_default();
+    value["emoji"] = json!(icon::emoji(&record.icon));
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value() -> Value {
+        json!({
+            "name": "Osaka",
+            "temp": 21.26,
+            "humidity": 60,
+            "record": { "description": "薄い雲" },
+        })
+    }
+
+    #[test]
+    fn render_fields() {
+        let value = value();
+        assert_eq!(
+            render("{name} {temp:.1}°C {record.description}", &value),
+            "Osaka 21.3°C 薄い雲"
+        );
+        // 存在しない項目は空文字
+        assert_eq!(render("[{unknown}] [{record.missing}]", &value), "[] []");
+        assert_eq!(render("[{unknown:>3}]", &value), "[   ]");
+        assert_eq!(render("{{name}} = {name}", &value), "{name} = Osaka");
+        assert_eq!(render("}} {humidity}%", &value), "} 60%");
+    }
+
+    #[test]
+    fn format_specs() {
+        let value = value();
+        assert_eq!(format_spec(&value, "temp", ".0"), "21");
+        assert_eq!(format_spec(&value, "temp", ".2"), "21.26");
+        assert_eq!(format_spec(&value, "temp", "8.2"), "   21.26");
+        assert_eq!(format_spec(&value, "temp", "<8.1"), "21.3    ");
+        assert_eq!(format_spec(&value, "humidity", "4"), "  60");
+        assert_eq!(format_spec(&value, "name", "7"), "Osaka  ");
+        assert_eq!(format_spec(&value, "name", ">7"), "  Osaka");
+        // 文字列には桁数を適用しない
+        assert_eq!(format_spec(&value, "name", ".1"), "Osaka");
+        // 解釈できない書式は無視する
+        assert_eq!(format_spec(&value, "temp", "x"), "21.26");
+    }
+
+    #[test]
+    fn unbalanced_braces() {
+        let value = value();
+        // 閉じていない「{」以降は消さずにそのまま出力する
+        assert_eq!(render("{name} {temp:.1", &value), "Osaka {temp:.1");
+        assert_eq!(render("{", &value), "{");
+        assert_eq!(render("{name}}", &value), "Osaka}");
+
+        assert!(check("{name} {temp:.1}°C {{literal}} }").is_ok());
+        assert_eq!(
+            check("{name} {temp:.1").err().unwrap(),
+            "unclosed \"{\" at 7 (write \"{{\" for a literal brace)"
+        );
+        assert!(check("{").is_err());
+        assert!(check("{{").is_ok());
+    }
+}
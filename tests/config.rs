@@ -0,0 +1,98 @@
+use std::process::Command;
+
+// `config`の表示から項目の値を取り出す
+fn value<'a>(output: &'a str, key: &str) -> &'a str {
+    let prefix = format!("{}:", key);
+    output
+        .lines()
+        .find(|line| line.starts_with(&prefix))
+        .map(|line| line[prefix.len()..].trim())
+        .unwrap_or_else(|| panic!("{} not found in:\n{}", key, output))
+}
+
+// 優先順位は コマンドライン > 環境変数 > 設定ファイル > .env
+#[test]
+fn precedence() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join(".env"),
+        "WEATHER_UNITS=standard\nLOCATION_NAME=dotenv\nWEATHER_LANG=fr\nINTERVAL=5m\nCHART_STYLE=ascii\n",
+    )
+    .unwrap();
+    let config = dir.path().join("config.toml");
+    std::fs::write(
+        &config,
+        "[api]\nunits = \"imperial\"\nlang = \"de\"\n[locations]\nnames = [\"file\"]\n[schedule]\ninterval = \"15m\"\n",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_openweather-client"))
+        .current_dir(dir.path())
+        .env_clear()
+        .env("HOME", dir.path())
+        .env("LOCATION_NAME", "env")
+        .env("WEATHER_LANG", "en")
+        .args(["--config", config.to_str().unwrap()])
+        .args(["--lang", "ja", "--non-interactive", "config"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success(), "{}", stdout);
+
+    // コマンドラインが環境変数より優先される
+    assert_eq!(value(&stdout, "WEATHER_LANG"), "ja");
+    // 環境変数が設定ファイルより優先される
+    assert_eq!(value(&stdout, "LOCATION_NAME"), "env");
+    // 設定ファイルが.envより優先される
+    assert_eq!(value(&stdout, "WEATHER_UNITS"), "imperial");
+    assert_eq!(value(&stdout, "INTERVAL"), "15m");
+    // どこにも指定がなければ.envの値を使う
+    assert_eq!(value(&stdout, "CHART_STYLE"), "ascii");
+}
+
+#[test]
+fn config_error_names_the_key() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("config.toml");
+    std::fs::write(&config, "[profiles.travel.api]\nunits = \"kelvin\"\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_openweather-client"))
+        .current_dir(dir.path())
+        .env_clear()
+        .env("HOME", dir.path())
+        .args(["--config", config.to_str().unwrap()])
+        .args(["--profile", "travel", "--non-interactive", "config"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("profiles.travel.api.units: invalid value \"kelvin\""),
+        "{}",
+        stderr
+    );
+}
+
+// .envや環境変数の値も設定ファイルと同じ確認を行う
+#[test]
+fn dotenv_error_names_the_key() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join(".env"), "ALERT_RULES=\"temp >> 30\"\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_openweather-client"))
+        .current_dir(dir.path())
+        .env_clear()
+        .env("HOME", dir.path())
+        .env("INTERVAL", "0s")
+        .args(["--non-interactive", "config"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("ALERT_RULES: unknown operator: >>")
+            && stderr.contains("INTERVAL: must be greater than 0"),
+        "{}",
+        stderr
+    );
+}